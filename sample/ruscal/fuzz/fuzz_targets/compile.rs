@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ruscal::{compiler::Compiler, file_io::parse_program};
+
+// The compiler is run even on programs that do not type check, which
+// it has to reject without panicking.
+fuzz_target!(|src: &str| {
+  if let Ok(stmts) = parse_program("fuzz.rscl", src) {
+    let _ = Compiler::new().compile(&stmts);
+  }
+});
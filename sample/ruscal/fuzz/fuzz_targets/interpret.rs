@@ -0,0 +1,53 @@
+#![no_main]
+
+use std::{cell::RefCell, error::Error, rc::Rc};
+
+use libfuzzer_sys::fuzz_target;
+use ruscal::{
+  file_io::read_program,
+  tracer::Tracer,
+  value::Value,
+  vm::{Vm, YieldResult},
+};
+
+/// Bytes of strings a program can have on its stack, since it can
+/// double a string with every instruction.
+const MAX_STR_BYTES: usize = 1 << 20;
+
+/// Stops the program after a number of instructions, since it can
+/// loop forever, or when its strings grow too large.
+struct Fuel(usize);
+
+impl Tracer for Fuel {
+  fn on_instruction(
+    &mut self,
+    vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    self.0 = self.0.checked_sub(1).ok_or("Out of fuel")?;
+    let bytes: usize = vm
+      .frames()
+      .iter()
+      .flat_map(|frame| frame.stack())
+      .map(|value| match value {
+        Value::Str(s) | Value::Error(s) => s.len(),
+        _ => 0,
+      })
+      .sum();
+    if MAX_STR_BYTES < bytes {
+      return Err("Out of memory".into());
+    }
+    Ok(())
+  }
+}
+
+fuzz_target!(|data: &[u8]| {
+  let Ok(bytecode) = read_program(&mut &data[..]) else {
+    return;
+  };
+  let mut vm = Vm::new(Rc::new(bytecode), Box::new(()));
+  vm.add_tracer(Rc::new(RefCell::new(Fuel(10_000))));
+  if vm.init_fn("main", &[]).is_err() {
+    return;
+  }
+  while let Ok(YieldResult::Suspend(_)) = vm.interpret() {}
+});
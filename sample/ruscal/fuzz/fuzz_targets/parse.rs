@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ruscal::file_io::parse_program;
+
+fuzz_target!(|src: &str| {
+  let _ = parse_program("fuzz.rscl", src);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ruscal::file_io::read_program;
+
+fuzz_target!(|data: &[u8]| {
+  if let Ok(bytecode) = read_program(&mut &data[..]) {
+    let _ = bytecode.disasm(&mut std::io::sink());
+  }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ruscal::{
+  file_io::parse_program,
+  type_checker::{type_check, TypeCheckContext},
+};
+
+fuzz_target!(|src: &str| {
+  if let Ok(stmts) = parse_program("fuzz.rscl", src) {
+    let _ = type_check(&stmts, &mut TypeCheckContext::new());
+  }
+});
@@ -0,0 +1,22 @@
+#!/bin/sh
+# Seed the corpora of the fuzz targets from the scripts: the sources
+# for the targets taking source text, and the compiled bytecode for
+# the ones taking bytecode. Run from this directory.
+set -e
+cargo build --manifest-path ../Cargo.toml --bin ruscal
+for target in parse type_check compile read_program interpret; do
+  mkdir -p "corpus/$target"
+done
+for script in ../scripts/*.rscl; do
+  name=$(basename "$script" .rscl)
+  for target in parse type_check compile; do
+    cp "$script" "corpus/$target/$name.rscl"
+  done
+  if ../target/debug/ruscal -c "$script" -o "corpus/$name.bin" \
+    > /dev/null 2>&1 && [ -s "corpus/$name.bin" ]; then
+    cp "corpus/$name.bin" "corpus/read_program/$name.bin"
+    mv "corpus/$name.bin" "corpus/interpret/$name.bin"
+  else
+    rm -f "corpus/$name.bin"
+  fi
+done
@@ -0,0 +1,501 @@
+//! Text format of the bytecode, written by `disasm` and `-d` and
+//! read back by `asm`.
+//!
+//! A function looks like this:
+//!
+//! ```text
+//! .fn add(a, b)
+//!     .literal 0 str "print"
+//!     .line 2
+//!     Copy 1
+//! L0:
+//!     Jf L1
+//!     ...
+//!     .handler L0 L1 L2 3
+//!     .local x 2 L0 L1
+//! .end
+//! ```
+//!
+//! `.fn` is `.cofn` for a coroutine and is followed by `test` for a
+//! test function. Literals are numbered in order and are `f64`,
+//! `i64`, `str` or `err` with a value, or `none`, with the strings
+//! quoted as in JSON. A `.line` gives the source line of the
+//! statement starting at the next instruction. Jump targets, the
+//! instructions covered by a `.handler` (start, end, target and
+//! stack depth) and the scopes of the `.local` variables (name,
+//! slot, start and end) refer to labels, or to instruction numbers.
+//! Comments start with `;`. The functions are written in the order
+//! of the bytecode file, so that assembling the text of a file gives
+//! the same bytes.
+
+use std::{
+  collections::{BTreeSet, HashMap},
+  error::Error,
+  io::{Read, Write},
+};
+
+use crate::{
+  bytecode::{
+    read_fn_list, write_fn_list, DebugInfo, FnByteCode,
+    Handler, LocalVar,
+  },
+  instructions::{Instruction, OpCode},
+  value::Value,
+};
+
+/// Disassemble a bytecode file.
+pub fn disasm_file(
+  reader: &mut impl Read,
+  writer: &mut impl Write,
+) -> std::io::Result<()> {
+  for (i, func) in read_fn_list(reader)?.iter().enumerate() {
+    if i > 0 {
+      writeln!(writer)?;
+    }
+    disasm_fn(func, writer)?;
+  }
+  Ok(())
+}
+
+/// Write assembled functions as a bytecode file.
+pub fn write_funcs(
+  funcs: &[FnByteCode],
+  writer: &mut impl Write,
+) -> std::io::Result<()> {
+  write_fn_list(funcs.iter(), writer)
+}
+
+fn has_operand(op: OpCode) -> bool {
+  use OpCode::*;
+  matches!(
+    op,
+    LoadLiteral
+      | Store
+      | Copy
+      | Dup
+      | Call
+      | Jmp
+      | Jf
+      | Jn
+      | Pop
+      | Ret
+  )
+}
+
+fn is_jump(op: OpCode) -> bool {
+  matches!(op, OpCode::Jmp | OpCode::Jf | OpCode::Jn)
+}
+
+fn literal_text(value: &Value) -> std::io::Result<String> {
+  Ok(match value {
+    Value::F64(value) => format!("f64 {value:?}"),
+    Value::I64(value) => format!("i64 {value}"),
+    Value::Str(value) => format!("str {}", quote(value)),
+    Value::Error(value) => format!("err {}", quote(value)),
+    Value::None => "none".to_string(),
+    Value::Coro(_) => {
+      return Err(std::io::Error::other(
+        "Coroutine can't be disassembled",
+      ))
+    }
+  })
+}
+
+fn quote(s: &str) -> String {
+  serde_json::Value::from(s).to_string()
+}
+
+pub(crate) fn disasm_fn(
+  func: &FnByteCode,
+  writer: &mut impl Write,
+) -> std::io::Result<()> {
+  let kw = if func.cofn { ".cofn" } else { ".fn" };
+  let test = if func.test { " test" } else { "" };
+  writeln!(
+    writer,
+    "{kw} {}({}){test}",
+    func.name,
+    func.args.join(", ")
+  )?;
+  for (i, literal) in func.literals.iter().enumerate() {
+    writeln!(
+      writer,
+      "    .literal {i} {}",
+      literal_text(literal)?
+    )?;
+  }
+
+  // Name the instructions referred to, except ones out of the
+  // function which are left as numbers.
+  let len = func.instructions.len();
+  let jumps = func
+    .instructions
+    .iter()
+    .filter(|inst| is_jump(inst.op))
+    .map(|inst| inst.arg0 as usize);
+  let handlers = func
+    .handlers
+    .iter()
+    .flat_map(|h| [h.start, h.end, h.target]);
+  let locals = func
+    .debug
+    .locals
+    .iter()
+    .flat_map(|var| [var.start, var.end]);
+  let labels: HashMap<usize, String> = jumps
+    .chain(handlers)
+    .chain(locals)
+    .filter(|ip| *ip <= len)
+    .collect::<BTreeSet<_>>()
+    .into_iter()
+    .enumerate()
+    .map(|(i, ip)| (ip, format!("L{i}")))
+    .collect();
+  let target = |ip: usize| {
+    labels.get(&ip).cloned().unwrap_or_else(|| ip.to_string())
+  };
+
+  let mut lines = func.debug.lines.iter().peekable();
+  for ip in 0..=len {
+    if let Some(label) = labels.get(&ip) {
+      writeln!(writer, "{label}:")?;
+    }
+    while let Some((_, line)) = lines.next_if(|(i, _)| *i == ip)
+    {
+      writeln!(writer, "    .line {line}")?;
+    }
+    let Some(inst) = func.instructions.get(ip) else {
+      break;
+    };
+    write!(writer, "    {:?}", inst.op)?;
+    if is_jump(inst.op) {
+      write!(writer, " {}", target(inst.arg0 as usize))?;
+    } else if has_operand(inst.op) || inst.arg0 != 0 {
+      write!(writer, " {}", inst.arg0)?;
+    }
+    if let OpCode::LoadLiteral = inst.op {
+      if let Some(literal) =
+        func.literals.get(inst.arg0 as usize)
+      {
+        write!(writer, " ; {}", literal_text(literal)?)?;
+      }
+    }
+    writeln!(writer)?;
+  }
+  if let Some((ip, _)) = lines.next() {
+    return Err(std::io::Error::other(format!(
+      "Line table of {} refers to instruction {ip} out of the \
+      function",
+      func.name
+    )));
+  }
+
+  for h in &func.handlers {
+    writeln!(
+      writer,
+      "    .handler {} {} {} {}",
+      target(h.start),
+      target(h.end),
+      target(h.target),
+      h.stack_depth
+    )?;
+  }
+  for var in &func.debug.locals {
+    writeln!(
+      writer,
+      "    .local {} {} {} {}",
+      var.name,
+      var.slot,
+      target(var.start),
+      target(var.end)
+    )?;
+  }
+  writeln!(writer, ".end")
+}
+
+/// An instruction number, or a label to be resolved at the end of
+/// the function.
+enum Target<'a> {
+  Ip(usize),
+  Label(&'a str, usize),
+}
+
+/// A function being assembled.
+struct FnAsm<'a> {
+  func: FnByteCode,
+  labels: HashMap<&'a str, usize>,
+  jumps: Vec<(usize, Target<'a>)>,
+  handlers: Vec<([Target<'a>; 3], usize)>,
+  locals: Vec<(String, usize, Target<'a>, Target<'a>)>,
+}
+
+impl<'a> FnAsm<'a> {
+  fn resolve(&self, target: &Target) -> Result<usize, String> {
+    match target {
+      Target::Ip(ip) => Ok(*ip),
+      Target::Label(label, line) => {
+        self.labels.get(label).copied().ok_or_else(|| {
+          format!("line {line}: Undefined label {label}")
+        })
+      }
+    }
+  }
+
+  fn finish(mut self) -> Result<FnByteCode, String> {
+    for (ip, target) in &self.jumps {
+      let dest = self.resolve(target)?;
+      let Ok(dest) = u8::try_from(dest) else {
+        return Err(format!(
+          "Jump at {ip} in {} is too far to {dest}",
+          self.func.name
+        ));
+      };
+      self.func.instructions[*ip].arg0 = dest;
+    }
+    for ([start, end, target], stack_depth) in &self.handlers {
+      self.func.handlers.push(Handler {
+        start: self.resolve(start)?,
+        end: self.resolve(end)?,
+        target: self.resolve(target)?,
+        stack_depth: *stack_depth,
+      });
+    }
+    for (name, slot, start, end) in &self.locals {
+      self.func.debug.locals.push(LocalVar {
+        name: name.clone(),
+        slot: *slot,
+        start: self.resolve(start)?,
+        end: self.resolve(end)?,
+      });
+    }
+    Ok(self.func)
+  }
+}
+
+/// Remove the comment from a line, leaving `;` in strings.
+fn strip_comment(line: &str) -> &str {
+  let mut in_str = false;
+  let mut escaped = false;
+  for (i, c) in line.char_indices() {
+    match c {
+      _ if escaped => escaped = false,
+      '\\' if in_str => escaped = true,
+      '"' => in_str = !in_str,
+      ';' if !in_str => return &line[..i],
+      _ => (),
+    }
+  }
+  line
+}
+
+fn number<T: std::str::FromStr>(
+  token: Option<&str>,
+  what: &str,
+) -> Result<T, String> {
+  let token =
+    token.ok_or_else(|| format!("Expected {what}"))?;
+  token
+    .parse()
+    .map_err(|_| format!("Expected {what}, got {token:?}"))
+}
+
+fn target<'a>(
+  token: Option<&'a str>,
+  line: usize,
+) -> Result<Target<'a>, String> {
+  let token = token.ok_or("Expected a label")?;
+  Ok(match token.parse() {
+    Ok(ip) => Target::Ip(ip),
+    Err(_) => Target::Label(token, line),
+  })
+}
+
+fn parse_literal(
+  index: usize,
+  rest: &str,
+) -> Result<Value, String> {
+  let rest = rest.trim();
+  let (number, rest) =
+    rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+  if number.parse() != Ok(index) {
+    return Err(format!(
+      "Expected literal {index}, got {number:?}"
+    ));
+  }
+  let rest = rest.trim();
+  let (kind, value) =
+    rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+  let value = value.trim();
+  let string = || {
+    serde_json::from_str::<String>(value).map_err(|_| {
+      format!("Expected a quoted string, got {value}")
+    })
+  };
+  Ok(match kind {
+    "f64" => Value::F64(self::number(Some(value), "a number")?),
+    "i64" => {
+      Value::I64(self::number(Some(value), "an integer")?)
+    }
+    "str" => Value::Str(string()?),
+    "err" => Value::Error(string()?),
+    "none" if value.is_empty() => Value::None,
+    _ => {
+      return Err(format!(
+        "Expected f64, i64, str, err or none, got {rest:?}"
+      ))
+    }
+  })
+}
+
+fn parse_header(
+  kw: &str,
+  rest: &str,
+) -> Result<FnByteCode, String> {
+  let (name, rest) = rest
+    .split_once('(')
+    .ok_or("Expected '(' after the function name")?;
+  let (args, flags) = rest
+    .split_once(')')
+    .ok_or("Expected ')' after the arguments")?;
+  let name = name.trim();
+  if name.is_empty() {
+    return Err("Expected a function name".to_string());
+  }
+  let args = args
+    .split(',')
+    .map(str::trim)
+    .filter(|arg| !arg.is_empty())
+    .map(str::to_string)
+    .collect();
+  let test = match flags.trim() {
+    "" => false,
+    "test" => true,
+    flags => return Err(format!("Unknown flag {flags:?}")),
+  };
+  let mut func = FnByteCode::new(
+    name.to_string(),
+    args,
+    vec![],
+    vec![],
+    vec![],
+    kw == ".cofn",
+    DebugInfo::default(),
+  );
+  func.test = test;
+  Ok(func)
+}
+
+/// Assemble the text into functions in the order they are written.
+pub fn assemble(
+  src: &str,
+) -> Result<Vec<FnByteCode>, Box<dyn Error>> {
+  let mut funcs = vec![];
+  let mut current: Option<FnAsm> = None;
+  for (i, line) in src.lines().enumerate() {
+    let line_no = i + 1;
+    let at = |e: String| format!("line {line_no}: {e}");
+    let text = strip_comment(line).trim();
+    if text.is_empty() {
+      continue;
+    }
+    let (word, rest) = text
+      .split_once(char::is_whitespace)
+      .unwrap_or((text, ""));
+    let Some(f) = &mut current else {
+      if word != ".fn" && word != ".cofn" {
+        return Err(
+          at(format!("Expected .fn or .cofn, got {word}"))
+            .into(),
+        );
+      }
+      current = Some(FnAsm {
+        func: parse_header(word, rest).map_err(at)?,
+        labels: HashMap::new(),
+        jumps: vec![],
+        handlers: vec![],
+        locals: vec![],
+      });
+      continue;
+    };
+    let mut tokens = rest.split_whitespace();
+    let ip = f.func.instructions.len();
+    match word {
+      ".end" => {
+        let f = current.take().unwrap();
+        funcs.push(f.finish()?);
+        continue;
+      }
+      ".literal" => {
+        let index = f.func.literals.len();
+        let literal = parse_literal(index, rest).map_err(at)?;
+        f.func.literals.push(literal);
+        continue;
+      }
+      ".line" => {
+        let line =
+          number(tokens.next(), "a line number").map_err(at)?;
+        f.func.debug.lines.push((ip, line));
+      }
+      ".handler" => {
+        let targets = [
+          target(tokens.next(), line_no).map_err(at)?,
+          target(tokens.next(), line_no).map_err(at)?,
+          target(tokens.next(), line_no).map_err(at)?,
+        ];
+        let depth =
+          number(tokens.next(), "a stack depth").map_err(at)?;
+        f.handlers.push((targets, depth));
+      }
+      ".local" => {
+        let name = tokens
+          .next()
+          .ok_or_else(|| at("Expected a name".to_string()))?;
+        let slot =
+          number(tokens.next(), "a slot").map_err(at)?;
+        let start =
+          target(tokens.next(), line_no).map_err(at)?;
+        let end = target(tokens.next(), line_no).map_err(at)?;
+        f.locals.push((name.to_string(), slot, start, end));
+      }
+      label if label.ends_with(':') && rest.is_empty() => {
+        let label = &label[..label.len() - 1];
+        if f.labels.insert(label, ip).is_some() {
+          return Err(
+            at(format!("Label {label} is defined twice"))
+              .into(),
+          );
+        }
+        continue;
+      }
+      op => {
+        let op: OpCode = op.parse().map_err(|_| {
+          at(format!("Unknown instruction {op}"))
+        })?;
+        let arg0 = match tokens.next() {
+          None => 0,
+          Some(token) if is_jump(op) => {
+            f.jumps.push((
+              ip,
+              target(Some(token), line_no).map_err(at)?,
+            ));
+            0
+          }
+          token => number(token, "an operand (0 to 255)")
+            .map_err(at)?,
+        };
+        f.func.instructions.push(Instruction { op, arg0 });
+      }
+    }
+    if tokens.next().is_some() {
+      return Err(
+        at(format!("Unexpected text after {word}")).into(),
+      );
+    }
+  }
+  if let Some(f) = current {
+    return Err(
+      format!("Missing .end of {}", f.func.name).into(),
+    );
+  }
+  Ok(funcs)
+}
@@ -1,15 +1,19 @@
-use crate::parser::{calc_offset, GetSpan};
 use nom_locate::LocatedSpan;
 
 pub type Span<'a> = LocatedSpan<&'a str>;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum TypeDecl {
   Any,
   F64,
   I64,
   Str,
-  Coro,
+  /// Coroutine yielding values of the inner type, written as
+  /// `cofn<T>`. A bare `cofn` yields `Any`.
+  Coro(Box<TypeDecl>),
+  Error,
+  /// Optional value of the inner type, written as `T?`
+  Opt(Box<TypeDecl>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -17,6 +21,11 @@ pub enum ExprEnum<'src> {
   Ident(Span<'src>),
   NumLiteral(f64),
   StrLiteral(String),
+  /// String literal with embedded expressions, like `"x = {x:.3}"`
+  Interpolated(Vec<StrPart<'src>>),
+  NoneLiteral,
+  /// `some(ex)` wraps a value into an optional
+  SomeValue(Box<Expression<'src>>),
   FnInvoke(Span<'src>, Vec<Expression<'src>>),
   Add(Box<Expression<'src>>, Box<Expression<'src>>),
   Sub(Box<Expression<'src>>, Box<Expression<'src>>),
@@ -24,12 +33,30 @@ pub enum ExprEnum<'src> {
   Div(Box<Expression<'src>>, Box<Expression<'src>>),
   Gt(Box<Expression<'src>>, Box<Expression<'src>>),
   Lt(Box<Expression<'src>>, Box<Expression<'src>>),
+  /// `lhs ?? rhs` evaluates to rhs if lhs is none
+  Coalesce(Box<Expression<'src>>, Box<Expression<'src>>),
   If(
     Box<Expression<'src>>,
     Box<Statements<'src>>,
     Option<Box<Statements<'src>>>,
   ),
+  /// `if let name = ex { ... } else { ... }`
+  IfLet(
+    Span<'src>,
+    Box<Expression<'src>>,
+    Box<Statements<'src>>,
+    Option<Box<Statements<'src>>>,
+  ),
   Await(Box<Expression<'src>>),
+  /// `try { body } catch name { handler }`
+  Try(Box<Statements<'src>>, Span<'src>, Box<Statements<'src>>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum StrPart<'src> {
+  Text(String),
+  /// An embedded expression with an optional format spec after `:`
+  Expr(Expression<'src>, Option<Span<'src>>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -50,7 +77,9 @@ pub enum Statement<'src> {
   VarDef {
     span: Span<'src>,
     name: Span<'src>,
-    td: TypeDecl,
+    /// Type annotation, or `None` if the type should be inferred
+    /// from the initializer.
+    td: Option<TypeDecl>,
     ex: Expression<'src>,
   },
   VarAssign {
@@ -65,17 +94,22 @@ pub enum Statement<'src> {
     end: Expression<'src>,
     stmts: Statements<'src>,
   },
-  Break,
-  Continue,
+  Break(Span<'src>),
+  Continue(Span<'src>),
   FnDef {
+    span: Span<'src>,
     name: Span<'src>,
     args: Vec<(Span<'src>, TypeDecl)>,
     ret_type: TypeDecl,
     stmts: Statements<'src>,
     cofn: bool,
+    /// A `test fn`, run by `-T`, which has no arguments and returns
+    /// `Any`
+    test: bool,
   },
   Return(Expression<'src>),
   Yield(Expression<'src>),
+  Throw(Expression<'src>),
 }
 
 impl<'src> Statement<'src> {
@@ -86,13 +120,11 @@ impl<'src> Statement<'src> {
       VarDef { span, .. } => *span,
       VarAssign { span, .. } => *span,
       For { span, .. } => *span,
-      FnDef { name, stmts, .. } => {
-        calc_offset(*name, stmts.span())
-      }
+      FnDef { span, .. } => *span,
       Return(ex) => ex.span,
-      Break => return None,
-      Continue => return None,
+      Break(span) | Continue(span) => *span,
       Yield(ex) => ex.span,
+      Throw(ex) => ex.span,
     })
   }
 }
@@ -0,0 +1,376 @@
+//! Machine-readable export of the AST, by `--ast-format`.
+//!
+//! Every statement and expression becomes a node with its `kind`,
+//! which is the name of the variant in `ast`, its `span` and the
+//! fields of the variant. Spans are objects with the 1-based `line`
+//! and `column`, the byte `offset` and the byte length `len` of the
+//! source text, or `null` for `break` and `continue`, which have no
+//! span. Type annotations are written as in the source, like
+//! `"cofn<i64>"` or `"str?"`. The same tree can be written as JSON
+//! or as S-expressions, where the fields are `:keyword value` pairs.
+//! The tests in `tests/ast_export.rs` show the nodes by example.
+
+use std::{io::Write, str::FromStr};
+
+use serde_json::{json, Map};
+
+use crate::{
+  ast::{
+    ExprEnum, Expression, Span, Statement, Statements, StrPart,
+  },
+  formatter::type_name,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AstFormat {
+  /// The `Debug` output of the statements
+  Debug,
+  Json,
+  Sexpr,
+}
+
+impl FromStr for AstFormat {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Ok(match s {
+      "debug" => Self::Debug,
+      "json" => Self::Json,
+      "sexpr" => Self::Sexpr,
+      _ => {
+        return Err(format!(
+          "Unknown AST format {s:?}; expected json, sexpr or debug"
+        ))
+      }
+    })
+  }
+}
+
+/// Write the statements in the format, followed by a newline.
+pub fn write_ast(
+  stmts: &[Statement],
+  format: AstFormat,
+  writer: &mut impl Write,
+) -> std::io::Result<()> {
+  match format {
+    AstFormat::Debug => writeln!(writer, "AST: {stmts:#?}"),
+    AstFormat::Json => {
+      serde_json::to_writer_pretty(
+        &mut *writer,
+        &to_json(stmts),
+      )?;
+      writeln!(writer)
+    }
+    AstFormat::Sexpr => writeln!(writer, "{}", to_sexpr(stmts)),
+  }
+}
+
+/// The statements as an array of nodes.
+pub fn to_json(stmts: &[Statement]) -> serde_json::Value {
+  stmts.iter().map(|stmt| stmt_node(stmt).to_json()).collect()
+}
+
+/// The statements as a list of nodes, one statement per line.
+pub fn to_sexpr(stmts: &[Statement]) -> String {
+  let mut out = String::new();
+  stmts_field(stmts).write_sexpr(&mut out);
+  out
+}
+
+enum Field {
+  Node(Node),
+  List(Vec<Node>),
+  Str(String),
+  Num(f64),
+  Bool(bool),
+  Null,
+}
+
+impl Field {
+  fn to_json(&self) -> serde_json::Value {
+    match self {
+      Self::Node(node) => node.to_json(),
+      Self::List(nodes) => {
+        nodes.iter().map(Node::to_json).collect()
+      }
+      Self::Str(s) => json!(s),
+      Self::Num(n) => json!(n),
+      Self::Bool(b) => json!(b),
+      Self::Null => serde_json::Value::Null,
+    }
+  }
+
+  /// Whether the field is written on a line of its own.
+  fn is_nested(&self) -> bool {
+    match self {
+      Self::Node(_) => true,
+      Self::List(nodes) => !nodes.is_empty(),
+      _ => false,
+    }
+  }
+
+  /// Write the field, with the elements of lists one per line
+  /// aligned to the first one.
+  fn write_sexpr(&self, out: &mut String) {
+    match self {
+      Self::Node(node) => node.write_sexpr(out),
+      Self::List(nodes) => {
+        let indent = column(out) + 1;
+        *out += "(";
+        for (i, node) in nodes.iter().enumerate() {
+          if i > 0 {
+            newline(out, indent);
+          }
+          node.write_sexpr(out);
+        }
+        *out += ")";
+      }
+      Self::Str(s) => *out += &json!(s).to_string(),
+      Self::Num(n) => *out += &format!("{n:?}"),
+      Self::Bool(b) => *out += &b.to_string(),
+      Self::Null => *out += "nil",
+    }
+  }
+}
+
+/// Where a node is in the source.
+#[derive(Clone, Copy)]
+struct Location {
+  line: u32,
+  column: usize,
+  offset: usize,
+  len: usize,
+}
+
+impl From<Span<'_>> for Location {
+  fn from(span: Span) -> Self {
+    Self {
+      line: span.location_line(),
+      column: span.get_utf8_column(),
+      offset: span.location_offset(),
+      len: span.len(),
+    }
+  }
+}
+
+struct Node {
+  kind: &'static str,
+  loc: Option<Location>,
+  fields: Vec<(&'static str, Field)>,
+}
+
+impl Node {
+  fn new(kind: &'static str, span: Option<Span>) -> Self {
+    Self {
+      kind,
+      loc: span.map(Location::from),
+      fields: vec![],
+    }
+  }
+
+  fn field(mut self, name: &'static str, value: Field) -> Self {
+    self.fields.push((name, value));
+    self
+  }
+
+  fn to_json(&self) -> serde_json::Value {
+    let mut obj = Map::new();
+    obj.insert("kind".to_string(), json!(self.kind));
+    let span = self.loc.map(|loc| {
+      json!({
+        "line": loc.line,
+        "column": loc.column,
+        "offset": loc.offset,
+        "len": loc.len,
+      })
+    });
+    obj.insert("span".to_string(), json!(span));
+    for (name, value) in &self.fields {
+      obj.insert(name.to_string(), value.to_json());
+    }
+    obj.into()
+  }
+
+  /// Write the node with the scalar fields on its first line, until
+  /// a nested one, and the other fields each on its own line.
+  fn write_sexpr(&self, out: &mut String) {
+    let indent = column(out) + 1;
+    let mut nested = false;
+    *out += "(";
+    *out += self.kind;
+    if let Some(loc) = self.loc {
+      *out += &format!(
+        " :span ({} {} {} {})",
+        loc.line, loc.column, loc.offset, loc.len
+      );
+    }
+    for (name, value) in &self.fields {
+      nested |= value.is_nested();
+      if nested {
+        newline(out, indent);
+      } else {
+        *out += " ";
+      }
+      *out += &format!(":{name} ");
+      value.write_sexpr(out);
+    }
+    *out += ")";
+  }
+}
+
+/// The column where the next character is written, from 0.
+fn column(out: &str) -> usize {
+  out.len() - out.rfind('\n').map_or(0, |i| i + 1)
+}
+
+fn newline(out: &mut String, indent: usize) {
+  *out += "\n";
+  *out += &" ".repeat(indent);
+}
+
+fn name(span: &Span) -> Field {
+  Field::Str(span.to_string())
+}
+
+fn stmts_field(stmts: &[Statement]) -> Field {
+  Field::List(stmts.iter().map(stmt_node).collect())
+}
+
+fn opt_stmts_field(stmts: &Option<Box<Statements>>) -> Field {
+  stmts
+    .as_ref()
+    .map_or(Field::Null, |stmts| stmts_field(stmts))
+}
+
+fn expr_field(ex: &Expression) -> Field {
+  Field::Node(expr_node(ex))
+}
+
+fn stmt_node(stmt: &Statement) -> Node {
+  use Statement::*;
+  let node = |kind| Node::new(kind, stmt.span());
+  match stmt {
+    Expression(ex) => {
+      node("Expression").field("expr", expr_field(ex))
+    }
+    VarDef {
+      name: n, td, ex, ..
+    } => node("VarDef")
+      .field("name", name(n))
+      .field(
+        "type",
+        td.as_ref()
+          .map_or(Field::Null, |td| Field::Str(type_name(td))),
+      )
+      .field("value", expr_field(ex)),
+    VarAssign { name: n, ex, .. } => node("VarAssign")
+      .field("name", name(n))
+      .field("value", expr_field(ex)),
+    For {
+      loop_var,
+      start,
+      end,
+      stmts,
+      ..
+    } => node("For")
+      .field("var", name(loop_var))
+      .field("start", expr_field(start))
+      .field("end", expr_field(end))
+      .field("body", stmts_field(stmts)),
+    Break(_) => node("Break"),
+    Continue(_) => node("Continue"),
+    FnDef {
+      name: n,
+      args,
+      ret_type,
+      stmts,
+      cofn,
+      test,
+      ..
+    } => node("FnDef")
+      .field("name", name(n))
+      .field(
+        "args",
+        Field::List(
+          args
+            .iter()
+            .map(|(arg, td)| {
+              Node::new("Arg", Some(*arg))
+                .field("name", name(arg))
+                .field("type", Field::Str(type_name(td)))
+            })
+            .collect(),
+        ),
+      )
+      .field("ret_type", Field::Str(type_name(ret_type)))
+      .field("cofn", Field::Bool(*cofn))
+      .field("test", Field::Bool(*test))
+      .field("body", stmts_field(stmts)),
+    Return(ex) => node("Return").field("value", expr_field(ex)),
+    Yield(ex) => node("Yield").field("value", expr_field(ex)),
+    Throw(ex) => node("Throw").field("value", expr_field(ex)),
+  }
+}
+
+fn expr_node(ex: &Expression) -> Node {
+  use ExprEnum::*;
+  let node = |kind| Node::new(kind, Some(ex.span));
+  let binary = |kind, lhs, rhs| {
+    node(kind)
+      .field("lhs", expr_field(lhs))
+      .field("rhs", expr_field(rhs))
+  };
+  match &ex.expr {
+    Ident(n) => node("Ident").field("name", name(n)),
+    NumLiteral(n) => {
+      node("NumLiteral").field("value", Field::Num(*n))
+    }
+    StrLiteral(s) => {
+      node("StrLiteral").field("value", Field::Str(s.clone()))
+    }
+    Interpolated(parts) => node("Interpolated").field(
+      "parts",
+      Field::List(parts.iter().map(str_part_node).collect()),
+    ),
+    NoneLiteral => node("NoneLiteral"),
+    SomeValue(ex) => {
+      node("SomeValue").field("value", expr_field(ex))
+    }
+    FnInvoke(n, args) => {
+      node("FnInvoke").field("name", name(n)).field(
+        "args",
+        Field::List(args.iter().map(expr_node).collect()),
+      )
+    }
+    Add(lhs, rhs) => binary("Add", lhs, rhs),
+    Sub(lhs, rhs) => binary("Sub", lhs, rhs),
+    Mul(lhs, rhs) => binary("Mul", lhs, rhs),
+    Div(lhs, rhs) => binary("Div", lhs, rhs),
+    Gt(lhs, rhs) => binary("Gt", lhs, rhs),
+    Lt(lhs, rhs) => binary("Lt", lhs, rhs),
+    Coalesce(lhs, rhs) => binary("Coalesce", lhs, rhs),
+    If(cond, t_case, f_case) => node("If")
+      .field("cond", expr_field(cond))
+      .field("then", stmts_field(t_case))
+      .field("else", opt_stmts_field(f_case)),
+    IfLet(n, ex, t_case, f_case) => node("IfLet")
+      .field("name", name(n))
+      .field("value", expr_field(ex))
+      .field("then", stmts_field(t_case))
+      .field("else", opt_stmts_field(f_case)),
+    Await(ex) => node("Await").field("value", expr_field(ex)),
+    Try(body, n, handler) => node("Try")
+      .field("body", stmts_field(body))
+      .field("name", name(n))
+      .field("handler", stmts_field(handler)),
+  }
+}
+
+fn str_part_node(part: &StrPart) -> Node {
+  match part {
+    StrPart::Text(s) => Node::new("Text", None)
+      .field("value", Field::Str(s.clone())),
+    StrPart::Expr(ex, spec) => Node::new("Expr", Some(ex.span))
+      .field("expr", expr_field(ex))
+      .field("spec", spec.as_ref().map_or(Field::Null, name)),
+  }
+}
@@ -0,0 +1,9 @@
+//! Language server for ruscal scripts, speaking the Language Server
+//! Protocol over stdin and stdout.
+
+fn main() -> std::io::Result<()> {
+  ruscal::lsp::run(
+    std::io::stdin().lock(),
+    std::io::stdout().lock(),
+  )
+}
@@ -6,36 +6,226 @@ use std::{
 };
 
 use crate::{
+  asm::disasm_fn,
   ast::{Span, TypeDecl},
-  instructions::{Instruction, OpCode},
+  format::format_values,
+  instructions::Instruction,
   value::{
-    deserialize_size, deserialize_str, serialize_size,
-    serialize_str, Value,
+    deserialize_size, deserialize_str, deserialize_vec,
+    serialize_size, serialize_str, Value,
   },
 };
 
+/// An entry in the exception handler table of a function.
+///
+/// An error thrown by an instruction in `start..end` truncates the
+/// stack to `stack_depth`, pushes the error value and resumes
+/// execution at `target`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Handler {
+  pub(crate) start: usize,
+  pub(crate) end: usize,
+  pub(crate) target: usize,
+  pub(crate) stack_depth: usize,
+}
+
+impl Handler {
+  fn serialize(
+    &self,
+    writer: &mut impl Write,
+  ) -> std::io::Result<()> {
+    serialize_size(self.start, writer)?;
+    serialize_size(self.end, writer)?;
+    serialize_size(self.target, writer)?;
+    serialize_size(self.stack_depth, writer)?;
+    Ok(())
+  }
+
+  fn deserialize(
+    reader: &mut impl Read,
+  ) -> std::io::Result<Self> {
+    Ok(Self {
+      start: deserialize_size(reader)?,
+      end: deserialize_size(reader)?,
+      target: deserialize_size(reader)?,
+      stack_depth: deserialize_size(reader)?,
+    })
+  }
+}
+
+/// A local variable in the debug information of a function.
+#[derive(Debug, Clone)]
+pub(crate) struct LocalVar {
+  pub(crate) name: String,
+  /// Index in the stack frame
+  pub(crate) slot: usize,
+  /// Range of the instructions where the variable is in scope
+  pub(crate) start: usize,
+  pub(crate) end: usize,
+}
+
+impl LocalVar {
+  fn serialize(
+    &self,
+    writer: &mut impl Write,
+  ) -> std::io::Result<()> {
+    serialize_str(&self.name, writer)?;
+    serialize_size(self.slot, writer)?;
+    serialize_size(self.start, writer)?;
+    serialize_size(self.end, writer)?;
+    Ok(())
+  }
+
+  fn deserialize(
+    reader: &mut impl Read,
+  ) -> std::io::Result<Self> {
+    Ok(Self {
+      name: deserialize_str(reader)?,
+      slot: deserialize_size(reader)?,
+      start: deserialize_size(reader)?,
+      end: deserialize_size(reader)?,
+    })
+  }
+}
+
+/// Debug information mapping the instructions of a function back to
+/// the source, for the debugger.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DebugInfo {
+  /// Pairs of the first instruction of a statement and the line of
+  /// the statement, ordered by the instruction.
+  pub(crate) lines: Vec<(usize, usize)>,
+  pub(crate) locals: Vec<LocalVar>,
+}
+
+impl DebugInfo {
+  /// Record that a statement on `line` starts at `ip`.
+  pub(crate) fn add_line(&mut self, ip: usize, line: usize) {
+    if let Some(last) = self.lines.last_mut() {
+      if last.0 == ip {
+        // The last statement had no instructions.
+        *last = (ip, line);
+      }
+      if last.1 == line {
+        return;
+      }
+    }
+    self.lines.push((ip, line));
+  }
+
+  /// The line of the statement starting at `ip`, if any.
+  pub(crate) fn stmt_line(&self, ip: usize) -> Option<usize> {
+    let i =
+      self.lines.binary_search_by_key(&ip, |l| l.0).ok()?;
+    Some(self.lines[i].1)
+  }
+
+  /// The line of the statement containing `ip`.
+  pub(crate) fn line(&self, ip: usize) -> Option<usize> {
+    let i = self.lines.partition_point(|l| l.0 <= ip);
+    Some(self.lines.get(i.checked_sub(1)?)?.1)
+  }
+
+  /// Local variables in scope at `ip`, where the later one of the
+  /// variables with the same name shadows the others.
+  pub(crate) fn locals_at(
+    &self,
+    ip: usize,
+  ) -> impl Iterator<Item = &LocalVar> {
+    let mut live: Vec<&LocalVar> = self
+      .locals
+      .iter()
+      .filter(|var| var.start <= ip && ip < var.end)
+      .collect();
+    live.sort_by_key(|var| var.slot);
+    let shadowed: Vec<bool> = live
+      .iter()
+      .enumerate()
+      .map(|(i, var)| {
+        live[i + 1..].iter().any(|other| other.name == var.name)
+      })
+      .collect();
+    live
+      .into_iter()
+      .zip(shadowed)
+      .filter_map(|(var, shadowed)| (!shadowed).then_some(var))
+  }
+
+  fn serialize(
+    &self,
+    writer: &mut impl Write,
+  ) -> std::io::Result<()> {
+    serialize_size(self.lines.len(), writer)?;
+    for (ip, line) in &self.lines {
+      serialize_size(*ip, writer)?;
+      serialize_size(*line, writer)?;
+    }
+    serialize_size(self.locals.len(), writer)?;
+    for var in &self.locals {
+      var.serialize(writer)?;
+    }
+    Ok(())
+  }
+
+  fn deserialize(
+    reader: &mut impl Read,
+  ) -> std::io::Result<Self> {
+    let lines = deserialize_vec(reader, |reader| {
+      Ok((deserialize_size(reader)?, deserialize_size(reader)?))
+    })?;
+    let locals =
+      deserialize_vec(reader, LocalVar::deserialize)?;
+    Ok(Self { lines, locals })
+  }
+}
+
 pub struct FnByteCode {
-  args: Vec<String>,
+  pub(crate) name: String,
+  pub(crate) args: Vec<String>,
   pub(crate) literals: Vec<Value>,
   pub(crate) instructions: Vec<Instruction>,
+  /// Exception handlers, innermost first.
+  pub(crate) handlers: Vec<Handler>,
   pub(crate) cofn: bool,
+  /// A `test fn`, run by `-T`
+  pub(crate) test: bool,
+  pub(crate) debug: DebugInfo,
 }
 
 impl FnByteCode {
   pub(crate) fn new(
+    name: String,
     args: Vec<String>,
     literals: Vec<Value>,
     instructions: Vec<Instruction>,
+    handlers: Vec<Handler>,
     cofn: bool,
+    debug: DebugInfo,
   ) -> Self {
     Self {
+      name,
       args,
       literals,
       instructions,
+      handlers,
       cofn,
+      test: false,
+      debug,
     }
   }
 
+  /// Find the innermost exception handler covering the given
+  /// instruction pointer.
+  pub(crate) fn find_handler(
+    &self,
+    ip: usize,
+  ) -> Option<&Handler> {
+    self
+      .handlers
+      .iter()
+      .find(|handler| handler.start <= ip && ip < handler.end)
+  }
+
   fn write_args(
     args: &[String],
     writer: &mut impl Write,
@@ -64,7 +254,7 @@ impl FnByteCode {
   ) -> std::io::Result<()> {
     serialize_size(instructions.len(), writer)?;
     for instruction in instructions {
-      instruction.serialize(writer).unwrap();
+      instruction.serialize(writer)?;
     }
     Ok(())
   }
@@ -76,96 +266,65 @@ impl FnByteCode {
     Self::write_args(&self.args, writer)?;
     Self::write_literals(&self.literals, writer)?;
     Self::write_insts(&self.instructions, writer)?;
-    writer.write_all(&[self.cofn as u8])?;
+    serialize_size(self.handlers.len(), writer)?;
+    for handler in &self.handlers {
+      handler.serialize(writer)?;
+    }
+    writer
+      .write_all(&[self.cofn as u8 | (self.test as u8) << 1])?;
+    self.debug.serialize(writer)?;
     Ok(())
   }
 
   fn read_args(
     reader: &mut impl Read,
   ) -> std::io::Result<Vec<String>> {
-    let num_args = deserialize_size(reader)?;
-    let mut args = Vec::with_capacity(num_args);
-    for _ in 0..num_args {
-      args.push(deserialize_str(reader)?);
-    }
-    Ok(args)
+    deserialize_vec(reader, deserialize_str)
   }
 
   fn read_literals(
     reader: &mut impl Read,
   ) -> std::io::Result<Vec<Value>> {
-    let num_literals = deserialize_size(reader)?;
-    let mut literals = Vec::with_capacity(num_literals);
-    for _ in 0..num_literals {
-      literals.push(Value::deserialize(reader)?);
-    }
-    Ok(literals)
+    deserialize_vec(reader, Value::deserialize)
   }
 
   fn read_instructions(
     reader: &mut impl Read,
   ) -> std::io::Result<Vec<Instruction>> {
-    let num_instructions = deserialize_size(reader)?;
-    let mut instructions = Vec::with_capacity(num_instructions);
-    for _ in 0..num_instructions {
-      let inst = Instruction::deserialize(reader)?;
-      instructions.push(inst);
-    }
-    Ok(instructions)
+    deserialize_vec(reader, Instruction::deserialize)
   }
 
   fn deserialize(
+    name: String,
     reader: &mut impl Read,
   ) -> std::io::Result<Self> {
     let args = Self::read_args(reader)?;
     let literals = Self::read_literals(reader)?;
     let instructions = Self::read_instructions(reader)?;
-    let mut cofn = [0u8];
-    reader.read_exact(&mut cofn)?;
+    let handlers =
+      deserialize_vec(reader, Handler::deserialize)?;
+    let mut flags = [0u8];
+    reader.read_exact(&mut flags)?;
+    let debug = DebugInfo::deserialize(reader)?;
     Ok(Self {
+      name,
       args,
       literals,
       instructions,
-      cofn: cofn[0] != 0,
+      handlers,
+      cofn: flags[0] & 1 != 0,
+      test: flags[0] & 2 != 0,
+      debug,
     })
   }
 
+  /// Write the function in the text format of the `asm` module.
   pub(crate) fn disasm(
     &self,
     writer: &mut impl Write,
   ) -> std::io::Result<()> {
-    disasm_common(&self.literals, &self.instructions, writer)
-  }
-}
-
-fn disasm_common(
-  literals: &[Value],
-  instructions: &[Instruction],
-  writer: &mut impl Write,
-) -> std::io::Result<()> {
-  use OpCode::*;
-  writeln!(writer, "  Literals [{}]", literals.len())?;
-  for (i, con) in literals.iter().enumerate() {
-    writeln!(writer, "    [{i}] {}", *con)?;
-  }
-
-  writeln!(writer, "  Instructions [{}]", instructions.len())?;
-  for (i, inst) in instructions.iter().enumerate() {
-    match inst.op {
-      LoadLiteral => writeln!(
-        writer,
-        "    [{i}] {:?} {} ({:?})",
-        inst.op, inst.arg0, literals[inst.arg0 as usize]
-      )?,
-      Copy | Dup | Call | Jmp | Jf | Pop | Store => writeln!(
-        writer,
-        "    [{i}] {:?} {}",
-        inst.op, inst.arg0
-      )?,
-      _ => writeln!(writer, "    [{i}] {:?}", inst.op)?,
-    }
+    disasm_fn(self, writer)
   }
-  Ok(())
 }
 pub(crate) enum FnDecl<'src> {
   User(UserFn<'src>),
@@ -178,7 +337,7 @@ impl<'src> FnDecl<'src> {
       Self::User(user) => user
         .args
         .iter()
-        .map(|(name, ty)| (*name.fragment(), *ty))
+        .map(|(name, ty)| (*name.fragment(), ty.clone()))
         .collect(),
       Self::Native(code) => code.args.clone(),
     }
@@ -188,17 +347,52 @@ impl<'src> FnDecl<'src> {
     match self {
       Self::User(user) => {
         if user.cofn {
-          TypeDecl::Coro
+          TypeDecl::Coro(Box::new(user.ret_type.clone()))
         } else {
-          user.ret_type
+          user.ret_type.clone()
         }
       }
-      Self::Native(native) => native.ret_type,
+      Self::Native(native) => native.ret_type.clone(),
+    }
+  }
+
+  /// Where the function is defined, or `None` if it is native.
+  pub fn def_span(&self) -> Option<Span<'src>> {
+    match self {
+      Self::User(user) => Some(user.name),
+      Self::Native(_) => None,
+    }
+  }
+
+  /// Whether the function accepts more arguments than declared.
+  pub fn variadic(&self) -> bool {
+    match self {
+      Self::User(_) => false,
+      Self::Native(native) => native.variadic,
+    }
+  }
+
+  /// Human readable signature, like `fn pow(lhs: F64, rhs: F64) -> F64`.
+  pub fn signature(&self, name: &str) -> String {
+    let mut args: Vec<_> = self
+      .args()
+      .iter()
+      .map(|(name, ty)| format!("{name}: {ty:?}"))
+      .collect();
+    if self.variadic() {
+      args.push("...".to_string());
     }
+    format!(
+      "fn {name}({}) -> {:?}",
+      args.join(", "),
+      self.ret_type()
+    )
   }
 }
 
 pub struct UserFn<'src> {
+  /// Name of the function where it is defined
+  name: Span<'src>,
   args: Vec<(Span<'src>, TypeDecl)>,
   ret_type: TypeDecl,
   cofn: bool,
@@ -206,11 +400,13 @@ pub struct UserFn<'src> {
 
 impl<'src> UserFn<'src> {
   pub fn new(
+    name: Span<'src>,
     args: Vec<(Span<'src>, TypeDecl)>,
     ret_type: TypeDecl,
     cofn: bool,
   ) -> Self {
     Self {
+      name,
       args,
       ret_type,
       cofn,
@@ -218,12 +414,19 @@ impl<'src> UserFn<'src> {
   }
 }
 
+/// A native function is given mutable access to the user data of the
+/// `Vm`, which it can downcast to the type of the host. It returns
+/// either its result or an error message, which is thrown as an
+/// error value that scripts can catch.
 pub(crate) type NativeFnClosure =
-  Box<dyn Fn(&dyn Any, &[Value]) -> Value>;
+  Box<dyn Fn(&mut dyn Any, &[Value]) -> Result<Value, String>>;
 
 pub struct NativeFn<'src> {
   args: Vec<(&'src str, TypeDecl)>,
   ret_type: TypeDecl,
+  /// Accepts any number of extra arguments of any type after the
+  /// declared ones.
+  variadic: bool,
   pub(crate) code: NativeFnClosure,
 }
 
@@ -236,9 +439,17 @@ impl<'src> NativeFn<'src> {
     Self {
       args,
       ret_type,
+      variadic: false,
       code,
     }
   }
+
+  /// Mark the function as accepting extra arguments after the
+  /// declared ones, like `print`.
+  pub fn variadic(mut self) -> Self {
+    self.variadic = true;
+    self
+  }
 }
 
 pub enum FnDef {
@@ -265,8 +476,9 @@ pub(crate) fn standard_functions<'src>() -> Functions<'src> {
   funcs.insert(
     "print".to_string(),
     FnDecl::Native(NativeFn {
-      args: vec![("arg", TypeDecl::Any)],
+      args: vec![],
       ret_type: TypeDecl::Any,
+      variadic: true,
       code: Box::new(print_fn),
     }),
   );
@@ -275,14 +487,16 @@ pub(crate) fn standard_functions<'src>() -> Functions<'src> {
     FnDecl::Native(NativeFn {
       args: vec![("arg", TypeDecl::Any)],
       ret_type: TypeDecl::Any,
+      variadic: false,
       code: Box::new(dbg_fn),
     }),
   );
   funcs.insert(
     "puts".to_string(),
     FnDecl::Native(NativeFn {
-      args: vec![("arg", TypeDecl::Any)],
+      args: vec![],
       ret_type: TypeDecl::Any,
+      variadic: true,
       code: Box::new(puts_fn),
     }),
   );
@@ -291,6 +505,7 @@ pub(crate) fn standard_functions<'src>() -> Functions<'src> {
     FnDecl::Native(NativeFn {
       args: vec![("arg", TypeDecl::Any)],
       ret_type: TypeDecl::Str,
+      variadic: false,
       code: Box::new(type_fn),
     }),
   );
@@ -299,14 +514,14 @@ pub(crate) fn standard_functions<'src>() -> Functions<'src> {
     FnDecl::Native(NativeFn {
       args: vec![("arg", TypeDecl::Any)],
       ret_type: TypeDecl::I64,
+      variadic: false,
       code: Box::new(move |_, args| {
-        Value::I64(
+        Ok(Value::I64(
           args
             .first()
-            .expect("function missing argument")
-            .coerce_i64()
-            .unwrap_or(0),
-        )
+            .ok_or("function missing argument")?
+            .coerce_i64()?,
+        ))
       }),
     }),
   );
@@ -315,14 +530,14 @@ pub(crate) fn standard_functions<'src>() -> Functions<'src> {
     FnDecl::Native(NativeFn {
       args: vec![("arg", TypeDecl::Any)],
       ret_type: TypeDecl::F64,
+      variadic: false,
       code: Box::new(move |_, args| {
-        Value::F64(
+        Ok(Value::F64(
           args
             .first()
-            .expect("function missing argument")
-            .coerce_f64()
-            .unwrap_or(0.),
-        )
+            .ok_or("function missing argument")?
+            .coerce_f64()?,
+        ))
       }),
     }),
   );
@@ -331,31 +546,89 @@ pub(crate) fn standard_functions<'src>() -> Functions<'src> {
     FnDecl::Native(NativeFn {
       args: vec![("arg", TypeDecl::Any)],
       ret_type: TypeDecl::Str,
+      variadic: false,
+      code: Box::new(move |_, args| {
+        Ok(Value::Str(
+          args
+            .first()
+            .ok_or("function missing argument")?
+            .coerce_str()?,
+        ))
+      }),
+    }),
+  );
+  funcs.insert(
+    "format".to_string(),
+    FnDecl::Native(NativeFn {
+      args: vec![("fmt", TypeDecl::Str)],
+      ret_type: TypeDecl::Str,
+      variadic: true,
+      code: Box::new(format_fn),
+    }),
+  );
+  funcs.insert(
+    "find".to_string(),
+    FnDecl::Native(NativeFn {
+      args: vec![("s", TypeDecl::Str), ("pat", TypeDecl::Str)],
+      ret_type: TypeDecl::Opt(Box::new(TypeDecl::I64)),
+      variadic: false,
+      code: Box::new(find_fn),
+    }),
+  );
+  funcs.insert(
+    "error".to_string(),
+    FnDecl::Native(NativeFn {
+      args: vec![("msg", TypeDecl::Str)],
+      ret_type: TypeDecl::Error,
+      variadic: false,
       code: Box::new(move |_, args| {
-        Value::Str(
+        Ok(Value::Error(
           args
             .first()
-            .expect("function missing argument")
-            .coerce_str()
-            .unwrap_or("".to_string()),
-        )
+            .ok_or("function missing argument")?
+            .coerce_str()?,
+        ))
       }),
     }),
   );
+  funcs.insert(
+    "assert".to_string(),
+    FnDecl::Native(NativeFn {
+      args: vec![
+        ("cond", TypeDecl::Any),
+        ("msg", TypeDecl::Str),
+      ],
+      ret_type: TypeDecl::Any,
+      variadic: false,
+      code: Box::new(assert_fn),
+    }),
+  );
+  funcs.insert(
+    "assert_eq".to_string(),
+    FnDecl::Native(NativeFn {
+      args: vec![
+        ("left", TypeDecl::Any),
+        ("right", TypeDecl::Any),
+      ],
+      ret_type: TypeDecl::Any,
+      variadic: false,
+      code: Box::new(assert_eq_fn),
+    }),
+  );
   funcs
 }
 
 fn unary_fn<'a>(f: fn(f64) -> f64) -> FnDecl<'a> {
   FnDecl::Native(NativeFn {
-    args: vec![("lhs", TypeDecl::F64), ("rhs", TypeDecl::F64)],
+    args: vec![("x", TypeDecl::F64)],
     ret_type: TypeDecl::F64,
+    variadic: false,
     code: Box::new(move |_, args| {
-      Value::F64(f(args
+      Ok(Value::F64(f(args
         .iter()
         .next()
-        .expect("function missing argument")
-        .coerce_f64()
-        .unwrap()))
+        .ok_or("function missing argument")?
+        .coerce_f64()?)))
     }),
   })
 }
@@ -364,55 +637,162 @@ fn binary_fn<'a>(f: fn(f64, f64) -> f64) -> FnDecl<'a> {
   FnDecl::Native(NativeFn {
     args: vec![("lhs", TypeDecl::F64), ("rhs", TypeDecl::F64)],
     ret_type: TypeDecl::F64,
+    variadic: false,
     code: Box::new(move |_, args| {
       let mut args = args.iter();
       let lhs = args
         .next()
-        .expect("function missing the first argument")
-        .coerce_f64()
-        .unwrap();
+        .ok_or("function missing the first argument")?
+        .coerce_f64()?;
       let rhs = args
         .next()
-        .expect("function missing the second argument")
-        .coerce_f64()
-        .unwrap();
-      Value::F64(f(lhs, rhs))
+        .ok_or("function missing the second argument")?
+        .coerce_f64()?;
+      Ok(Value::F64(f(lhs, rhs)))
     }),
   })
 }
 
-fn print_fn(_: &dyn Any, args: &[Value]) -> Value {
+fn print_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
   for arg in args {
     print!("{} ", arg);
   }
   println!();
-  Value::F64(0.)
+  Ok(Value::F64(0.))
 }
 
-fn dbg_fn(_: &dyn Any, values: &[Value]) -> Value {
-  println!("dbg: {:?}", values[0]);
-  Value::I64(0)
+fn dbg_fn(
+  _: &mut dyn Any,
+  values: &[Value],
+) -> Result<Value, String> {
+  println!(
+    "dbg: {:?}",
+    values.first().ok_or("function missing argument")?
+  );
+  Ok(Value::I64(0))
 }
 
-fn puts_fn(_: &dyn Any, args: &[Value]) -> Value {
+fn puts_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
   for arg in args {
     print!("{}", arg);
   }
-  Value::F64(0.)
+  Ok(Value::F64(0.))
 }
 
-fn type_fn(_: &dyn Any, args: &[Value]) -> Value {
-  Value::Str(match args.first() {
+fn type_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
+  Ok(Value::Str(match args.first() {
     Some(value) => match value {
       Value::I64(_) => "I64".to_string(),
       Value::F64(_) => "F64".to_string(),
       Value::Str(_) => "Str".to_string(),
       Value::Coro(_) => "Coro".to_string(),
+      Value::Error(_) => "Error".to_string(),
+      Value::None => "None".to_string(),
     },
     _ => "".to_string(),
+  }))
+}
+
+/// Formats the rest of the arguments by the format string in the
+/// first argument. See the `format` module for the syntax.
+fn format_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
+  let [fmt, args @ ..] = args else {
+    return Err("format needs a format string".to_string());
+  };
+  Ok(Value::Str(format_values(&fmt.coerce_str()?, args)?))
+}
+
+/// Returns the character index of the first occurrence of `pat` in
+/// `s`, or none if it is not found.
+fn find_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
+  let [s, pat, ..] = args else {
+    return Err("find needs 2 arguments".to_string());
+  };
+  let (s, pat) = (s.coerce_str()?, pat.coerce_str()?);
+  Ok(match s.find(&pat) {
+    Some(pos) => Value::I64(s[..pos].chars().count() as i64),
+    None => Value::None,
   })
 }
 
+/// Throws the message if the condition is false, which is zero like
+/// in `if`.
+fn assert_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
+  let [cond, msg, ..] = args else {
+    return Err("assert needs 2 arguments".to_string());
+  };
+  if cond.coerce_f64() == Ok(0.) {
+    return Err(format!(
+      "assertion failed: {}",
+      msg.coerce_str()?
+    ));
+  }
+  Ok(Value::I64(0))
+}
+
+/// Throws if the values differ. Numbers are compared by value,
+/// whether they are `i64` or `f64`.
+fn assert_eq_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
+  let [left, right, ..] = args else {
+    return Err("assert_eq needs 2 arguments".to_string());
+  };
+  let equal = match (left, right) {
+    (Value::I64(i), Value::F64(f))
+    | (Value::F64(f), Value::I64(i)) => *i as f64 == *f,
+    _ => left == right,
+  };
+  if !equal {
+    return Err(format!(
+      "assertion failed: left == right\n  left: {left}\n right: {right}"
+    ));
+  }
+  Ok(Value::I64(0))
+}
+
+/// Read the functions of a bytecode file in the order they are
+/// written.
+pub(crate) fn read_fn_list(
+  reader: &mut impl Read,
+) -> std::io::Result<Vec<FnByteCode>> {
+  deserialize_vec(reader, |reader| {
+    let name = deserialize_str(reader)?;
+    FnByteCode::deserialize(name, reader)
+  })
+}
+
+pub(crate) fn write_fn_list<'a>(
+  funcs: impl ExactSizeIterator<Item = &'a FnByteCode>,
+  writer: &mut impl Write,
+) -> std::io::Result<()> {
+  serialize_size(funcs.len(), writer)?;
+  for func in funcs {
+    serialize_str(&func.name, writer)?;
+    func.serialize(writer)?;
+  }
+  Ok(())
+}
+
 pub struct ByteCode {
   pub(crate) funcs: HashMap<String, FnDef>,
 }
@@ -442,7 +822,6 @@ impl ByteCode {
     &mut self,
     reader: &mut impl Read,
   ) -> std::io::Result<()> {
-    let num_funcs = deserialize_size(reader)?;
     let mut funcs: HashMap<_, _> = standard_functions()
       .into_iter()
       .filter_map(|(name, f)| {
@@ -453,32 +832,99 @@ impl ByteCode {
         }
       })
       .collect();
-    for _ in 0..num_funcs {
-      let name = deserialize_str(reader)?;
-      funcs.insert(
-        name,
-        FnDef::User(Rc::new(FnByteCode::deserialize(reader)?)),
-      );
+    for func in read_fn_list(reader)? {
+      funcs
+        .insert(func.name.clone(), FnDef::User(Rc::new(func)));
     }
     self.funcs = funcs;
     Ok(())
   }
 
+  /// Disassemble the functions in the order of their names, with
+  /// the native ones as comments.
   pub fn disasm(
     &self,
     writer: &mut impl Write,
   ) -> std::io::Result<()> {
-    for (fname, fn_def) in &self.funcs {
-      match fn_def {
-        FnDef::User(f) => {
-          writeln!(writer, "Function {}:", fname)?;
-          f.disasm(writer)?;
-        }
-        FnDef::Native(_) => {
-          writeln!(writer, "Function {}: <Native>", fname)?;
-        }
+    let mut funcs: Vec<_> = self
+      .funcs
+      .iter()
+      .filter_map(|(name, f)| match f {
+        FnDef::User(f) => Some((name, f)),
+        FnDef::Native(_) => None,
+      })
+      .collect();
+    funcs.sort_by_key(|(name, _)| *name);
+    for (i, (_, func)) in funcs.into_iter().enumerate() {
+      if i > 0 {
+        writeln!(writer)?;
       }
+      func.disasm(writer)?;
     }
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn local(
+    name: &str,
+    slot: usize,
+    start: usize,
+    end: usize,
+  ) -> LocalVar {
+    LocalVar {
+      name: name.to_string(),
+      slot,
+      start,
+      end,
+    }
+  }
+
+  fn names(debug: &DebugInfo, ip: usize) -> Vec<(&str, usize)> {
+    debug
+      .locals_at(ip)
+      .map(|var| (var.name.as_str(), var.slot))
+      .collect()
+  }
+
+  #[test]
+  fn test_add_line() {
+    let mut debug = DebugInfo::default();
+    debug.add_line(0, 1);
+    debug.add_line(3, 2);
+    // Another statement on the same line
+    debug.add_line(5, 2);
+    assert_eq!(debug.lines, [(0, 1), (3, 2)]);
+    // A statement without instructions is replaced by the next one.
+    debug.add_line(7, 3);
+    debug.add_line(7, 4);
+    assert_eq!(debug.lines, [(0, 1), (3, 2), (7, 4)]);
+
+    assert_eq!(debug.stmt_line(3), Some(2));
+    assert_eq!(debug.stmt_line(5), None);
+    assert_eq!(debug.line(5), Some(2));
+    assert_eq!(debug.line(100), Some(4));
+    assert_eq!(DebugInfo::default().line(0), None);
+  }
+
+  #[test]
+  fn test_locals_at_shadowing() {
+    let debug = DebugInfo {
+      lines: vec![],
+      locals: vec![
+        local("x", 0, 0, 10),
+        local("y", 1, 2, 10),
+        // `var x` again in a block
+        local("x", 2, 5, 8),
+      ],
+    };
+    assert_eq!(names(&debug, 1), [("x", 0)]);
+    assert_eq!(names(&debug, 2), [("x", 0), ("y", 1)]);
+    assert_eq!(names(&debug, 6), [("y", 1), ("x", 2)]);
+    assert_eq!(names(&debug, 8), [("x", 0), ("y", 1)]);
+    assert_eq!(names(&debug, 10), []);
+  }
+}
@@ -5,13 +5,16 @@ use std::{
 
 use crate::{
   ast::{
-    ExprEnum, Expression, Span, Statement, Statements, TypeDecl,
+    ExprEnum, Expression, Span, Statement, Statements, StrPart,
+    TypeDecl,
   },
   bytecode::{
-    standard_functions, ByteCode, FnByteCode, FnDecl, FnDef,
+    standard_functions, write_fn_list, ByteCode, DebugInfo,
+    FnByteCode, FnDecl, FnDef, Handler, LocalVar,
   },
+  diagnostics::Diagnostic,
   instructions::{Instruction, OpCode},
-  value::{serialize_size, serialize_str, Value},
+  value::Value,
 };
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -61,12 +64,46 @@ impl Display for LoopStackUnderflowError {
 
 impl Error for LoopStackUnderflowError {}
 
+/// The value as the argument of an instruction, which is a byte.
+fn inst_arg(
+  value: usize,
+  what: &str,
+) -> Result<u8, Box<dyn Error>> {
+  u8::try_from(value).map_err(|_| {
+    Diagnostic::error(format!(
+      "{what} {value} does not fit in an instruction, which takes \
+      at most {}",
+      u8::MAX
+    ))
+    .into()
+  })
+}
+
+/// Give an error the location of the statement being compiled,
+/// unless it has one already.
+fn locate(
+  e: Box<dyn Error>,
+  stmt: &Statement,
+) -> Box<dyn Error> {
+  match (e.downcast::<Diagnostic>(), stmt.span()) {
+    (Ok(diag), Some(span)) if diag.primary.is_none() => {
+      Box::new(diag.with_span(span))
+    }
+    (Ok(diag), _) => diag,
+    (Err(e), _) => e,
+  }
+}
+
 pub struct Compiler {
   literals: Vec<Value>,
   instructions: Vec<Instruction>,
+  handlers: Vec<Handler>,
   target_stack: Vec<Target>,
   funcs: HashMap<String, FnByteCode>,
   loop_stack: Vec<LoopFrame>,
+  debug: DebugInfo,
+  /// Local variables in scope, whose ends are not known yet
+  open_locals: Vec<LocalVar>,
 }
 
 impl Default for Compiler {
@@ -80,9 +117,12 @@ impl Compiler {
     Self {
       literals: vec![],
       instructions: vec![],
+      handlers: vec![],
       target_stack: vec![],
       funcs: HashMap::new(),
       loop_stack: vec![],
+      debug: DebugInfo::default(),
+      open_locals: vec![],
     }
   }
 
@@ -113,7 +153,8 @@ impl Compiler {
       self.loop_stack.pop().ok_or(LoopStackUnderflowError)?;
     let break_jmp_addr = self.instructions.len();
     for ip in loop_frame.break_ips {
-      self.instructions[ip.0].arg0 = break_jmp_addr as u8;
+      self.instructions[ip.0].arg0 =
+        inst_arg(break_jmp_addr, "Jump target")?;
     }
     Ok(())
   }
@@ -123,26 +164,45 @@ impl Compiler {
       self.loop_stack.last().ok_or(LoopStackUnderflowError)?;
     let continue_jmp_addr = self.instructions.len();
     for (ip, stk) in &loop_frame.continue_ips {
-      self.instructions[ip.0].arg0 =
-        (self.target_stack.len() - stk) as u8;
+      self.instructions[ip.0].arg0 = inst_arg(
+        self.target_stack.len() - stk,
+        "Number of values to pop",
+      )?;
       self.instructions[ip.0 + 1].arg0 =
-        continue_jmp_addr as u8;
+        inst_arg(continue_jmp_addr, "Jump target")?;
     }
     Ok(())
   }
 
-  fn add_literal(&mut self, value: Value) -> u8 {
+  /// The innermost loop being compiled, or an error pointing at the
+  /// `break` or `continue` statement at `span` if there is none.
+  fn innermost_loop(
+    &mut self,
+    span: Span,
+  ) -> Result<&mut LoopFrame, Box<dyn Error>> {
+    self.loop_stack.last_mut().ok_or_else(|| {
+      Diagnostic::error(LoopStackUnderflowError.to_string())
+        .with_span(span)
+        .into()
+    })
+  }
+
+  fn add_literal(
+    &mut self,
+    value: Value,
+  ) -> Result<u8, Box<dyn Error>> {
     let existing = self
       .literals
       .iter()
       .enumerate()
       .find(|(_, val)| **val == value);
     if let Some((i, _)) = existing {
-      i as u8
+      inst_arg(i, "Literal number")
     } else {
-      let ret = self.literals.len();
+      let ret =
+        inst_arg(self.literals.len(), "Literal number")?;
       self.literals.push(value);
-      ret as u8
+      Ok(ret)
     }
   }
 
@@ -153,13 +213,45 @@ impl Compiler {
     InstPtr(inst)
   }
 
-  fn add_copy_inst(&mut self, stack_idx: StkIdx) -> InstPtr {
-    let inst = self.add_inst(
-      OpCode::Copy,
-      (self.target_stack.len() - stack_idx.0 - 1) as u8,
-    );
+  /// Distance of the stack index from the top, as the argument of
+  /// an instruction.
+  fn stack_offset(
+    &self,
+    stack_idx: StkIdx,
+  ) -> Result<u8, Box<dyn Error>> {
+    let offset = self.target_stack.len() - stack_idx.0 - 1;
+    u8::try_from(offset).map_err(|_| {
+      Diagnostic::error(format!(
+        "Too many values on the stack: {offset} values above the \
+        one to access, but at most {} are supported",
+        u8::MAX
+      ))
+      .into()
+    })
+  }
+
+  fn add_copy_inst(
+    &mut self,
+    stack_idx: StkIdx,
+  ) -> Result<InstPtr, Box<dyn Error>> {
+    let inst = self
+      .add_inst(OpCode::Copy, self.stack_offset(stack_idx)?);
     self.target_stack.push(Target::Temp);
-    inst
+    Ok(inst)
+  }
+
+  /// Copy the value of the last expression of a function body to
+  /// the top of the stack, where a function falling off its end
+  /// returns it from. A loop or a variable definition may have left
+  /// other values above it.
+  fn copy_result_to_top(
+    &mut self,
+    res: StkIdx,
+  ) -> Result<(), Box<dyn Error>> {
+    if res.0 + 1 != self.target_stack.len() {
+      self.add_copy_inst(res)?;
+    }
+    Ok(())
   }
 
   fn add_load_literal_inst(&mut self, lit: u8) -> InstPtr {
@@ -173,23 +265,19 @@ impl Compiler {
     self.add_inst(op, 0)
   }
 
-  fn add_store_inst(&mut self, stack_idx: StkIdx) -> InstPtr {
+  fn add_store_inst(
+    &mut self,
+    stack_idx: StkIdx,
+  ) -> Result<InstPtr, Box<dyn Error>> {
     if self.target_stack.len() < stack_idx.0 + 1 {
-      eprintln!("Compiled bytecode so far:");
-      disasm_common(
-        &self.literals,
-        &self.instructions,
-        &mut std::io::stderr(),
-      )
-      .unwrap();
-      panic!("Target stack undeflow during compilation!");
+      return Err(Box::new(Diagnostic::error(
+        "Target stack underflow during compilation",
+      )));
     }
-    let inst = self.add_inst(
-      OpCode::Store,
-      (self.target_stack.len() - stack_idx.0 - 1) as u8,
-    );
+    let inst = self
+      .add_inst(OpCode::Store, self.stack_offset(stack_idx)?);
     self.target_stack.pop();
-    inst
+    Ok(inst)
   }
 
   fn add_jf_inst(&mut self) -> InstPtr {
@@ -199,25 +287,34 @@ impl Compiler {
     inst
   }
 
-  fn fixup_jmp(&mut self, ip: InstPtr) {
+  fn add_jn_inst(&mut self) -> InstPtr {
+    // Push with jump address 0, because it will be set later
+    let inst = self.add_inst(OpCode::Jn, 0);
+    self.target_stack.pop();
+    inst
+  }
+
+  fn fixup_jmp(
+    &mut self,
+    ip: InstPtr,
+  ) -> Result<(), Box<dyn Error>> {
     self.instructions[ip.0].arg0 =
-      self.instructions.len() as u8;
+      inst_arg(self.instructions.len(), "Jump target")?;
+    Ok(())
   }
 
   /// Pop until given stack index
   fn add_pop_until_inst(
     &mut self,
     stack_idx: StkIdx,
-  ) -> Option<InstPtr> {
+  ) -> Result<Option<InstPtr>, Box<dyn Error>> {
     if self.target_stack.len() <= stack_idx.0 {
-      return None;
+      return Ok(None);
     }
-    let inst = self.add_inst(
-      OpCode::Pop,
-      (self.target_stack.len() - stack_idx.0 - 1) as u8,
-    );
+    let inst =
+      self.add_inst(OpCode::Pop, self.stack_offset(stack_idx)?);
     self.target_stack.resize(stack_idx.0 + 1, Target::Temp);
-    Some(inst)
+    Ok(Some(inst))
   }
 
   fn add_fn(
@@ -225,28 +322,88 @@ impl Compiler {
     name: String,
     args: &[(Span, TypeDecl)],
     cofn: bool,
-  ) {
-    self.funcs.insert(
-      name,
-      FnByteCode::new(
-        args.iter().map(|(arg, _)| arg.to_string()).collect(),
-        std::mem::take(&mut self.literals),
-        std::mem::take(&mut self.instructions),
-        cofn,
-      ),
+  ) -> &mut FnByteCode {
+    self.sync_locals();
+    let end = self.instructions.len();
+    for mut var in std::mem::take(&mut self.open_locals) {
+      var.end = end;
+      if var.start < var.end {
+        self.debug.locals.push(var);
+      }
+    }
+    let func = FnByteCode::new(
+      name.clone(),
+      args.iter().map(|(arg, _)| arg.to_string()).collect(),
+      std::mem::take(&mut self.literals),
+      std::mem::take(&mut self.instructions),
+      std::mem::take(&mut self.handlers),
+      cofn,
+      std::mem::take(&mut self.debug),
     );
+    self.funcs.entry(name).insert_entry(func).into_mut()
+  }
+
+  /// Update the scopes of the local variables in the debug
+  /// information to the variables on the stack now.
+  fn sync_locals(&mut self) {
+    let ip = self.instructions.len();
+    let live: Vec<(usize, &String)> = self
+      .target_stack
+      .iter()
+      .enumerate()
+      .filter_map(|(slot, target)| match target {
+        Target::Local(name) => Some((slot, name)),
+        _ => None,
+      })
+      .collect();
+    let (open, closed): (Vec<_>, Vec<_>) =
+      std::mem::take(&mut self.open_locals)
+        .into_iter()
+        .partition(|var| live.contains(&(var.slot, &var.name)));
+    for mut var in closed {
+      var.end = ip;
+      if var.start < var.end {
+        self.debug.locals.push(var);
+      }
+    }
+    let opened: Vec<_> = live
+      .iter()
+      .filter(|(slot, name)| {
+        !open
+          .iter()
+          .any(|var| var.slot == *slot && var.name == **name)
+      })
+      .map(|(slot, name)| LocalVar {
+        name: name.to_string(),
+        slot: *slot,
+        start: ip,
+        end: ip,
+      })
+      .collect();
+    self.open_locals = open;
+    self.open_locals.extend(opened);
+  }
+
+  /// Record where a statement starts in the debug information.
+  fn add_debug_line(&mut self, stmt: &Statement) {
+    // A function definition has no instructions here.
+    if matches!(stmt, Statement::FnDef { .. }) {
+      return;
+    }
+    self.sync_locals();
+    if let Some(span) = stmt.span() {
+      self.debug.add_line(
+        self.instructions.len(),
+        span.location_line() as usize,
+      );
+    }
   }
 
   pub(crate) fn write_funcs(
     &self,
     writer: &mut impl Write,
   ) -> std::io::Result<()> {
-    serialize_size(self.funcs.len(), writer)?;
-    for (name, func) in &self.funcs {
-      serialize_str(name, writer)?;
-      func.serialize(writer)?;
-    }
-    Ok(())
+    write_fn_list(self.funcs.values(), writer)
   }
 
   fn compile_expr(
@@ -255,17 +412,53 @@ impl Compiler {
   ) -> Result<StkIdx, Box<dyn Error>> {
     Ok(match &ex.expr {
       ExprEnum::NumLiteral(num) => {
-        let id = self.add_literal(Value::F64(*num));
+        let id = self.add_literal(Value::F64(*num))?;
         self.add_load_literal_inst(id);
         self.stack_top()
       }
       ExprEnum::StrLiteral(str) => {
-        let id = self.add_literal(Value::Str(str.clone()));
+        let id = self.add_literal(Value::Str(str.clone()))?;
         self.add_load_literal_inst(id);
         self.stack_top()
       }
+      ExprEnum::Interpolated(parts) => {
+        // Compiles to a call to the format native function with the
+        // embedded expressions replaced by placeholders.
+        let mut fmt = String::new();
+        let mut args = vec![];
+        for part in parts {
+          match part {
+            // The text is printed as it is written.
+            StrPart::Text(text) => {
+              fmt += &text.replace('{', "{{").replace('}', "}}")
+            }
+            StrPart::Expr(arg, spec) => {
+              match spec {
+                Some(spec) => fmt += &format!("{{:{spec}}}"),
+                None => fmt += "{}",
+              }
+              args.push(arg.clone());
+            }
+          }
+        }
+        args.insert(
+          0,
+          Expression::new(ExprEnum::StrLiteral(fmt), ex.span),
+        );
+        self.compile_fn_invoke("format", &args)?
+      }
+      ExprEnum::NoneLiteral => {
+        let id = self.add_literal(Value::None)?;
+        self.add_load_literal_inst(id);
+        self.stack_top()
+      }
+      // A present optional value has the same representation as
+      // the inner value.
+      ExprEnum::SomeValue(ex) => self.compile_expr(ex)?,
       ExprEnum::Ident(ident) => {
-        let var = self.target_stack.iter().enumerate().find(
+        // Search from the top so that the latest definition of a
+        // shadowed variable is found.
+        let var = self.target_stack.iter().enumerate().rfind(
           |(_i, tgt)| {
             if let Target::Local(id) = tgt {
               id == ident.fragment()
@@ -277,9 +470,12 @@ impl Compiler {
         if let Some(var) = var {
           return Ok(StkIdx(var.0));
         } else {
-          return Err(
-            format!("Variable not found: {ident:?}").into(),
-          );
+          return Err(Box::new(
+            Diagnostic::error(format!(
+              "Variable \"{ident}\" not found in scope"
+            ))
+            .with_span(*ident),
+          ));
         }
       }
       ExprEnum::Add(lhs, rhs) => {
@@ -301,56 +497,147 @@ impl Compiler {
         self.bin_op(OpCode::Lt, lhs, rhs)?
       }
       ExprEnum::FnInvoke(name, args) => {
-        let stack_before_args = self.target_stack.len();
-        let name =
-          self.add_literal(Value::Str(name.to_string()));
-        let args = args
-          .iter()
-          .map(|arg| self.compile_expr(arg))
-          .collect::<Result<Vec<_>, _>>()?;
-
-        let stack_before_call = self.target_stack.len();
-        self.add_load_literal_inst(name);
-        for arg in &args {
-          self.add_copy_inst(*arg);
-        }
-
-        self.add_inst(OpCode::Call, args.len() as u8);
-        self
-          .target_stack
-          .resize(stack_before_call + 1, Target::Temp);
-        self.coerce_stack(StkIdx(stack_before_args));
-        self.stack_top()
+        self.compile_fn_invoke(name, args)?
       }
       ExprEnum::If(cond, true_branch, false_branch) => {
         use OpCode::*;
         let cond = self.compile_expr(cond)?;
-        self.add_copy_inst(cond);
+        self.add_copy_inst(cond)?;
         let jf_inst = self.add_jf_inst();
         let stack_size_before = self.target_stack.len();
         self.compile_stmts_or_zero(true_branch)?;
-        self.coerce_stack(StkIdx(stack_size_before + 1));
+        self.coerce_stack(StkIdx(stack_size_before + 1))?;
         let jmp_inst = self.add_inst(Jmp, 0);
-        self.fixup_jmp(jf_inst);
+        self.fixup_jmp(jf_inst)?;
         self
           .target_stack
           .resize(stack_size_before, Target::Temp);
         if let Some(false_branch) = false_branch.as_ref() {
           self.compile_stmts_or_zero(false_branch)?;
         }
-        self.coerce_stack(StkIdx(stack_size_before + 1));
-        self.fixup_jmp(jmp_inst);
+        self.coerce_stack(StkIdx(stack_size_before + 1))?;
+        self.fixup_jmp(jmp_inst)?;
+        self.stack_top()
+      }
+      ExprEnum::Coalesce(lhs, rhs) => {
+        let lhs = self.compile_expr(lhs)?;
+        self.add_copy_inst(lhs)?;
+        let jn_inst = self.add_jn_inst();
+        let stack_size_before = self.target_stack.len();
+        self.add_copy_inst(lhs)?;
+        let jmp_inst = self.add_inst(OpCode::Jmp, 0);
+        self.fixup_jmp(jn_inst)?;
+        self
+          .target_stack
+          .resize(stack_size_before, Target::Temp);
+        let rhs = self.compile_expr(rhs)?;
+        if rhs != self.stack_top() {
+          self.add_copy_inst(rhs)?;
+        }
+        self.coerce_stack(StkIdx(stack_size_before))?;
+        self.fixup_jmp(jmp_inst)?;
+        self.stack_top()
+      }
+      ExprEnum::IfLet(name, ex, true_branch, false_branch) => {
+        let ex = self.compile_expr(ex)?;
+        self.add_copy_inst(ex)?;
+        let jn_inst = self.add_jn_inst();
+        let stack_size_before = self.target_stack.len();
+        self.add_copy_inst(ex)?;
+        self.target_stack[stack_size_before] =
+          Target::Local(name.to_string());
+        let res = self.compile_stmts_or_zero(true_branch)?;
+        if res != self.stack_top() {
+          self.add_copy_inst(res)?;
+        }
+        self.coerce_stack(StkIdx(stack_size_before))?;
+        self.target_stack[stack_size_before] = Target::Temp;
+        let jmp_inst = self.add_inst(OpCode::Jmp, 0);
+        self.fixup_jmp(jn_inst)?;
+        self
+          .target_stack
+          .resize(stack_size_before, Target::Temp);
+        let res = if let Some(false_branch) = false_branch {
+          self.compile_stmts_or_zero(false_branch)?
+        } else {
+          self.compile_stmts_or_zero(&vec![])?
+        };
+        if res != self.stack_top() {
+          self.add_copy_inst(res)?;
+        }
+        self.coerce_stack(StkIdx(stack_size_before))?;
+        self.fixup_jmp(jmp_inst)?;
         self.stack_top()
       }
       ExprEnum::Await(ex) => {
         let res = self.compile_expr(ex)?;
-        self.add_copy_inst(res);
+        self.add_copy_inst(res)?;
         self.add_inst(OpCode::Await, 0);
         self.stack_top()
       }
+      ExprEnum::Try(body, name, handler) => {
+        let stack_depth = self.target_stack.len();
+        let start = self.instructions.len();
+        let res = self.compile_stmts_or_zero(body)?;
+        if res != self.stack_top() {
+          self.add_copy_inst(res)?;
+        }
+        self.coerce_stack(StkIdx(stack_depth))?;
+        let end = self.instructions.len();
+        let jmp_inst = self.add_inst(OpCode::Jmp, 0);
+
+        // The VM pushes the thrown error value on top of the
+        // truncated stack before jumping to the handler.
+        let target = self.instructions.len();
+        self.target_stack.resize(stack_depth, Target::Temp);
+        self.target_stack.push(Target::Local(name.to_string()));
+        let res = self.compile_stmts_or_zero(handler)?;
+        if res != self.stack_top() {
+          self.add_copy_inst(res)?;
+        }
+        self.coerce_stack(StkIdx(stack_depth))?;
+        self.target_stack[stack_depth] = Target::Temp;
+        self.fixup_jmp(jmp_inst)?;
+
+        self.handlers.push(Handler {
+          start,
+          end,
+          target,
+          stack_depth,
+        });
+        self.stack_top()
+      }
     })
   }
 
+  fn compile_fn_invoke(
+    &mut self,
+    name: &str,
+    args: &[Expression],
+  ) -> Result<StkIdx, Box<dyn Error>> {
+    let argc = inst_arg(args.len(), "Number of arguments")?;
+    let stack_before_args = self.target_stack.len();
+    let name =
+      self.add_literal(Value::Str(name.to_string()))?;
+    let args = args
+      .iter()
+      .map(|arg| self.compile_expr(arg))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let stack_before_call = self.target_stack.len();
+    self.add_load_literal_inst(name);
+    for arg in &args {
+      self.add_copy_inst(*arg)?;
+    }
+
+    self.add_inst(OpCode::Call, argc);
+    self
+      .target_stack
+      .resize(stack_before_call + 1, Target::Temp);
+    self.coerce_stack(StkIdx(stack_before_args))?;
+    Ok(self.stack_top())
+  }
+
   fn bin_op(
     &mut self,
     op: OpCode,
@@ -359,8 +646,8 @@ impl Compiler {
   ) -> Result<StkIdx, Box<dyn Error>> {
     let lhs = self.compile_expr(lhs)?;
     let rhs = self.compile_expr(rhs)?;
-    self.add_copy_inst(lhs);
-    self.add_copy_inst(rhs);
+    self.add_copy_inst(lhs)?;
+    self.add_copy_inst(rhs)?;
     self.add_inst(op, 0);
     self.target_stack.pop();
     self.target_stack.pop();
@@ -370,20 +657,24 @@ impl Compiler {
 
   /// Coerce the stack size to be target + 1, and move the old top
   /// to the new top.
-  fn coerce_stack(&mut self, target: StkIdx) {
+  fn coerce_stack(
+    &mut self,
+    target: StkIdx,
+  ) -> Result<(), Box<dyn Error>> {
     use std::cmp::Ordering;
     match target.0.cmp(&(self.target_stack.len() - 1)) {
       Ordering::Less => {
-        self.add_store_inst(target);
-        self.add_pop_until_inst(target);
+        self.add_store_inst(target)?;
+        self.add_pop_until_inst(target)?;
       }
       Ordering::Greater => {
         for _ in self.target_stack.len() - 1..target.0 {
-          self.add_copy_inst(self.stack_top());
+          self.add_copy_inst(self.stack_top())?;
         }
       }
       _ => {}
     }
+    Ok(())
   }
 
   fn compile_stmts(
@@ -392,162 +683,181 @@ impl Compiler {
   ) -> Result<Option<StkIdx>, Box<dyn Error>> {
     let mut last_result = None;
     for stmt in stmts {
-      match stmt {
-        Statement::Expression(ex) => {
-          last_result = Some(self.compile_expr(ex)?);
-        }
-        Statement::VarDef { name, ex, .. } => {
-          let mut ex = self.compile_expr(ex)?;
-          if !matches!(self.target_stack[ex.0], Target::Temp) {
-            self.add_copy_inst(ex);
-            ex = self.stack_top();
-          }
-          self.target_stack[ex.0] =
-            Target::Local(name.to_string());
-        }
-        Statement::VarAssign { name, ex, .. } => {
-          let stk_ex = self.compile_expr(ex)?;
-          let (stk_local, _) = self
-            .target_stack
-            .iter_mut()
-            .enumerate()
-            .find(|(_, tgt)| {
-              if let Target::Local(tgt) = tgt {
-                tgt == name.fragment()
-              } else {
-                false
-              }
-            })
-            .ok_or_else(|| {
-              format!("Variable name not found: {name}")
-            })?;
-          self.add_copy_inst(stk_ex);
-          self.add_store_inst(StkIdx(stk_local));
-        }
-        Statement::For {
-          loop_var,
-          start,
-          end,
-          stmts,
-          ..
-        } => {
-          let stk_start = self.compile_expr(start)?;
-          let stk_end = self.compile_expr(end)?;
-          // dprintln!("start: {stk_start:?} end: {stk_end:?}");
-          self.add_copy_inst(stk_start);
-          let stk_loop_var = self.stack_top();
-          self.target_stack[stk_loop_var.0] =
-            Target::Local(loop_var.to_string());
-          // dprintln!("after start: {:?}", self.target_stack);
-          let inst_check_exit = self.instructions.len();
-          self.add_copy_inst(stk_loop_var);
-          self.add_copy_inst(stk_end);
-          // dprintln!("before cmp: {:?}", self.target_stack);
-          self.add_binop_inst(OpCode::Lt);
-          let jf_inst = self.add_jf_inst();
-          // dprintln!("start in loop: {:?}", self.target_stack);
-          self.loop_stack.push(LoopFrame::new(stk_loop_var));
-          self.compile_stmts(stmts)?;
-          self.fixup_continues()?;
-          let one = self.add_literal(Value::F64(1.));
-          // dprintln!("end in loop: {:?}", self.target_stack);
-          self.add_copy_inst(stk_loop_var);
-          self.add_load_literal_inst(one);
-          self.add_inst(OpCode::Add, 0);
-          self.target_stack.pop();
-          self.add_store_inst(stk_loop_var);
-          self.add_pop_until_inst(stk_loop_var);
-          self.add_inst(OpCode::Jmp, inst_check_exit as u8);
-          self.fixup_jmp(jf_inst);
-          self.fixup_breaks()?;
-        }
-        Statement::Break => {
-          let start = self
-            .loop_stack
-            .last()
-            .map(|loop_frame| loop_frame.start)
-            .ok_or(LoopStackUnderflowError)?;
-          self.add_pop_until_inst(start);
-
-          let loop_frame = self
-            .loop_stack
-            .last_mut()
-            .ok_or(LoopStackUnderflowError)?;
-          let break_ip = self.instructions.len();
-          loop_frame.break_ips.push(InstPtr(break_ip));
-          self.add_inst(OpCode::Jmp, 0);
-        }
-        Statement::Continue => {
-          let start = self
-            .loop_stack
-            .last()
-            .map(|frame| frame.start)
-            .ok_or(LoopStackUnderflowError)?;
-          self.add_pop_until_inst(start);
-
-          let loop_frame = self
-            .loop_stack
-            .last_mut()
-            .ok_or(LoopStackUnderflowError)?;
-          let continue_ip = self.instructions.len();
-          loop_frame.continue_ips.push((
-            InstPtr(continue_ip),
-            self.target_stack.len(),
-          ));
-          self.add_inst(OpCode::Dup, 0);
-          self.add_inst(OpCode::Jmp, 0);
-        }
-        Statement::FnDef {
-          name,
-          args,
-          stmts,
-          cofn,
-          ..
-        } => {
-          let literals = std::mem::take(&mut self.literals);
-          let instructions =
-            std::mem::take(&mut self.instructions);
-          let target_stack =
-            std::mem::take(&mut self.target_stack);
-          self.target_stack = args
-            .iter()
-            .map(|arg| Target::Local(arg.0.to_string()))
-            .collect();
-          self.compile_stmts(stmts)?;
-          self.add_fn(name.to_string(), args, *cofn);
-          self.literals = literals;
-          self.instructions = instructions;
-          self.target_stack = target_stack;
-        }
-        Statement::Return(ex) => {
-          let res = self.compile_expr(ex)?;
-          self.add_inst(
-            OpCode::Ret,
-            (self.target_stack.len() - res.0 - 1) as u8,
-          );
+      self.add_debug_line(stmt);
+      self
+        .compile_stmt(stmt, &mut last_result)
+        .map_err(|e| locate(e, stmt))?;
+    }
+    Ok(last_result)
+  }
+
+  /// Compile a statement. The stack index of the value of an
+  /// expression statement is written to `last_result`.
+  fn compile_stmt(
+    &mut self,
+    stmt: &Statement,
+    last_result: &mut Option<StkIdx>,
+  ) -> Result<(), Box<dyn Error>> {
+    match stmt {
+      Statement::Expression(ex) => {
+        *last_result = Some(self.compile_expr(ex)?);
+      }
+      Statement::VarDef { name, ex, .. } => {
+        let mut ex = self.compile_expr(ex)?;
+        if !matches!(self.target_stack[ex.0], Target::Temp) {
+          self.add_copy_inst(ex)?;
+          ex = self.stack_top();
         }
-        Statement::Yield(ex) => {
-          let res = self.compile_expr(ex)?;
-          self.add_inst(
-            OpCode::Yield,
-            (self.target_stack.len() - res.0 - 1) as u8,
-          );
-          self.target_stack.pop();
+        self.target_stack[ex.0] =
+          Target::Local(name.to_string());
+      }
+      Statement::VarAssign { name, ex, .. } => {
+        let stk_ex = self.compile_expr(ex)?;
+        let (stk_local, _) = self
+          .target_stack
+          .iter_mut()
+          .enumerate()
+          .rfind(|(_, tgt)| {
+            if let Target::Local(tgt) = tgt {
+              tgt == name.fragment()
+            } else {
+              false
+            }
+          })
+          .ok_or_else(|| {
+            Diagnostic::error(format!(
+              "Variable \"{name}\" not found in scope"
+            ))
+            .with_span(*name)
+          })?;
+        self.add_copy_inst(stk_ex)?;
+        self.add_store_inst(StkIdx(stk_local))?;
+      }
+      Statement::For {
+        loop_var,
+        start,
+        end,
+        stmts,
+        ..
+      } => {
+        let stk_start = self.compile_expr(start)?;
+        let stk_end = self.compile_expr(end)?;
+        // dprintln!("start: {stk_start:?} end: {stk_end:?}");
+        self.add_copy_inst(stk_start)?;
+        let stk_loop_var = self.stack_top();
+        self.target_stack[stk_loop_var.0] =
+          Target::Local(loop_var.to_string());
+        // dprintln!("after start: {:?}", self.target_stack);
+        let inst_check_exit = self.instructions.len();
+        self.add_copy_inst(stk_loop_var)?;
+        self.add_copy_inst(stk_end)?;
+        // dprintln!("before cmp: {:?}", self.target_stack);
+        self.add_binop_inst(OpCode::Lt);
+        let jf_inst = self.add_jf_inst();
+        // dprintln!("start in loop: {:?}", self.target_stack);
+        self.loop_stack.push(LoopFrame::new(stk_loop_var));
+        self.compile_stmts(stmts)?;
+        self.fixup_continues()?;
+        let one = self.add_literal(Value::F64(1.))?;
+        // dprintln!("end in loop: {:?}", self.target_stack);
+        self.add_copy_inst(stk_loop_var)?;
+        self.add_load_literal_inst(one);
+        self.add_inst(OpCode::Add, 0);
+        self.target_stack.pop();
+        self.add_store_inst(stk_loop_var)?;
+        self.add_pop_until_inst(stk_loop_var)?;
+        self.add_inst(
+          OpCode::Jmp,
+          inst_arg(inst_check_exit, "Jump target")?,
+        );
+        self.fixup_jmp(jf_inst)?;
+        self.fixup_breaks()?;
+      }
+      Statement::Break(span) => {
+        let start = self.innermost_loop(*span)?.start;
+        self.add_pop_until_inst(start)?;
+
+        let break_ip = self.instructions.len();
+        self
+          .innermost_loop(*span)?
+          .break_ips
+          .push(InstPtr(break_ip));
+        self.add_inst(OpCode::Jmp, 0);
+      }
+      Statement::Continue(span) => {
+        let start = self.innermost_loop(*span)?.start;
+        self.add_pop_until_inst(start)?;
+
+        let continue_ip = self.instructions.len();
+        let stack_len = self.target_stack.len();
+        self
+          .innermost_loop(*span)?
+          .continue_ips
+          .push((InstPtr(continue_ip), stack_len));
+        self.add_inst(OpCode::Dup, 0);
+        self.add_inst(OpCode::Jmp, 0);
+      }
+      Statement::FnDef {
+        name,
+        args,
+        stmts,
+        cofn,
+        test,
+        ..
+      } => {
+        let literals = std::mem::take(&mut self.literals);
+        let instructions =
+          std::mem::take(&mut self.instructions);
+        let handlers = std::mem::take(&mut self.handlers);
+        let target_stack =
+          std::mem::take(&mut self.target_stack);
+        let debug = std::mem::take(&mut self.debug);
+        let open_locals = std::mem::take(&mut self.open_locals);
+        self.target_stack = args
+          .iter()
+          .map(|arg| Target::Local(arg.0.to_string()))
+          .collect();
+        if let Some(res) = self.compile_stmts(stmts)? {
+          self.copy_result_to_top(res)?;
         }
+        self.add_fn(name.to_string(), args, *cofn).test = *test;
+        self.literals = literals;
+        self.instructions = instructions;
+        self.handlers = handlers;
+        self.target_stack = target_stack;
+        self.debug = debug;
+        self.open_locals = open_locals;
+      }
+      Statement::Return(ex) => {
+        let res = self.compile_expr(ex)?;
+        self.add_inst(OpCode::Ret, self.stack_offset(res)?);
+      }
+      Statement::Yield(ex) => {
+        let res = self.compile_expr(ex)?;
+        self.add_copy_inst(res)?;
+        self.add_inst(OpCode::Yield, 0);
+        self.target_stack.pop();
+      }
+      Statement::Throw(ex) => {
+        let res = self.compile_expr(ex)?;
+        self.add_copy_inst(res)?;
+        self.add_inst(OpCode::Throw, 0);
+        self.target_stack.pop();
       }
     }
-    Ok(last_result)
+    Ok(())
   }
 
   fn compile_stmts_or_zero(
     &mut self,
     stmts: &Statements,
   ) -> Result<StkIdx, Box<dyn Error>> {
-    Ok(self.compile_stmts(stmts)?.unwrap_or_else(|| {
-      let id = self.add_literal(Value::F64(0.));
-      self.add_load_literal_inst(id);
-      self.stack_top()
-    }))
+    if let Some(res) = self.compile_stmts(stmts)? {
+      return Ok(res);
+    }
+    let id = self.add_literal(Value::F64(0.))?;
+    self.add_load_literal_inst(id);
+    Ok(self.stack_top())
   }
 
   pub fn compile(
@@ -555,53 +865,118 @@ impl Compiler {
     stmts: &Statements,
   ) -> Result<(), Box<dyn std::error::Error>> {
     let name = "main";
-    self.compile_stmts_or_zero(stmts)?;
+    let res = self.compile_stmts_or_zero(stmts)?;
+    self.copy_result_to_top(res)?;
     self.add_fn(name.to_string(), &[], false);
     Ok(())
   }
 
+  /// Compile an input of a REPL as the function `name`.
+  ///
+  /// The function runs on the stack left by the previous inputs,
+  /// so the variables defined by them stay live. It ends by
+  /// yielding the value of the last expression, or `none`, instead
+  /// of returning, so that the stack survives for the next input.
+  /// On error, the variables defined by the input are discarded.
+  pub(crate) fn compile_repl(
+    &mut self,
+    name: &str,
+    stmts: &Statements,
+  ) -> Result<(), Box<dyn Error>> {
+    let depth = self.stack_len();
+    if let Err(e) = self.compile_repl_stmts(stmts, depth) {
+      self.truncate_stack(depth);
+      self.literals.clear();
+      self.instructions.clear();
+      self.handlers.clear();
+      self.funcs.clear();
+      self.debug = DebugInfo::default();
+      self.open_locals.clear();
+      return Err(e);
+    }
+    self.add_fn(name.to_string(), &[], false);
+    Ok(())
+  }
+
+  fn compile_repl_stmts(
+    &mut self,
+    stmts: &Statements,
+    depth: usize,
+  ) -> Result<(), Box<dyn Error>> {
+    match self.compile_stmts(stmts)? {
+      Some(res) => {
+        self.add_copy_inst(res)?;
+      }
+      None => {
+        let id = self.add_literal(Value::None)?;
+        self.add_load_literal_inst(id);
+      }
+    }
+    self.pop_temps(depth)?;
+    self.add_inst(OpCode::Yield, 0);
+    self.target_stack.pop();
+    Ok(())
+  }
+
+  /// Move the variables above `depth` down over the temporary
+  /// values left between them and pop the temporaries, keeping the
+  /// value on the top. Otherwise the temporaries of every REPL
+  /// input would pile up on the stack.
+  fn pop_temps(
+    &mut self,
+    depth: usize,
+  ) -> Result<(), Box<dyn Error>> {
+    let top = self.stack_top();
+    let mut free = depth;
+    for idx in depth..top.0 {
+      if !matches!(self.target_stack[idx], Target::Local(_)) {
+        continue;
+      }
+      if idx != free {
+        self.add_copy_inst(StkIdx(idx))?;
+        self.add_store_inst(StkIdx(free))?;
+        self.target_stack.swap(idx, free);
+      }
+      free += 1;
+    }
+    if free != top.0 {
+      self.add_store_inst(StkIdx(free))?;
+      self.target_stack[free] = Target::Temp;
+      self.add_pop_until_inst(StkIdx(free))?;
+    }
+    Ok(())
+  }
+
+  /// Number of values on the stack of the function being compiled.
+  pub(crate) fn stack_len(&self) -> usize {
+    self.target_stack.len()
+  }
+
+  /// Forget the values on the stack above `len`, when a REPL input
+  /// that pushed them failed.
+  pub(crate) fn truncate_stack(&mut self, len: usize) {
+    self.target_stack.truncate(len);
+  }
+
+  /// Take the functions compiled so far.
+  pub(crate) fn take_funcs(
+    &mut self,
+  ) -> HashMap<String, FnByteCode> {
+    std::mem::take(&mut self.funcs)
+  }
+
   pub fn disasm(
     &self,
     writer: &mut impl Write,
   ) -> std::io::Result<()> {
-    for (name, fn_def) in &self.funcs {
-      if fn_def.cofn {
-        writeln!(writer, "Coroutine {name:?}:")?;
-      } else {
-        writeln!(writer, "Function {name:?}:")?;
+    let mut funcs: Vec<_> = self.funcs.values().collect();
+    funcs.sort_by_key(|func| &func.name);
+    for (i, func) in funcs.into_iter().enumerate() {
+      if i > 0 {
+        writeln!(writer)?;
       }
-      fn_def.disasm(writer)?;
+      func.disasm(writer)?;
     }
     Ok(())
   }
 }
-
-fn disasm_common(
-  literals: &[Value],
-  instructions: &[Instruction],
-  writer: &mut impl Write,
-) -> std::io::Result<()> {
-  use OpCode::*;
-  writeln!(writer, "  Literals [{}]", literals.len())?;
-  for (i, con) in literals.iter().enumerate() {
-    writeln!(writer, "    [{i}] {}", *con)?;
-  }
-
-  writeln!(writer, "  Instructions [{}]", instructions.len())?;
-  for (i, inst) in instructions.iter().enumerate() {
-    match inst.op {
-      LoadLiteral => writeln!(
-        writer,
-        "    [{i}] {:?} {} ({:?})",
-        inst.op, inst.arg0, literals[inst.arg0 as usize]
-      )?,
-      Copy | Call | Jmp | Jf | Pop | Store | Ret => writeln!(
-        writer,
-        "    [{i}] {:?} {}",
-        inst.op, inst.arg0
-      )?,
-      _ => writeln!(writer, "    [{i}] {:?}", inst.op)?,
-    }
-  }
-  Ok(())
-}
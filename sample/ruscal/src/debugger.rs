@@ -0,0 +1,318 @@
+//! Source-level debugger attached to a `Vm` by `-g`.
+//!
+//! Execution stops at the start of statements, found by the line
+//! tables that the compiler records in the debug information of the
+//! functions, and the commands are read from stdin. Coroutines run
+//! in their own `Vm`s, which share the debugger with the `Vm` that
+//! created them, so stepping and backtraces go across them.
+
+use std::{
+  collections::BTreeSet, error::Error, ffi::OsStr, path::Path,
+};
+
+use crate::{
+  bytecode::{ByteCode, FnDef},
+  tracer::Tracer,
+  value::Value,
+  vm::{StackFrame, Vm},
+};
+
+/// The error returned by `Vm::interpret` when the user quits the
+/// debugger.
+#[derive(Debug)]
+pub struct Quit;
+
+impl std::fmt::Display for Quit {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "Execution stopped by the debugger")
+  }
+}
+
+impl Error for Quit {}
+
+const HELP: &str = r#"Commands:
+    break [file:]line   Set a breakpoint, or list them without line
+    delete [file:]line  Delete a breakpoint
+    continue            Run until a breakpoint
+    step                Run to the next statement, into calls
+    next                Run to the next statement, over calls
+    finish              Run until the current function returns
+    print [name]        Show a variable, or all the variables
+    backtrace           Show the functions being called
+    list                Show the source around the current line
+    quit                Stop the program
+Commands can be abbreviated to the first letter, and bt for
+backtrace."#;
+
+enum Mode {
+  Continue,
+  Step,
+  /// Stop at a statement in a frame at most this deep
+  Next(usize),
+  /// Stop at a statement in a frame less deep than this
+  Finish(usize),
+}
+
+/// A stack frame as shown in backtraces.
+struct FrameInfo {
+  name: String,
+  line: Option<usize>,
+}
+
+impl FrameInfo {
+  fn new(frame: &StackFrame) -> Self {
+    Self {
+      name: frame.fn_def.name.clone(),
+      line: frame.fn_def.debug.line(frame.ip),
+    }
+  }
+}
+
+pub struct Debugger {
+  source_name: String,
+  source: Vec<String>,
+  /// Lines where statements start, which breakpoints can be set on
+  stmt_lines: BTreeSet<usize>,
+  breakpoints: BTreeSet<usize>,
+  mode: Mode,
+  /// Frames of the `Vm`s waiting for coroutines, outermost first
+  outer: Vec<Vec<FrameInfo>>,
+}
+
+impl Debugger {
+  /// A debugger for the program compiled from the source, which
+  /// stops at its first statement.
+  pub fn new(
+    source_name: &str,
+    source: &str,
+    bytecode: &ByteCode,
+  ) -> Self {
+    let stmt_lines = bytecode
+      .funcs
+      .values()
+      .filter_map(|f| match f {
+        FnDef::User(f) => Some(f),
+        FnDef::Native(_) => None,
+      })
+      .flat_map(|f| f.debug.lines.iter().map(|(_, line)| *line))
+      .collect();
+    Self {
+      source_name: source_name.to_string(),
+      source: source.lines().map(str::to_string).collect(),
+      stmt_lines,
+      breakpoints: BTreeSet::new(),
+      mode: Mode::Step,
+      outer: vec![],
+    }
+  }
+
+  fn prompt(
+    &mut self,
+    vm: &Vm,
+    frame: &StackFrame,
+    depth: usize,
+  ) -> Result<(), Box<dyn Error>> {
+    loop {
+      print!("(debug) ");
+      std::io::Write::flush(&mut std::io::stdout())?;
+      let mut input = String::new();
+      if std::io::stdin().read_line(&mut input)? == 0 {
+        println!();
+        return Err(Quit.into());
+      }
+      let input = input.trim();
+      let (command, arg) =
+        input.split_once(' ').unwrap_or((input, ""));
+      let arg = arg.trim();
+      self.mode = match command {
+        "c" | "continue" => Mode::Continue,
+        "s" | "step" => Mode::Step,
+        "n" | "next" => Mode::Next(depth),
+        "f" | "finish" => Mode::Finish(depth),
+        "q" | "quit" => return Err(Quit.into()),
+        _ => {
+          self.command(vm, frame, command, arg);
+          continue;
+        }
+      };
+      return Ok(());
+    }
+  }
+
+  /// Run a command that does not resume execution.
+  fn command(
+    &mut self,
+    vm: &Vm,
+    frame: &StackFrame,
+    command: &str,
+    arg: &str,
+  ) {
+    match command {
+      "b" | "break" if arg.is_empty() => {
+        for line in &self.breakpoints {
+          println!("Breakpoint at {}:{line}", self.source_name);
+        }
+      }
+      "b" | "break" => match self.set_breakpoint(arg) {
+        Ok(line) => {
+          println!("Breakpoint at {}:{line}", self.source_name)
+        }
+        Err(e) => println!("{e}"),
+      },
+      "d" | "delete" => match self.location(arg) {
+        Ok(line) if self.breakpoints.remove(&line) => (),
+        Ok(line) => println!("No breakpoint at line {line}"),
+        Err(e) => println!("{e}"),
+      },
+      "p" | "print" => self.print(frame, arg),
+      "bt" | "backtrace" => self.backtrace(vm),
+      "l" | "list" => {
+        if let Some(line) = frame.fn_def.debug.line(frame.ip) {
+          for i in line.saturating_sub(5).max(1)..line + 5 {
+            self.show_line(i, if i == line { ">" } else { "" });
+          }
+        }
+      }
+      "h" | "help" => println!("{HELP}"),
+      "" => (),
+      _ => println!("Unknown command {command}; try help"),
+    }
+  }
+
+  /// Parse a location written as `file:line` or `line`.
+  fn location(&self, arg: &str) -> Result<usize, String> {
+    let line = match arg.rsplit_once(':') {
+      Some((file, line)) => {
+        let name = Path::new(&self.source_name).file_name();
+        if file != self.source_name
+          && name != Some(OsStr::new(file))
+        {
+          return Err(format!("No source file named {file}"));
+        }
+        line
+      }
+      None => arg,
+    };
+    line.parse().map_err(|_| {
+      format!("Expected a line number, got {line:?}")
+    })
+  }
+
+  /// Set a breakpoint at the first statement from the location and
+  /// return its line.
+  fn set_breakpoint(
+    &mut self,
+    arg: &str,
+  ) -> Result<usize, String> {
+    let line = self.location(arg)?;
+    let line =
+      *self.stmt_lines.range(line..).next().ok_or_else(
+        || format!("No statement at or after line {line}"),
+      )?;
+    self.breakpoints.insert(line);
+    Ok(line)
+  }
+
+  fn print(&self, frame: &StackFrame, name: &str) {
+    let mut found = false;
+    for var in frame.fn_def.debug.locals_at(frame.ip) {
+      if !name.is_empty() && var.name != name {
+        continue;
+      }
+      if let Some(value) = frame.stack().get(var.slot) {
+        println!("{} = {value}", var.name);
+        found = true;
+      }
+    }
+    if !found {
+      if name.is_empty() {
+        println!("No variables in scope");
+      } else {
+        println!("No variable {name} in scope");
+      }
+    }
+  }
+
+  fn backtrace(&self, vm: &Vm) {
+    let current: Vec<_> =
+      vm.frames().iter().map(FrameInfo::new).collect();
+    let frames = self.outer.iter().flatten().chain(&current);
+    for (i, frame) in frames.rev().enumerate() {
+      let name = &frame.name;
+      match frame.line {
+        Some(line) => {
+          println!(
+            "#{i} {name} at {}:{line}",
+            self.source_name
+          );
+          self.show_line(line, "");
+        }
+        None => println!("#{i} {name}"),
+      }
+    }
+  }
+
+  fn show_line(&self, line: usize, marker: &str) {
+    if let Some(text) =
+      line.checked_sub(1).and_then(|i| self.source.get(i))
+    {
+      println!("{marker:>1}{line:>4} | {text}");
+    }
+  }
+}
+
+impl Tracer for Debugger {
+  /// Enter the command prompt if execution should stop at the
+  /// instruction.
+  fn on_instruction(
+    &mut self,
+    vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    let Some(frame) = vm.frames().last() else {
+      return Ok(());
+    };
+    let Some(line) = frame.fn_def.debug.stmt_line(frame.ip)
+    else {
+      return Ok(());
+    };
+    let depth = self.outer.iter().map(Vec::len).sum::<usize>()
+      + vm.frames().len();
+    let stop = match self.mode {
+      Mode::Continue => false,
+      Mode::Step => true,
+      Mode::Next(max) => depth <= max,
+      Mode::Finish(max) => depth < max,
+    };
+    if !stop && !self.breakpoints.contains(&line) {
+      return Ok(());
+    }
+    println!(
+      "{}:{line} in {}",
+      self.source_name, frame.fn_def.name
+    );
+    self.show_line(line, "");
+    self.prompt(vm, frame, depth)
+  }
+
+  fn on_await(
+    &mut self,
+    vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    self
+      .outer
+      .push(vm.frames().iter().map(FrameInfo::new).collect());
+    Ok(())
+  }
+
+  fn on_resume(
+    &mut self,
+    _vm: &Vm,
+    _value: Option<&Value>,
+  ) -> Result<(), Box<dyn Error>> {
+    self.outer.pop();
+    Ok(())
+  }
+}
@@ -0,0 +1,438 @@
+//! Rendering of errors and warnings with source snippets, in the
+//! style of rustc:
+//!
+//! ```text
+//! error: Operation Add between incompatible type: I64 and Str
+//!  --> example.rscl:3:9
+//!   |
+//! 3 | var b = a + "x";
+//!   |         ^^^^^^^
+//!   |         - this is I64
+//!   |             --- this is Str
+//! ```
+//!
+//! A `Diagnostic` owns its locations, so it can be returned as a
+//! `Box<dyn Error>` and rendered later with the source text.
+
+use std::fmt::Write;
+
+use crate::ast::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+  Error,
+  Warning,
+}
+
+impl Level {
+  fn name(&self) -> &'static str {
+    match self {
+      Self::Error => "error",
+      Self::Warning => "warning",
+    }
+  }
+
+  fn color(&self) -> &'static str {
+    match self {
+      Self::Error => RED,
+      Self::Warning => YELLOW,
+    }
+  }
+}
+
+/// A location in the source with an optional message.
+#[derive(Debug, Clone)]
+pub struct Label {
+  /// Byte offset of the start of the span in the source
+  pub offset: usize,
+  /// Length of the span in bytes
+  pub len: usize,
+  pub line: u32,
+  pub column: usize,
+  pub msg: String,
+}
+
+impl Label {
+  pub fn new(span: Span, msg: impl Into<String>) -> Self {
+    Self {
+      offset: span.location_offset(),
+      len: span.fragment().len(),
+      line: span.location_line(),
+      column: span.get_utf8_column(),
+      msg: msg.into(),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+  pub level: Level,
+  /// Short name of the lint or error kind, shown in brackets
+  pub code: Option<String>,
+  pub msg: String,
+  pub primary: Option<Label>,
+  pub secondary: Vec<Label>,
+  pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+  pub fn new(level: Level, msg: impl Into<String>) -> Self {
+    Self {
+      level,
+      code: None,
+      msg: msg.into(),
+      primary: None,
+      secondary: vec![],
+      notes: vec![],
+    }
+  }
+
+  pub fn error(msg: impl Into<String>) -> Self {
+    Self::new(Level::Error, msg)
+  }
+
+  pub fn warning(msg: impl Into<String>) -> Self {
+    Self::new(Level::Warning, msg)
+  }
+
+  pub fn with_code(mut self, code: impl Into<String>) -> Self {
+    self.code = Some(code.into());
+    self
+  }
+
+  pub fn with_span(mut self, span: Span) -> Self {
+    self.primary = Some(Label::new(span, ""));
+    self
+  }
+
+  pub fn with_label(
+    mut self,
+    span: Span,
+    msg: impl Into<String>,
+  ) -> Self {
+    self.secondary.push(Label::new(span, msg));
+    self
+  }
+
+  pub fn with_note(mut self, note: impl Into<String>) -> Self {
+    self.notes.push(note.into());
+    self
+  }
+
+  /// Render the diagnostic with snippets of `src`, which shall be
+  /// the source text the spans point into.
+  pub fn render(
+    &self,
+    file: &str,
+    src: &str,
+    color: bool,
+  ) -> String {
+    let paint = |style: &str, text: &str| {
+      if color {
+        format!("{style}{text}{RESET}")
+      } else {
+        text.to_string()
+      }
+    };
+    let level_color = self.level.color();
+    let mut out = String::new();
+
+    let mut header = self.level.name().to_string();
+    if let Some(code) = &self.code {
+      header += &format!("[{code}]");
+    }
+    let _ = writeln!(
+      out,
+      "{}{}",
+      paint(&format!("{BOLD}{level_color}"), &header),
+      paint(BOLD, &format!(": {}", self.msg))
+    );
+
+    let mut labels: Vec<(&Label, bool)> = self
+      .primary
+      .iter()
+      .map(|label| (label, true))
+      .chain(self.secondary.iter().map(|label| (label, false)))
+      .collect();
+    labels.sort_by_key(|(label, _)| label.offset);
+
+    let width = labels
+      .iter()
+      .map(|(label, _)| label.line.to_string().len())
+      .max()
+      .unwrap_or(1);
+    let gutter = |line: &str| {
+      paint(
+        &format!("{BOLD}{BLUE}"),
+        &format!("{line:>width$} |"),
+      )
+    };
+
+    if let Some(loc) =
+      self.primary.as_ref().or(labels.first().map(|l| l.0))
+    {
+      let _ = writeln!(
+        out,
+        "{:width$}{} {file}:{}:{}",
+        "",
+        paint(&format!("{BOLD}{BLUE}"), "-->"),
+        loc.line,
+        loc.column
+      );
+    }
+
+    if !labels.is_empty() {
+      let _ = writeln!(out, "{}", gutter(""));
+    }
+    let mut prev_line = None;
+    let mut i = 0;
+    while i < labels.len() {
+      let line = labels[i].0.line;
+      let same_line = labels[i..]
+        .iter()
+        .take_while(|(label, _)| label.line == line)
+        .count();
+      if prev_line.is_some_and(|prev| prev + 1 < line) {
+        let _ = writeln!(
+          out,
+          "{}",
+          paint(&format!("{BOLD}{BLUE}"), "...")
+        );
+      }
+      let text = line_text(src, labels[i].0.offset);
+      let _ =
+        writeln!(out, "{} {text}", gutter(&line.to_string()));
+      for (label, primary) in &labels[i..i + same_line] {
+        let (mark, style) = if *primary {
+          ('^', level_color)
+        } else {
+          ('-', BLUE)
+        };
+        let start = label.column - 1;
+        let len = underline_len(src, label).max(1);
+        let mut text = mark.to_string().repeat(len);
+        if !label.msg.is_empty() {
+          text += " ";
+          text += &label.msg;
+        }
+        let _ = writeln!(
+          out,
+          "{} {:start$}{}",
+          gutter(""),
+          "",
+          paint(&format!("{BOLD}{style}"), &text)
+        );
+      }
+      prev_line = Some(line);
+      i += same_line;
+    }
+
+    for note in &self.notes {
+      let _ = writeln!(
+        out,
+        "{:width$} {} {note}",
+        "",
+        paint(&format!("{BOLD}{BLUE}"), "= note:")
+      );
+    }
+    out
+  }
+}
+
+impl std::fmt::Display for Diagnostic {
+  /// Display without the source text, for places where it is not
+  /// available.
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    if let Some(primary) = &self.primary {
+      write!(f, "{}:{}: ", primary.line, primary.column)?;
+    }
+    write!(f, "{}", self.msg)?;
+    for label in &self.secondary {
+      if !label.msg.is_empty() {
+        write!(
+          f,
+          "\n  {}:{}: {}",
+          label.line, label.column, label.msg
+        )?;
+      }
+    }
+    for note in &self.notes {
+      write!(f, "\n  note: {note}")?;
+    }
+    Ok(())
+  }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Error returned when the compilation is aborted by the errors in
+/// the diagnostics.
+#[derive(Debug)]
+pub struct Aborted(pub Vec<Diagnostic>);
+
+impl std::fmt::Display for Aborted {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(
+      f,
+      "aborting due to {} previous error(s)",
+      self.0.len()
+    )
+  }
+}
+
+impl std::error::Error for Aborted {}
+
+/// Whether diagnostics printed to stderr should be colored.
+pub fn use_color() -> bool {
+  use std::io::IsTerminal;
+  std::io::stderr().is_terminal()
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+
+/// The line containing the byte offset, without the line break.
+fn line_text(src: &str, offset: usize) -> &str {
+  let offset = offset.min(src.len());
+  let start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+  let end =
+    src[offset..].find('\n').map_or(src.len(), |i| offset + i);
+  src[start..end].trim_end_matches('\r')
+}
+
+/// Number of characters to underline, up to the end of the first
+/// line of the span.
+fn underline_len(src: &str, label: &Label) -> usize {
+  let start = label.offset.min(src.len());
+  let end = (label.offset + label.len).min(src.len());
+  src[start..end]
+    .lines()
+    .next()
+    .map_or(0, |line| line.trim_end().chars().count())
+}
+
+#[cfg(test)]
+mod test {
+  use nom::Slice;
+
+  use super::*;
+
+  const SRC: &str = "var a = 1;
+var b = a + \"x\";
+print(b);
+print(a,
+  b);
+";
+
+  /// The span of `len` bytes at `offset` in `SRC`.
+  fn span(offset: usize, len: usize) -> Span<'static> {
+    Span::new(SRC).slice(offset..offset + len)
+  }
+
+  fn render(diag: &Diagnostic) -> String {
+    diag.render("test.rscl", SRC, false)
+  }
+
+  #[test]
+  fn test_labels_on_one_line() {
+    let diag = Diagnostic::error("Incompatible types")
+      .with_span(span(19, 7))
+      .with_label(span(19, 1), "this is F64")
+      .with_label(span(23, 3), "this is Str");
+    assert_eq!(
+      render(&diag),
+      r#"error: Incompatible types
+ --> test.rscl:2:9
+  |
+2 | var b = a + "x";
+  |         ^^^^^^^
+  |         - this is F64
+  |             --- this is Str
+"#
+    );
+  }
+
+  #[test]
+  fn test_gap_between_lines() {
+    let diag = Diagnostic::warning("Unused variable")
+      .with_code("unused_variable")
+      .with_span(span(15, 1))
+      .with_label(span(4, 1), "first defined here")
+      .with_label(span(44, 1), "read here");
+    assert_eq!(
+      render(&diag),
+      r#"warning[unused_variable]: Unused variable
+ --> test.rscl:2:5
+  |
+1 | var a = 1;
+  |     - first defined here
+2 | var b = a + "x";
+  |     ^
+...
+4 | print(a,
+  |       - read here
+"#
+    );
+  }
+
+  #[test]
+  fn test_multi_line_span() {
+    let diag = Diagnostic::error("Too many arguments")
+      .with_span(span(38, 14));
+    assert_eq!(
+      render(&diag),
+      r#"error: Too many arguments
+ --> test.rscl:4:1
+  |
+4 | print(a,
+  | ^^^^^^^^
+"#
+    );
+  }
+
+  #[test]
+  fn test_notes_without_span() {
+    let diag = Diagnostic::error("Aborted")
+      .with_note("first note")
+      .with_note("second note");
+    assert_eq!(
+      render(&diag),
+      "error: Aborted
+  = note: first note
+  = note: second note
+"
+    );
+  }
+
+  #[test]
+  fn test_color() {
+    let diag = Diagnostic::error("Bad").with_span(span(0, 3));
+    let plain = render(&diag);
+    assert!(!plain.contains('\x1b'));
+    let colored = diag.render("test.rscl", SRC, true);
+    assert!(
+      colored.starts_with(&format!("{BOLD}{RED}error{RESET}"))
+    );
+    let stripped = colored
+      .split('\x1b')
+      .enumerate()
+      .map(|(i, part)| {
+        if i == 0 {
+          part
+        } else {
+          part.split_once('m').map_or(part, |(_, rest)| rest)
+        }
+      })
+      .collect::<String>();
+    assert_eq!(stripped, plain);
+  }
+}
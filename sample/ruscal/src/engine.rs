@@ -0,0 +1,563 @@
+//! A high level API to embed ruscal in a Rust program.
+//!
+//! An `Engine` holds the native functions registered by the host,
+//! compiles sources against them and runs the results. Natives are
+//! ordinary Rust closures, whose signatures are derived from the
+//! types of their arguments and return values:
+//!
+//! ```
+//! use ruscal::engine::Engine;
+//!
+//! let mut engine = Engine::new();
+//! engine.register_fn("hypot", |x: f64, y: f64| x.hypot(y));
+//! let res: f64 = engine.eval("hypot(3, 4);").unwrap();
+//! assert_eq!(res, 5.);
+//! ```
+//!
+//! Natives can also take the host context of the engine as their
+//! first argument to mutate it, and return a `Result` to throw an
+//! error in the script:
+//!
+//! ```
+//! use ruscal::engine::Engine;
+//!
+//! let mut engine = Engine::with_host(String::new());
+//! engine.register_fn("emit", |out: &mut String, s: String| {
+//!   out.push_str(&s)
+//! });
+//! engine.register_fn("parse", |s: String| s.parse::<i64>());
+//! engine.eval::<i64>("emit(\"a\"); emit(\"b\"); 0;").unwrap();
+//! assert_eq!(*engine.host(), "ab");
+//! assert!(engine.eval::<i64>("parse(\"x\");").is_err());
+//! ```
+
+use std::{
+  cell::{Ref, RefCell, RefMut},
+  collections::HashMap,
+  error::Error,
+  fmt::Display,
+  marker::PhantomData,
+  rc::Rc,
+};
+
+use crate::{
+  ast::TypeDecl,
+  bytecode::{ByteCode, NativeFn},
+  compiler::Compiler,
+  diagnostics::{Aborted, Diagnostic},
+  file_io::parse_program,
+  type_checker::{type_check, TypeCheckContext},
+  value::Value,
+  vm::{TraceFrame, UncaughtError, UserData, Vm},
+};
+
+/// A Rust type that can be passed to a script.
+pub trait IntoValue {
+  /// The type of the value in the script
+  fn type_decl() -> TypeDecl;
+  fn into_value(self) -> Value;
+}
+
+/// A Rust type that can be received from a script.
+pub trait FromValue: Sized {
+  /// The type of the value in the script
+  fn type_decl() -> TypeDecl;
+  fn from_value(value: Value) -> Result<Self, String>;
+}
+
+impl IntoValue for f64 {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::F64
+  }
+
+  fn into_value(self) -> Value {
+    Value::F64(self)
+  }
+}
+
+impl FromValue for f64 {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::F64
+  }
+
+  fn from_value(value: Value) -> Result<Self, String> {
+    value.coerce_f64()
+  }
+}
+
+impl IntoValue for i64 {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::I64
+  }
+
+  fn into_value(self) -> Value {
+    Value::I64(self)
+  }
+}
+
+impl FromValue for i64 {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::I64
+  }
+
+  fn from_value(value: Value) -> Result<Self, String> {
+    value.coerce_i64()
+  }
+}
+
+impl IntoValue for String {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::Str
+  }
+
+  fn into_value(self) -> Value {
+    Value::Str(self)
+  }
+}
+
+impl IntoValue for &str {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::Str
+  }
+
+  fn into_value(self) -> Value {
+    Value::Str(self.to_string())
+  }
+}
+
+impl FromValue for String {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::Str
+  }
+
+  fn from_value(value: Value) -> Result<Self, String> {
+    match value {
+      Value::Str(s) => Ok(s),
+      value => value.coerce_str(),
+    }
+  }
+}
+
+/// An optional value, which is `none` in the script if absent.
+impl<T: IntoValue> IntoValue for Option<T> {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::Opt(Box::new(T::type_decl()))
+  }
+
+  fn into_value(self) -> Value {
+    self.map_or(Value::None, T::into_value)
+  }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::Opt(Box::new(T::type_decl()))
+  }
+
+  fn from_value(value: Value) -> Result<Self, String> {
+    match value {
+      Value::None => Ok(None),
+      value => T::from_value(value).map(Some),
+    }
+  }
+}
+
+/// A value of any type, left for the host to inspect.
+impl IntoValue for Value {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::Any
+  }
+
+  fn into_value(self) -> Value {
+    self
+  }
+}
+
+impl FromValue for Value {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::Any
+  }
+
+  fn from_value(value: Value) -> Result<Self, String> {
+    Ok(value)
+  }
+}
+
+/// Returns 0 to the script, like `print` does.
+impl IntoValue for () {
+  fn type_decl() -> TypeDecl {
+    TypeDecl::Any
+  }
+
+  fn into_value(self) -> Value {
+    Value::I64(0)
+  }
+}
+
+/// What a native returns to the script: either a value, or a
+/// `Result` whose error is thrown as an error value, which the script
+/// can catch and which has a backtrace if it does not.
+pub trait IntoNativeResult {
+  /// The type of the value in the script
+  fn type_decl() -> TypeDecl;
+  fn into_result(self) -> Result<Value, String>;
+}
+
+macro_rules! impl_native_result {
+  ($($ty:ty),*) => {
+    $(impl IntoNativeResult for $ty {
+      fn type_decl() -> TypeDecl {
+        <$ty as IntoValue>::type_decl()
+      }
+
+      fn into_result(self) -> Result<Value, String> {
+        Ok(self.into_value())
+      }
+    })*
+  };
+}
+
+impl_native_result!(f64, i64, String, Value, ());
+
+impl<T: IntoValue> IntoNativeResult for Option<T> {
+  fn type_decl() -> TypeDecl {
+    <Self as IntoValue>::type_decl()
+  }
+
+  fn into_result(self) -> Result<Value, String> {
+    Ok(self.into_value())
+  }
+}
+
+impl<T: IntoValue, E: Display> IntoNativeResult
+  for Result<T, E>
+{
+  fn type_decl() -> TypeDecl {
+    T::type_decl()
+  }
+
+  fn into_result(self) -> Result<Value, String> {
+    self.map(T::into_value).map_err(|e| e.to_string())
+  }
+}
+
+/// A Rust closure that can be registered as a native function of an
+/// engine with the host context `H`. `Args` is the tuple of its
+/// argument types, or `WithHost` of them if it takes `&mut H` first,
+/// which only serves to tell apart the implementations.
+pub trait IntoNativeFn<H, Args>: 'static {
+  fn arg_types() -> Vec<TypeDecl>;
+  fn ret_type() -> TypeDecl;
+  fn call(
+    &self,
+    host: &mut H,
+    args: &[Value],
+  ) -> Result<Value, String>;
+}
+
+/// Marks the natives taking the host context as the first argument.
+pub struct WithHost<Args>(PhantomData<Args>);
+
+/// Arguments of a call from the host, as a tuple of values.
+pub trait IntoArgs {
+  fn into_args(self) -> Vec<Value>;
+}
+
+/// Names of the arguments shown in the signatures of natives.
+const ARG_NAMES: [&str; 6] =
+  ["arg0", "arg1", "arg2", "arg3", "arg4", "arg5"];
+
+macro_rules! impl_native_fn {
+  ($($arg:ident),*) => {
+    impl<F, H, R, $($arg),*> IntoNativeFn<H, ($($arg,)*)> for F
+    where
+      F: Fn($($arg),*) -> R + 'static,
+      R: IntoNativeResult,
+      $($arg: FromValue,)*
+    {
+      fn arg_types() -> Vec<TypeDecl> {
+        vec![$($arg::type_decl()),*]
+      }
+
+      fn ret_type() -> TypeDecl {
+        R::type_decl()
+      }
+
+      #[allow(non_snake_case)]
+      fn call(
+        &self,
+        _host: &mut H,
+        args: &[Value],
+      ) -> Result<Value, String> {
+        let [$($arg),*] = args else {
+          return Err(arity_error::<Self, H, ($($arg,)*)>(args));
+        };
+        self($($arg::from_value($arg.clone())?),*).into_result()
+      }
+    }
+
+    impl<F, H, R, $($arg),*> IntoNativeFn<H, WithHost<($($arg,)*)>>
+      for F
+    where
+      F: Fn(&mut H, $($arg),*) -> R + 'static,
+      R: IntoNativeResult,
+      $($arg: FromValue,)*
+    {
+      fn arg_types() -> Vec<TypeDecl> {
+        vec![$($arg::type_decl()),*]
+      }
+
+      fn ret_type() -> TypeDecl {
+        R::type_decl()
+      }
+
+      #[allow(non_snake_case)]
+      fn call(
+        &self,
+        host: &mut H,
+        args: &[Value],
+      ) -> Result<Value, String> {
+        let [$($arg),*] = args else {
+          return Err(arity_error::<Self, H, WithHost<($($arg,)*)>>(
+            args,
+          ));
+        };
+        self(host, $($arg::from_value($arg.clone())?),*)
+          .into_result()
+      }
+    }
+
+    impl<$($arg: IntoValue),*> IntoArgs for ($($arg,)*) {
+      #[allow(non_snake_case)]
+      fn into_args(self) -> Vec<Value> {
+        let ($($arg,)*) = self;
+        vec![$($arg.into_value()),*]
+      }
+    }
+  };
+}
+
+fn arity_error<F: IntoNativeFn<H, Args>, H, Args>(
+  args: &[Value],
+) -> String {
+  format!(
+    "expected {} argument(s), but {} were given",
+    F::arg_types().len(),
+    args.len()
+  )
+}
+
+impl_native_fn!();
+impl_native_fn!(A0);
+impl_native_fn!(A0, A1);
+impl_native_fn!(A0, A1, A2);
+impl_native_fn!(A0, A1, A2, A3);
+impl_native_fn!(A0, A1, A2, A3, A4);
+impl_native_fn!(A0, A1, A2, A3, A4, A5);
+
+/// An error thrown by the script or a native that was not caught,
+/// with the functions being called when it was thrown.
+#[derive(Debug)]
+pub struct RuntimeError {
+  pub message: String,
+  /// Innermost first
+  pub backtrace: Vec<TraceFrame>,
+}
+
+impl Display for RuntimeError {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "{}", self.message)?;
+    for frame in &self.backtrace {
+      write!(f, "\n    at {frame}")?;
+    }
+    Ok(())
+  }
+}
+
+impl Error for RuntimeError {}
+
+/// A compiled script, which can be run many times.
+#[derive(Clone)]
+pub struct Program {
+  bytecode: Rc<ByteCode>,
+}
+
+impl Program {
+  pub fn bytecode(&self) -> &Rc<ByteCode> {
+    &self.bytecode
+  }
+}
+
+type NativeFnFactory = Box<dyn Fn() -> NativeFn<'static>>;
+
+/// Compiles and runs scripts with the natives registered to it,
+/// which can mutate the host context `H` given to the engine.
+pub struct Engine<H: 'static = ()> {
+  /// The type checker and each compiled program take their own
+  /// copies of the natives, so they are made on demand like
+  /// `Args::additional_funcs`.
+  funcs: HashMap<String, NativeFnFactory>,
+  strict: bool,
+  /// The host context, which is always an `H`
+  host: UserData,
+  _host: PhantomData<H>,
+}
+
+impl Engine {
+  pub fn new() -> Self {
+    Self::with_host(())
+  }
+}
+
+impl<H: Default + 'static> Default for Engine<H> {
+  fn default() -> Self {
+    Self::with_host(H::default())
+  }
+}
+
+impl<H: 'static> Engine<H> {
+  /// Create an engine whose natives can access `host`.
+  pub fn with_host(host: H) -> Self {
+    Self {
+      funcs: HashMap::new(),
+      strict: false,
+      host: Rc::new(RefCell::new(Box::new(host))),
+      _host: PhantomData,
+    }
+  }
+
+  /// The host context, as left by the natives run so far.
+  pub fn host(&self) -> Ref<'_, H> {
+    Ref::map(self.host.borrow(), |host| {
+      host.downcast_ref().expect("the host context is an H")
+    })
+  }
+
+  pub fn host_mut(&self) -> RefMut<'_, H> {
+    RefMut::map(self.host.borrow_mut(), |host| {
+      host.downcast_mut().expect("the host context is an H")
+    })
+  }
+
+  /// Type check in strict mode, see `TypeCheckContext::set_strict`.
+  pub fn set_strict(&mut self, strict: bool) {
+    self.strict = strict;
+  }
+
+  /// Register a Rust closure as a native function. The types of its
+  /// arguments and return value become its signature in the type
+  /// check, and the arguments are converted from the values passed
+  /// by the script. A closure taking `&mut H` as the first argument
+  /// is given the host context, and one returning a `Result` throws
+  /// its error in the script. A later registration replaces an
+  /// earlier one of the same name, as well as a standard function.
+  pub fn register_fn<Args, F: IntoNativeFn<H, Args>>(
+    &mut self,
+    name: &str,
+    f: F,
+  ) {
+    let f = Rc::new(f);
+    self.register_native(name, move || {
+      let f = f.clone();
+      let args =
+        ARG_NAMES.into_iter().zip(F::arg_types()).collect();
+      NativeFn::new(
+        args,
+        F::ret_type(),
+        Box::new(move |host, args| {
+          let host = host.downcast_mut().ok_or_else(|| {
+            "The host context has an unexpected type"
+              .to_string()
+          })?;
+          f.call(host, args)
+        }),
+      )
+    });
+  }
+
+  /// Register a native function built by hand, for the ones that
+  /// `register_fn` cannot describe, like variadic ones.
+  pub fn register_native(
+    &mut self,
+    name: &str,
+    f: impl Fn() -> NativeFn<'static> + 'static,
+  ) {
+    self.funcs.insert(name.to_string(), Box::new(f));
+  }
+
+  /// Parse, type check and compile the source. The errors are
+  /// returned as `Aborted` with their diagnostics.
+  pub fn compile(
+    &self,
+    source: &str,
+  ) -> Result<Program, Box<dyn Error>> {
+    let stmts = parse_program("<engine>", source)?;
+
+    let mut tc_ctx = TypeCheckContext::new();
+    for (fname, f) in &self.funcs {
+      tc_ctx.add_fn(fname.clone(), f());
+    }
+    tc_ctx.set_strict(self.strict);
+    type_check(&stmts, &mut tc_ctx).map_err(|errors| {
+      Aborted(errors.iter().map(Diagnostic::from).collect())
+    })?;
+
+    let mut compiler = Compiler::new();
+    compiler.compile(&stmts)?;
+    let mut bytecode = compiler.into_bytecode();
+    for (fname, f) in &self.funcs {
+      bytecode.add_fn(fname.clone(), f());
+    }
+    Ok(Program {
+      bytecode: Rc::new(bytecode),
+    })
+  }
+
+  /// Run the top level statements of the program and return the
+  /// value of the last one.
+  pub fn run<R: FromValue>(
+    &self,
+    program: &Program,
+  ) -> Result<R, Box<dyn Error>> {
+    self.call(program, "main", ())
+  }
+
+  /// Compile and run the source.
+  pub fn eval<R: FromValue>(
+    &self,
+    source: &str,
+  ) -> Result<R, Box<dyn Error>> {
+    self.run(&self.compile(source)?)
+  }
+
+  /// Call a function of the program with a tuple of arguments, like
+  /// `engine.call::<f64>(&program, "f", (1., 2.))`. An uncaught
+  /// error is returned as `RuntimeError`.
+  pub fn call<R: FromValue>(
+    &self,
+    program: &Program,
+    fn_name: &str,
+    args: impl IntoArgs,
+  ) -> Result<R, Box<dyn Error>> {
+    let mut vm = Vm::with_user_data(
+      program.bytecode.clone(),
+      self.host.clone(),
+    );
+    let res = match vm.run_fn(fn_name, &args.into_args()) {
+      Ok(res) => res,
+      Err(e) => {
+        return Err(match e.downcast::<UncaughtError>() {
+          Ok(e) => Box::new(RuntimeError {
+            message: e.0,
+            backtrace: vm.error_backtrace(),
+          }),
+          Err(e) => e,
+        })
+      }
+    };
+    Ok(R::from_value(res)?)
+  }
+}
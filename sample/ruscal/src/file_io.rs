@@ -5,27 +5,63 @@ use std::{
 
 use crate::{
   ast::{Span, Statements},
+  ast_export::write_ast,
   bytecode::ByteCode,
   compiler::Compiler,
+  diagnostics::{use_color, Aborted, Diagnostic, Level},
+  formatter::format_source,
+  lint::lint,
   parser::statements_finish,
   type_checker::{type_check, TypeCheckContext},
   Args, RunMode,
 };
 
 pub fn parse_program<'src>(
-  source_file: &str,
+  _source_file: &str,
   source: &'src str,
 ) -> Result<Statements<'src>, Box<dyn Error>> {
-  statements_finish(Span::new(source)).map_err(|e| {
-    format!(
-      "{}:{}:{}: {}",
-      source_file,
-      e.input.location_line(),
-      e.input.get_utf8_column(),
-      e
-    )
-    .into()
-  })
+  statements_finish(Span::new(source))
+    .map_err(|errors| Aborted(errors).into())
+}
+
+/// Print the diagnostics to stderr with source snippets.
+fn print_diagnostics<'a>(
+  source_file: &str,
+  source: &str,
+  diags: impl IntoIterator<Item = &'a Diagnostic>,
+) {
+  let color = use_color();
+  for diag in diags {
+    eprintln!("{}", diag.render(source_file, source, color));
+  }
+}
+
+/// Print the errors and return an error to abort the compilation.
+fn abort(
+  source_file: &str,
+  source: &str,
+  errors: Vec<Diagnostic>,
+) -> Box<dyn Error> {
+  print_diagnostics(source_file, source, &errors);
+  Box::new(Aborted(errors))
+}
+
+/// Print the error with the source if it has diagnostics.
+pub(crate) fn abort_on(
+  source_file: &str,
+  source: &str,
+  e: Box<dyn Error>,
+) -> Box<dyn Error> {
+  let e = match e.downcast::<Aborted>() {
+    Ok(aborted) => {
+      return abort(source_file, source, aborted.0)
+    }
+    Err(e) => e,
+  };
+  match e.downcast::<Diagnostic>() {
+    Ok(diag) => abort(source_file, source, vec![*diag]),
+    Err(e) => e,
+  }
 }
 
 pub fn write_program(
@@ -36,10 +72,11 @@ pub fn write_program(
   args: &Args,
 ) -> Result<(), Box<dyn std::error::Error>> {
   let mut compiler = Compiler::new();
-  let stmts = parse_program(source_file, source)?;
+  let stmts = parse_program(source_file, source)
+    .map_err(|e| abort_on(source_file, source, e))?;
 
   if args.show_ast {
-    println!("AST: {stmts:#?}");
+    write_ast(&stmts, args.ast_format, &mut std::io::stdout())?;
   }
 
   let mut tc_ctx = TypeCheckContext::new();
@@ -47,27 +84,37 @@ pub fn write_program(
     tc_ctx.add_fn(fname.clone(), f());
   }
 
+  if let Some(max_errors) = args.max_errors {
+    tc_ctx.set_max_errors(max_errors);
+  }
+  tc_ctx.set_strict(args.strict);
+
   match type_check(&stmts, &mut tc_ctx) {
     Ok(_) => println!("Typecheck Ok"),
-    Err(e) => {
-      return Err(
-        format!(
-          "{}:{}:{}: {}",
-          source_file,
-          e.span.location_line(),
-          e.span.get_utf8_column(),
-          e
-        )
-        .into(),
-      )
+    Err(errors) => {
+      let errors =
+        errors.iter().map(Diagnostic::from).collect();
+      return Err(abort(source_file, source, errors));
     }
   }
 
+  let (denied, warnings): (Vec<_>, Vec<_>) =
+    lint(&stmts, &args.lints)
+      .iter()
+      .map(Diagnostic::from)
+      .partition(|diag| diag.level == Level::Error);
+  print_diagnostics(source_file, source, &warnings);
+  if !denied.is_empty() {
+    return Err(abort(source_file, source, denied));
+  }
+
   if matches!(args.run_mode, RunMode::TypeCheck) {
     return Ok(());
   }
 
-  compiler.compile(&stmts)?;
+  compiler
+    .compile(&stmts)
+    .map_err(|e| abort_on(source_file, source, e))?;
 
   if args.disasm {
     compiler.disasm(&mut std::io::stdout())?;
@@ -88,8 +135,7 @@ pub fn compile(
   out_file: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
   let src = args.source.as_ref().ok_or_else(|| {
-    Box::new(std::io::Error::new(
-      std::io::ErrorKind::Other,
+    Box::new(std::io::Error::other(
       "Please specify source file to compile after -c"
         .to_string(),
     ))
@@ -98,6 +144,45 @@ pub fn compile(
   write_program(src, &source, writer, out_file, args)
 }
 
+/// Format the source file in place, or with `args.check`, only see
+/// if it would change. Returns whether it was formatted already.
+pub fn format_file(
+  args: &Args,
+) -> Result<bool, Box<dyn Error>> {
+  let src = args.source.as_ref().ok_or_else(|| {
+    Box::new(std::io::Error::other(
+      "Please specify source file to format after -f"
+        .to_string(),
+    ))
+  })?;
+  let source = std::fs::read_to_string(src)?;
+  let formatted = format_source(&source, &args.format)
+    .map_err(|errors| abort(src, &source, errors))?;
+  if formatted == source {
+    return Ok(true);
+  }
+  if !args.check {
+    std::fs::write(src, formatted)?;
+  }
+  Ok(false)
+}
+
+/// Parse the source file and write its AST to stdout, without type
+/// checking or compiling it.
+pub fn show_ast(args: &Args) -> Result<(), Box<dyn Error>> {
+  let src = args.source.as_ref().ok_or_else(|| {
+    Box::new(std::io::Error::other(
+      "Please specify source file to parse after -a"
+        .to_string(),
+    ))
+  })?;
+  let source = std::fs::read_to_string(src)?;
+  let stmts = parse_program(src, &source)
+    .map_err(|e| abort_on(src, &source, e))?;
+  write_ast(&stmts, args.ast_format, &mut std::io::stdout())?;
+  Ok(())
+}
+
 pub fn read_program(
   reader: &mut impl Read,
 ) -> std::io::Result<ByteCode> {
@@ -0,0 +1,228 @@
+//! Formatting of values by format specifications, shared by the
+//! `format` native function and interpolated string literals.
+//!
+//! A format string contains `{}` or `{:spec}` placeholders, which are
+//! replaced by the arguments in order. `{{` and `}}` are literal
+//! braces. The spec is a subset of Rust's:
+//!
+//! ```text
+//! [align][0][width][.precision][type]
+//! ```
+//!
+//! where `align` is one of `<`, `^` or `>` and `type` is `x` or `X`
+//! for hexadecimal integers.
+
+use crate::value::Value;
+
+/// Largest width or precision, as in Rust.
+const MAX_WIDTH: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+  Left,
+  Center,
+  Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FormatSpec {
+  pub align: Option<Align>,
+  pub zero: bool,
+  pub width: Option<usize>,
+  pub precision: Option<usize>,
+  /// `Some(false)` for lower case hex, `Some(true)` for upper case.
+  pub hex: Option<bool>,
+}
+
+impl FormatSpec {
+  pub fn parse(spec: &str) -> Result<Self, String> {
+    let mut ret = Self::default();
+    let mut chars = spec.chars().peekable();
+    ret.align = match chars.peek() {
+      Some('<') => Some(Align::Left),
+      Some('^') => Some(Align::Center),
+      Some('>') => Some(Align::Right),
+      _ => None,
+    };
+    if ret.align.is_some() {
+      chars.next();
+    }
+    if chars.peek() == Some(&'0') {
+      ret.zero = true;
+      chars.next();
+    }
+    ret.width = parse_digits(&mut chars);
+    if chars.peek() == Some(&'.') {
+      chars.next();
+      ret.precision =
+        Some(parse_digits(&mut chars).ok_or_else(|| {
+          format!("Missing precision in format spec {spec:?}")
+        })?);
+    }
+    if ret
+      .width
+      .max(ret.precision)
+      .is_some_and(|n| n > MAX_WIDTH)
+    {
+      return Err(format!(
+        "Width or precision over {MAX_WIDTH} in format spec \
+        {spec:?}"
+      ));
+    }
+    ret.hex = match chars.next() {
+      Some('x') => Some(false),
+      Some('X') => Some(true),
+      None => None,
+      Some(c) => {
+        return Err(format!(
+          "Unknown format type {c:?} in format spec {spec:?}"
+        ))
+      }
+    };
+    if let Some(c) = chars.next() {
+      return Err(format!(
+        "Unexpected {c:?} in format spec {spec:?}"
+      ));
+    }
+    Ok(ret)
+  }
+
+  pub fn format(
+    &self,
+    value: &Value,
+  ) -> Result<String, String> {
+    let numeric =
+      matches!(value, Value::F64(_) | Value::I64(_));
+    let body = if let Some(upper) = self.hex {
+      let i = match value {
+        Value::I64(i) => *i,
+        Value::F64(f) if f.fract() == 0. => *f as i64,
+        _ => {
+          return Err(format!(
+            "Hex format requires an integer, got {value}"
+          ))
+        }
+      };
+      if upper {
+        format!("{i:X}")
+      } else {
+        format!("{i:x}")
+      }
+    } else {
+      match (value, self.precision) {
+        (Value::F64(f), Some(precision)) => {
+          format!("{f:.precision$}")
+        }
+        (Value::Str(s), Some(precision)) => {
+          s.chars().take(precision).collect()
+        }
+        _ => value.to_string(),
+      }
+    };
+
+    let Some(width) = self.width else {
+      return Ok(body);
+    };
+    let len = body.chars().count();
+    if width <= len {
+      return Ok(body);
+    }
+    let pad = width - len;
+    if self.zero && numeric {
+      let (sign, digits) = match body.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", &body as &str),
+      };
+      return Ok(format!("{sign}{}{digits}", "0".repeat(pad)));
+    }
+    let align = self.align.unwrap_or(if numeric {
+      Align::Right
+    } else {
+      Align::Left
+    });
+    let (left, right) = match align {
+      Align::Left => (0, pad),
+      Align::Center => (pad / 2, pad - pad / 2),
+      Align::Right => (pad, 0),
+    };
+    Ok(format!(
+      "{}{body}{}",
+      " ".repeat(left),
+      " ".repeat(right)
+    ))
+  }
+}
+
+fn parse_digits(
+  chars: &mut std::iter::Peekable<std::str::Chars>,
+) -> Option<usize> {
+  let mut ret = None;
+  while let Some(digit) =
+    chars.peek().and_then(|c| c.to_digit(10))
+  {
+    ret = Some(
+      ret
+        .unwrap_or(0usize)
+        .saturating_mul(10)
+        .saturating_add(digit as usize),
+    );
+    chars.next();
+  }
+  ret
+}
+
+/// Format the arguments by the format string.
+pub fn format_values(
+  fmt: &str,
+  args: &[Value],
+) -> Result<String, String> {
+  let mut ret = String::new();
+  let mut args = args.iter();
+  let mut chars = fmt.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '{' if chars.peek() == Some(&'{') => {
+        chars.next();
+        ret.push('{');
+      }
+      '{' => {
+        let mut spec = String::new();
+        loop {
+          match chars.next() {
+            Some('}') => break,
+            Some(c) => spec.push(c),
+            None => {
+              return Err(
+                "Unclosed '{' in format string".to_string(),
+              )
+            }
+          }
+        }
+        let spec = match spec.strip_prefix(':') {
+          Some(spec) => FormatSpec::parse(spec)?,
+          None if spec.is_empty() => FormatSpec::default(),
+          None => {
+            return Err(format!(
+              "Invalid placeholder {{{spec}}} in format string"
+            ))
+          }
+        };
+        let arg = args.next().ok_or_else(|| {
+          "Not enough arguments for the format string"
+            .to_string()
+        })?;
+        ret.push_str(&spec.format(arg)?);
+      }
+      '}' => {
+        if chars.next() != Some('}') {
+          return Err(
+            "Unmatched '}' in format string".to_string(),
+          );
+        }
+        ret.push('}');
+      }
+      _ => ret.push(c),
+    }
+  }
+  Ok(ret)
+}
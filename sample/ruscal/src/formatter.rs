@@ -0,0 +1,752 @@
+//! Formatter printing the AST back to a source in the canonical
+//! style.
+//!
+//! The AST does not keep comments and blank lines, so they are
+//! taken from the source. A comment on its own line is written
+//! before the statement that follows it and a comment after code
+//! is appended to the last line written before it. Comments inside
+//! an argument or parameter list put an argument per line and
+//! follow the argument before them. Other comments inside an expression are
+//! moved before its statement, unless the statement has a block
+//! that they can go into. Blank lines
+//! between statements are kept, but not more than one in a row.
+
+use crate::{
+  ast::{
+    ExprEnum, Expression, Span, Statement, Statements, TypeDecl,
+  },
+  diagnostics::Diagnostic,
+  parser::{statements_finish, trivia_len},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct FormatConfig {
+  /// Number of spaces per indentation level
+  pub indent: usize,
+  /// Width of the lines, over which argument lists are wrapped
+  pub max_width: usize,
+}
+
+impl FormatConfig {
+  pub fn new() -> Self {
+    Self {
+      indent: 4,
+      max_width: 80,
+    }
+  }
+}
+
+impl Default for FormatConfig {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Format the source, or return the syntax errors if it cannot be
+/// parsed.
+pub fn format_source(
+  src: &str,
+  config: &FormatConfig,
+) -> Result<String, Vec<Diagnostic>> {
+  let stmts = statements_finish(Span::new(src))?;
+  let mut printer = Printer::new(src, *config, comments(src));
+  printer.statements(&stmts, 0);
+  printer.comments_before(src.len(), false);
+  let mut out = printer.out;
+  if !out.is_empty() {
+    out.push('\n');
+  }
+  Ok(out)
+}
+
+/// A `//` comment in the source.
+struct Comment {
+  start: usize,
+  end: usize,
+  /// Whether there is code before the comment on its line
+  trailing: bool,
+}
+
+/// Find the comments in the source, skipping string literals.
+fn comments(src: &str) -> Vec<Comment> {
+  let mut comments = vec![];
+  let mut in_str = false;
+  let mut line_start = 0;
+  let mut skip_to = 0;
+  for (pos, c) in src.char_indices() {
+    if pos < skip_to {
+      continue;
+    }
+    match c {
+      '\n' => line_start = pos + 1,
+      '"' => in_str = !in_str,
+      '/' if !in_str && src[pos..].starts_with("//") => {
+        let end = src[pos..]
+          .find('\n')
+          .map_or(src.len(), |len| pos + len);
+        comments.push(Comment {
+          start: pos,
+          end,
+          trailing: !src[line_start..pos].trim().is_empty(),
+        });
+        skip_to = end;
+      }
+      _ => (),
+    }
+  }
+  comments
+}
+
+fn end_of(span: Span) -> usize {
+  span.location_offset() + span.len()
+}
+
+pub(crate) fn type_name(td: &TypeDecl) -> String {
+  match td {
+    TypeDecl::Any => "any".to_string(),
+    TypeDecl::F64 => "f64".to_string(),
+    TypeDecl::I64 => "i64".to_string(),
+    TypeDecl::Str => "str".to_string(),
+    TypeDecl::Coro(td) if **td == TypeDecl::Any => {
+      "cofn".to_string()
+    }
+    TypeDecl::Coro(td) => format!("cofn<{}>", type_name(td)),
+    TypeDecl::Error => "err".to_string(),
+    TypeDecl::Opt(td) => format!("{}?", type_name(td)),
+  }
+}
+
+/// Binding strength of the expression, where an operand weaker
+/// than its operator needs parentheses.
+fn precedence(ex: &Expression) -> u8 {
+  use ExprEnum::*;
+  match ex.expr {
+    If(..) | IfLet(..) | Try(..) | Await(_) => 0,
+    Coalesce(..) => 1,
+    Gt(..) | Lt(..) => 2,
+    Add(..) | Sub(..) => 3,
+    Mul(..) | Div(..) => 4,
+    _ => 5,
+  }
+}
+
+/// Whether the expression has a block, so it spans multiple lines.
+fn has_block(ex: &Expression) -> bool {
+  use ExprEnum::*;
+  match &ex.expr {
+    If(..) | IfLet(..) | Try(..) => true,
+    Ident(_) | NumLiteral(_) | StrLiteral(_)
+    | Interpolated(_) | NoneLiteral => false,
+    SomeValue(ex) | Await(ex) => has_block(ex),
+    FnInvoke(_, args) => args.iter().any(has_block),
+    Add(lhs, rhs)
+    | Sub(lhs, rhs)
+    | Mul(lhs, rhs)
+    | Div(lhs, rhs)
+    | Gt(lhs, rhs)
+    | Lt(lhs, rhs)
+    | Coalesce(lhs, rhs) => has_block(lhs) || has_block(rhs),
+  }
+}
+
+/// Calls in the expression, which has no block.
+fn calls<'a, 'src>(
+  ex: &'a Expression<'src>,
+  calls: &mut Vec<&'a Expression<'src>>,
+) {
+  use ExprEnum::*;
+  match &ex.expr {
+    FnInvoke(_, args) => {
+      calls.push(ex);
+      for arg in args {
+        self::calls(arg, calls);
+      }
+    }
+    SomeValue(ex) | Await(ex) => self::calls(ex, calls),
+    Add(lhs, rhs)
+    | Sub(lhs, rhs)
+    | Mul(lhs, rhs)
+    | Div(lhs, rhs)
+    | Gt(lhs, rhs)
+    | Lt(lhs, rhs)
+    | Coalesce(lhs, rhs) => {
+      self::calls(lhs, calls);
+      self::calls(rhs, calls);
+    }
+    _ => (),
+  }
+}
+
+fn stmt_has_block(stmt: &Statement) -> bool {
+  match stmt {
+    Statement::For { .. } | Statement::FnDef { .. } => true,
+    Statement::Break(_) | Statement::Continue(_) => false,
+    Statement::VarDef { ex, .. }
+    | Statement::VarAssign { ex, .. }
+    | Statement::Expression(ex)
+    | Statement::Return(ex)
+    | Statement::Yield(ex)
+    | Statement::Throw(ex) => has_block(ex),
+  }
+}
+
+struct Printer<'src> {
+  src: &'src str,
+  config: FormatConfig,
+  out: String,
+  /// Current indentation level
+  level: usize,
+  /// Comments not written yet
+  comments: Vec<Comment>,
+  /// End of the last statement or comment written in the source,
+  /// to find blank lines after it.
+  last_end: usize,
+  /// Whether nothing is written in the block yet
+  block_start: bool,
+}
+
+impl<'src> Printer<'src> {
+  fn new(
+    src: &'src str,
+    config: FormatConfig,
+    comments: Vec<Comment>,
+  ) -> Self {
+    Self {
+      src,
+      config,
+      out: String::new(),
+      level: 0,
+      comments,
+      last_end: 0,
+      block_start: true,
+    }
+  }
+
+  fn push(&mut self, s: &str) {
+    self.out.push_str(s);
+  }
+
+  fn newline(&mut self) {
+    if !self.out.is_empty() {
+      self.out.push('\n');
+    }
+    let indent = self.level * self.config.indent;
+    self.out.extend(std::iter::repeat_n(' ', indent));
+    self.block_start = false;
+  }
+
+  /// Column of the end of the output.
+  fn column(&self) -> usize {
+    let line_start =
+      self.out.rfind('\n').map_or(0, |pos| pos + 1);
+    self.out[line_start..].chars().count()
+  }
+
+  fn fits(&self, s: &str) -> bool {
+    self.column() + s.chars().count() <= self.config.max_width
+  }
+
+  /// Write a blank line if there is one in the source between the
+  /// last item and `pos`, unless at the start of a block.
+  fn blank_line(&mut self, pos: usize) {
+    if self.block_start {
+      return;
+    }
+    let gap = self.src.get(self.last_end..pos).unwrap_or("");
+    if gap.matches('\n').count() > 1 {
+      self.out.push('\n');
+    }
+  }
+
+  /// Write the comments before `pos` in the source. With `hoist`,
+  /// the comments after code are written on their own lines too.
+  fn comments_before(&mut self, pos: usize, hoist: bool) {
+    while self.comments.first().is_some_and(|c| c.start < pos) {
+      let comment = self.comments.remove(0);
+      self.comment(comment, hoist);
+    }
+  }
+
+  fn comment(&mut self, comment: Comment, hoist: bool) {
+    let (start, end) = (comment.start, comment.end);
+    let text = self.src[start..end].trim_end();
+    let last_line =
+      self.out.rsplit('\n').next().unwrap_or("").trim_start();
+    if comment.trailing
+      && !hoist
+      && !last_line.is_empty()
+      && !last_line.starts_with("//")
+    {
+      self.out.push(' ');
+      self.push(text);
+    } else {
+      if !hoist {
+        self.blank_line(start);
+      }
+      self.newline();
+      self.push(text);
+    }
+    self.last_end = end;
+  }
+
+  /// Whether a comment not written yet is between `start` and
+  /// `end` in the source.
+  fn has_comment(&self, start: usize, end: usize) -> bool {
+    self.comments.iter().any(|comment| {
+      start < comment.start && comment.start < end
+    })
+  }
+
+  /// Write the comments inside a statement without a block before
+  /// it, except the ones in argument lists, which are written with
+  /// the arguments.
+  fn hoist_comments(&mut self, stmt: &Statement, end: usize) {
+    let mut call_exprs = vec![];
+    match stmt {
+      Statement::VarDef { ex, .. }
+      | Statement::VarAssign { ex, .. }
+      | Statement::Expression(ex)
+      | Statement::Return(ex)
+      | Statement::Yield(ex)
+      | Statement::Throw(ex) => calls(ex, &mut call_exprs),
+      _ => (),
+    }
+    let arg_lists: Vec<_> = call_exprs
+      .into_iter()
+      .map(|call| self.arg_list(call))
+      .collect();
+    let mut i = 0;
+    while let Some(comment) = self.comments.get(i) {
+      if end <= comment.start {
+        break;
+      }
+      let in_args = arg_lists.iter().any(|(open, close)| {
+        *open < comment.start && comment.start < *close
+      });
+      if in_args {
+        i += 1;
+      } else {
+        let comment = self.comments.remove(i);
+        self.comment(comment, true);
+      }
+    }
+  }
+
+  /// Positions of the parentheses of a call in the source.
+  fn arg_list(&self, call: &Expression) -> (usize, usize) {
+    let open = self.find(call.span.location_offset(), '(');
+    (open, end_of(call.span) - 1)
+  }
+
+  /// Skip whitespace and comments from `pos`.
+  fn skip_trivia(&self, pos: usize) -> usize {
+    pos + trivia_len(&self.src[pos..])
+  }
+
+  /// Position of the next `c` from `pos`, outside of comments and
+  /// string literals.
+  fn find(&self, mut pos: usize, c: char) -> usize {
+    let mut in_str = false;
+    loop {
+      if !in_str {
+        pos = self.skip_trivia(pos);
+      }
+      match self.src[pos..].chars().next() {
+        Some(found) if found == c && !in_str => return pos,
+        Some('"') => in_str = !in_str,
+        Some(_) => (),
+        None => return pos,
+      }
+      pos += self.src[pos..]
+        .chars()
+        .next()
+        .map_or(1, char::len_utf8);
+    }
+  }
+
+  /// End of a statement whose code ends at `end`, after the closing
+  /// parentheses and the `;`, and whether the `;` is there.
+  fn terminator(&self, mut end: usize) -> (usize, bool) {
+    loop {
+      let next = self.skip_trivia(end);
+      match self.src[next..].chars().next() {
+        Some(')') => end = next + 1,
+        Some(';') => return (next + 1, true),
+        _ => return (end, false),
+      }
+    }
+  }
+
+  fn stmt_end(&self, stmt: &Statement) -> (usize, bool) {
+    match stmt {
+      Statement::VarDef { span, .. }
+      | Statement::VarAssign { span, .. }
+      | Statement::For { span, .. }
+      | Statement::FnDef { span, .. } => (end_of(*span), false),
+      Statement::Expression(ex)
+      | Statement::Return(ex)
+      | Statement::Yield(ex)
+      | Statement::Throw(ex) => {
+        self.terminator(end_of(ex.span))
+      }
+      Statement::Break(span) | Statement::Continue(span) => {
+        self.terminator(end_of(*span))
+      }
+    }
+  }
+
+  /// Write the statements starting from `pos` in the source and
+  /// return the end of the last one.
+  fn statements(
+    &mut self,
+    stmts: &Statements,
+    mut pos: usize,
+  ) -> usize {
+    for stmt in stmts {
+      let start = self.skip_trivia(pos);
+      let (end, semicolon) = self.stmt_end(stmt);
+      self.comments_before(start, false);
+      self.blank_line(start);
+      if !stmt_has_block(stmt) {
+        self.hoist_comments(stmt, end);
+      }
+      self.newline();
+      self.statement(stmt);
+      if semicolon {
+        self.push(";");
+      }
+      pos = end;
+      self.last_end = end;
+    }
+    pos
+  }
+
+  fn statement(&mut self, stmt: &Statement) {
+    match stmt {
+      Statement::Expression(ex) => self.expr(ex, 0),
+      Statement::VarDef { name, td, ex, .. } => {
+        self.push("var ");
+        self.push(name);
+        if let Some(td) = td {
+          self.push(": ");
+          self.push(&type_name(td));
+        }
+        self.push(" = ");
+        self.expr(ex, 0);
+        self.push(";");
+      }
+      Statement::VarAssign { name, ex, .. } => {
+        self.push(name);
+        self.push(" = ");
+        self.expr(ex, 0);
+        self.push(";");
+      }
+      Statement::For {
+        loop_var,
+        start,
+        end,
+        stmts,
+        ..
+      } => {
+        self.push("for ");
+        self.push(loop_var);
+        self.push(" in ");
+        self.expr(start, 0);
+        self.push(" to ");
+        self.expr(end, 0);
+        self.push(" ");
+        let open = self.find(end_of(end.span), '{');
+        self.block(stmts, open);
+      }
+      Statement::Break(_) => self.push("break"),
+      Statement::Continue(_) => self.push("continue"),
+      Statement::FnDef {
+        name,
+        stmts,
+        test: true,
+        ..
+      } => {
+        self.push(&format!("test fn {name}() "));
+        let open = self.find(end_of(*name), '{');
+        self.block(stmts, open);
+      }
+      Statement::FnDef {
+        name,
+        args,
+        ret_type,
+        stmts,
+        cofn,
+        ..
+      } => {
+        self.fn_header(*name, args, ret_type, *cofn);
+        let open = self.find(end_of(*name), '{');
+        self.block(stmts, open);
+      }
+      Statement::Return(ex) => {
+        self.push("return ");
+        self.expr(ex, 0);
+      }
+      Statement::Yield(ex) => {
+        self.push("yield ");
+        self.expr(ex, 0);
+      }
+      Statement::Throw(ex) => {
+        self.push("throw ");
+        self.expr(ex, 0);
+      }
+    }
+  }
+
+  /// Write the function signature up to the body, with an argument
+  /// per line if it does not fit in a line or has comments, which
+  /// follow the argument before them like in a call.
+  fn fn_header(
+    &mut self,
+    name: Span,
+    args: &[(Span, TypeDecl)],
+    ret_type: &TypeDecl,
+    cofn: bool,
+  ) {
+    let open = self.find(end_of(name), '(');
+    let close = self.find(
+      args.last().map_or(open + 1, |(arg, _)| end_of(*arg)),
+      ')',
+    );
+    let comments = self.has_comment(open, close);
+    let names: Vec<_> =
+      args.iter().map(|(arg, _)| *arg).collect();
+    let args: Vec<_> = args
+      .iter()
+      .map(|(name, td)| format!("{name}: {}", type_name(td)))
+      .collect();
+    let kw = if cofn { "cofn" } else { "fn" };
+    let ret_type = type_name(ret_type);
+    let flat = format!(
+      "{kw} {name}({}) -> {ret_type} {{",
+      args.join(", ")
+    );
+    if !comments && (args.is_empty() || self.fits(&flat)) {
+      self.push(&flat[..flat.len() - 1]);
+      return;
+    }
+    self.push(&format!("{kw} {name}("));
+    self.level += 1;
+    self.last_end = open + 1;
+    let start_of = |i: usize| {
+      names.get(i).map_or(close, |name| name.location_offset())
+    };
+    self.comments_before(start_of(0), false);
+    for (i, arg) in args.iter().enumerate() {
+      self.newline();
+      self.push(arg);
+      if i + 1 < args.len() {
+        self.push(",");
+      }
+      self.last_end = end_of(names[i]);
+      self.comments_before(start_of(i + 1), false);
+    }
+    self.level -= 1;
+    self.newline();
+    self.push(&format!(") -> {ret_type} "));
+  }
+
+  /// Write a block whose `{` is at `open` in the source and return
+  /// the position after its `}`.
+  fn block(
+    &mut self,
+    stmts: &Statements,
+    open: usize,
+  ) -> usize {
+    self.push("{");
+    self.level += 1;
+    self.block_start = true;
+    self.last_end = open + 1;
+    let end = self.statements(stmts, open + 1);
+    let close = self.find(end, '}');
+    self.comments_before(close, false);
+    self.level -= 1;
+    if !self.out.ends_with('{') {
+      self.newline();
+    }
+    self.push("}");
+    self.last_end = close + 1;
+    close + 1
+  }
+
+  /// Write the else clause of an `if` whose block ends at `pos`.
+  fn else_clause(&mut self, stmts: &Statements, pos: usize) {
+    self.push(" else ");
+    let pos = self.skip_trivia(pos) + "else".len();
+    let pos = self.skip_trivia(pos);
+    match &stmts[..] {
+      [Statement::Expression(ex)]
+        if !self.src[pos..].starts_with('{') =>
+      {
+        self.expr(ex, 0)
+      }
+      _ => {
+        self.block(stmts, pos);
+      }
+    }
+  }
+
+  /// The expression in a line, or `None` if it has a block.
+  fn flat(&self, ex: &Expression) -> Option<String> {
+    if has_block(ex) {
+      return None;
+    }
+    let config = FormatConfig {
+      max_width: usize::MAX,
+      ..self.config
+    };
+    let mut printer = Printer::new(self.src, config, vec![]);
+    printer.expr(ex, 0);
+    Some(printer.out)
+  }
+
+  /// Write the expression, in parentheses if it binds weaker than
+  /// `min_prec`.
+  fn expr(&mut self, ex: &Expression, min_prec: u8) {
+    let parens = precedence(ex) < min_prec;
+    if parens {
+      self.push("(");
+    }
+    match &ex.expr {
+      ExprEnum::Ident(name) => self.push(name),
+      ExprEnum::NumLiteral(_)
+      | ExprEnum::StrLiteral(_)
+      | ExprEnum::Interpolated(_) => self.push(&ex.span),
+      ExprEnum::NoneLiteral => self.push("none"),
+      ExprEnum::SomeValue(ex) => {
+        self.push("some(");
+        self.expr(ex, 0);
+        self.push(")");
+      }
+      ExprEnum::FnInvoke(name, args) => {
+        self.fn_invoke(ex, name, args)
+      }
+      ExprEnum::Add(lhs, rhs) => {
+        self.binary(lhs, " + ", rhs, 3, 4)
+      }
+      ExprEnum::Sub(lhs, rhs) => {
+        self.binary(lhs, " - ", rhs, 3, 4)
+      }
+      ExprEnum::Mul(lhs, rhs) => {
+        self.binary(lhs, " * ", rhs, 4, 5)
+      }
+      ExprEnum::Div(lhs, rhs) => {
+        self.binary(lhs, " / ", rhs, 4, 5)
+      }
+      ExprEnum::Gt(lhs, rhs) => {
+        self.binary(lhs, " > ", rhs, 3, 3)
+      }
+      ExprEnum::Lt(lhs, rhs) => {
+        self.binary(lhs, " < ", rhs, 3, 3)
+      }
+      ExprEnum::Coalesce(lhs, rhs) => {
+        self.binary(lhs, " ?? ", rhs, 2, 1)
+      }
+      ExprEnum::If(cond, t_case, f_case) => {
+        self.push("if ");
+        self.expr(cond, 0);
+        self.push(" ");
+        let open = self.find(end_of(cond.span), '{');
+        let end = self.block(t_case, open);
+        if let Some(f_case) = f_case {
+          self.else_clause(f_case, end);
+        }
+      }
+      ExprEnum::IfLet(name, ex, t_case, f_case) => {
+        self.push("if let ");
+        self.push(name);
+        self.push(" = ");
+        self.expr(ex, 0);
+        self.push(" ");
+        let open = self.find(end_of(ex.span), '{');
+        let end = self.block(t_case, open);
+        if let Some(f_case) = f_case {
+          self.else_clause(f_case, end);
+        }
+      }
+      ExprEnum::Await(ex) => {
+        self.push("await ");
+        self.expr(ex, 0);
+      }
+      ExprEnum::Try(body, name, handler) => {
+        self.push("try ");
+        let open = self.find(ex.span.location_offset(), '{');
+        self.block(body, open);
+        self.push(" catch ");
+        self.push(name);
+        self.push(" ");
+        let open = self.find(end_of(*name), '{');
+        self.block(handler, open);
+      }
+    }
+    if parens {
+      self.push(")");
+    }
+  }
+
+  fn binary(
+    &mut self,
+    lhs: &Expression,
+    op: &str,
+    rhs: &Expression,
+    lhs_prec: u8,
+    rhs_prec: u8,
+  ) {
+    self.expr(lhs, lhs_prec);
+    self.push(op);
+    self.expr(rhs, rhs_prec);
+  }
+
+  /// Write a function call, with an argument per line if it does
+  /// not fit in a line.
+  fn fn_invoke(
+    &mut self,
+    ex: &Expression,
+    name: &str,
+    args: &[Expression],
+  ) {
+    let (open, close) = self.arg_list(ex);
+    let comments = self.has_comment(open, close);
+    // The flat printer has no width to check.
+    let fits = !comments
+      && (self.config.max_width == usize::MAX
+        || self.flat(ex).is_some_and(|flat| self.fits(&flat)));
+    self.push(name);
+    self.push("(");
+    if (args.is_empty() && !comments) || fits {
+      for (i, arg) in args.iter().enumerate() {
+        if 0 < i {
+          self.push(", ");
+        }
+        self.expr(arg, 0);
+      }
+      self.push(")");
+      return;
+    }
+    self.level += 1;
+    self.last_end = open + 1;
+    self.comments_before(
+      args
+        .first()
+        .map_or(close, |arg| arg.span.location_offset()),
+      false,
+    );
+    for (i, arg) in args.iter().enumerate() {
+      self.newline();
+      self.expr(arg, 0);
+      self.push(",");
+      self.last_end = end_of(arg.span);
+      let next = args
+        .get(i + 1)
+        .map_or(close, |arg| arg.span.location_offset());
+      self.comments_before(next, false);
+    }
+    self.level -= 1;
+    self.newline();
+    self.push(")");
+  }
+}
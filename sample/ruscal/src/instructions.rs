@@ -16,6 +16,8 @@ pub enum OpCode {
   Jmp,
   /// Jump if false
   Jf,
+  /// Pop a value from the stack and jump if it is none
+  Jn,
   /// Pop a value from the stack, compare it with a value at arg0, push true if it's less
   Lt,
   /// Pop n values from the stack where n is given by arg0
@@ -26,18 +28,35 @@ pub enum OpCode {
   Yield,
   /// Await a coroutine in progress until the next yield
   Await,
+  /// Pop a value from the stack and throw it as an error, unwinding
+  /// to the nearest exception handler
+  Throw,
 }
 
 macro_rules! impl_op_from {
     ($($op:ident),*) => {
-      impl From<u8> for OpCode {
+      impl TryFrom<u8> for OpCode {
+        type Error = String;
+
         #[allow(non_upper_case_globals)]
-        fn from(o: u8) -> Self {
+        fn try_from(o: u8) -> Result<Self, String> {
           $(const $op: u8 = OpCode::$op as u8;)*
 
           match o {
-            $($op => Self::$op,)*
-            _ => panic!("Opcode \"{:02X}\" unrecognized!", o),
+            $($op => Ok(Self::$op),)*
+            _ => Err(format!("Opcode \"{:02X}\" unrecognized!", o)),
+          }
+        }
+      }
+
+      /// Parses the name of an opcode as printed by `Debug`.
+      impl std::str::FromStr for OpCode {
+        type Err = ();
+
+        fn from_str(s: &str) -> Result<Self, ()> {
+          match s {
+            $(stringify!($op) => Ok(Self::$op),)*
+            _ => Err(()),
           }
         }
       }
@@ -56,11 +75,13 @@ impl_op_from!(
   Call,
   Jmp,
   Jf,
+  Jn,
   Lt,
   Pop,
   Ret,
   Yield,
-  Await
+  Await,
+  Throw
 );
 
 #[derive(Debug, Clone, Copy)]
@@ -75,6 +96,14 @@ impl Instruction {
     Self { op, arg0 }
   }
 
+  pub fn op(&self) -> OpCode {
+    self.op
+  }
+
+  pub fn arg0(&self) -> u8 {
+    self.arg0
+  }
+
   pub(crate) fn serialize(
     &self,
     writer: &mut impl Write,
@@ -88,6 +117,8 @@ impl Instruction {
   ) -> Result<Self, std::io::Error> {
     let mut buf = [0u8; 2];
     reader.read_exact(&mut buf)?;
-    Ok(Self::new(buf[0].into(), buf[1]))
+    let op =
+      buf[0].try_into().map_err(std::io::Error::other)?;
+    Ok(Self::new(op, buf[1]))
   }
 }
@@ -1,16 +1,32 @@
+pub mod asm;
 pub mod ast;
+pub mod ast_export;
 pub mod bytecode;
 pub mod compiler;
+pub mod debugger;
+pub mod diagnostics;
+pub mod engine;
 pub mod file_io;
-mod instructions;
+mod format;
+pub mod formatter;
+pub mod instructions;
+pub mod lint;
+pub mod lsp;
 pub mod parser;
+pub mod profiler;
+pub mod repl;
+pub mod test_runner;
+pub mod tracer;
 pub mod type_checker;
 pub mod value;
 pub mod vm;
 
 use std::{collections::HashMap, sync::atomic::AtomicBool};
 
+use ast_export::AstFormat;
 use bytecode::NativeFn;
+use formatter::FormatConfig;
+use lint::LintConfig;
 
 pub enum RunMode {
   None,
@@ -18,6 +34,16 @@ pub enum RunMode {
   Compile,
   Run(String),
   CompileAndRun,
+  /// Read and run statements interactively
+  Repl,
+  /// Rewrite the source in the canonical style
+  Format,
+  /// Compile and run the test functions
+  Test,
+  /// Assemble the text format of the bytecode
+  Asm,
+  /// Disassemble a bytecode file
+  Disasm,
 }
 
 pub struct Args {
@@ -26,7 +52,27 @@ pub struct Args {
   pub output: String,
   pub disasm: bool,
   pub show_ast: bool,
+  /// How the AST is shown by `show_ast`
+  pub ast_format: AstFormat,
   pub debug_output: bool,
+  /// File to write a JSON-lines trace of the execution to
+  pub trace: Option<String>,
+  /// Run under the debugger
+  pub debugger: bool,
+  /// Print a profile of the execution at exit
+  pub profile: bool,
+  /// File to write the folded stacks of the profile to
+  pub folded: Option<String>,
+  /// Maximum number of type errors to report, or the default if `None`
+  pub max_errors: Option<usize>,
+  /// Type check in strict mode, see `TypeCheckContext::set_strict`
+  pub strict: bool,
+  /// Levels of the lints run after the type check
+  pub lints: LintConfig,
+  /// Style of the formatted source
+  pub format: FormatConfig,
+  /// Only check that the source is formatted instead of rewriting it
+  pub check: bool,
   /// Because Args is passed as a shared reference, NativeFn can be requested to be generated multiple times.
   /// Having a function to return one is an easy trick to allow it without breaking API.
   pub additional_funcs:
@@ -41,7 +87,17 @@ impl Args {
       output: "".to_string(),
       disasm: false,
       show_ast: false,
+      ast_format: AstFormat::Debug,
       debug_output: false,
+      trace: None,
+      debugger: false,
+      profile: false,
+      folded: None,
+      max_errors: None,
+      strict: false,
+      lints: LintConfig::new(),
+      format: FormatConfig::new(),
+      check: false,
       additional_funcs: HashMap::new(),
     }
   }
@@ -61,9 +117,19 @@ pub fn parse_args(compilable: bool) -> Option<Args> {
   let mut output = None;
   let mut disasm = false;
   let mut show_ast = false;
+  let mut ast_format = AstFormat::Debug;
   let mut show_help = false;
   let mut args_is_empty = true;
   let mut debug_output = false;
+  let mut trace = None;
+  let mut debugger = false;
+  let mut profile = false;
+  let mut folded = None;
+  let mut max_errors = None;
+  let mut strict = false;
+  let mut lints = LintConfig::new();
+  let mut format = FormatConfig::new();
+  let mut check = false;
 
   let mut args = std::env::args();
   let exe = args.next();
@@ -71,6 +137,12 @@ pub fn parse_args(compilable: bool) -> Option<Args> {
   while let Some(arg) = next_arg {
     match &arg as &str {
       "-h" => show_help = true,
+      "asm" if compilable && args_is_empty => {
+        run_mode = RunMode::Asm
+      }
+      "disasm" if compilable && args_is_empty => {
+        run_mode = RunMode::Disasm
+      }
       "-c" => run_mode = RunMode::Compile,
       "-o" => output = args.next(),
       "-r" => {
@@ -80,9 +152,74 @@ pub fn parse_args(compilable: bool) -> Option<Args> {
         run_mode = RunMode::Run(bytecode);
       }
       "-R" => run_mode = RunMode::CompileAndRun,
+      "-T" => run_mode = RunMode::Test,
       "-d" => disasm = true,
       "-a" => show_ast = true,
+      _ if arg == "--ast-format"
+        || arg.starts_with("--ast-format=") =>
+      {
+        let value = match arg.split_once('=') {
+          Some((_, value)) => Some(value.to_string()),
+          None => args.next(),
+        };
+        match value.unwrap_or_default().parse() {
+          Ok(format) => ast_format = format,
+          Err(e) => {
+            println!("{e}");
+            return None;
+          }
+        }
+        show_ast = true;
+      }
+      "-g" => debugger = true,
+      "--profile" => profile = true,
+      "--trace" => {
+        let Some(file) = args.next() else {
+          println!("--trace needs a file name");
+          return None;
+        };
+        trace = Some(file);
+      }
+      "--folded" => {
+        let Some(file) = args.next() else {
+          println!("--folded needs a file name");
+          return None;
+        };
+        profile = true;
+        folded = Some(file);
+      }
       "-t" => run_mode = RunMode::TypeCheck,
+      "-i" => run_mode = RunMode::Repl,
+      "-f" => run_mode = RunMode::Format,
+      "--check" => check = true,
+      "--indent" | "--max-width" => {
+        let Some(n) = args.next().and_then(|n| n.parse().ok())
+        else {
+          println!("{arg} needs a number");
+          return None;
+        };
+        if arg == "--indent" {
+          format.indent = n;
+        } else {
+          format.max_width = n;
+        }
+      }
+      "--max-errors" => {
+        let Some(n) = args.next().and_then(|n| n.parse().ok())
+        else {
+          println!("--max-errors needs a number");
+          return None;
+        };
+        max_errors = Some(n);
+      }
+      "--strict" => strict = true,
+      "--lint" => {
+        let setting = args.next().unwrap_or_default();
+        if let Err(e) = lints.parse_setting(&setting) {
+          println!("{e}");
+          return None;
+        }
+      }
       "-D" => {
         DEBUG.store(true, std::sync::atomic::Ordering::Relaxed);
         debug_output = true;
@@ -106,20 +243,65 @@ pub fn parse_args(compilable: bool) -> Option<Args> {
     -o file  Specify output file
     -r       Run bytecode
     -R       Compile and run
-    -d       Disassemble compiled code"#
+    -T       Compile and run the test functions
+    -d       Disassemble compiled or loaded code
+    -g       Run under the debugger
+    --profile
+             Print a profile of the execution at exit
+    --folded file
+             Profile and write the folded stacks to the file,
+             for flamegraph tools
+    --trace file
+             Write a trace of the execution to the file as JSON
+             lines"#
     } else {
       ""
     };
+    let lint_names = lint::Lint::ALL
+      .iter()
+      .map(|lint| lint.name())
+      .collect::<Vec<_>>()
+      .join(", ");
+    let exe = exe.unwrap_or_else(|| "29-full-stmt".to_string());
+    let commands = if compilable {
+      format!(
+        r#"
+       {exe} asm file.s [-o file]
+             Assemble the text of a bytecode file
+       {exe} disasm file.bin
+             Disassemble a bytecode file to stdout"#
+      )
+    } else {
+      String::new()
+    };
     println!(
-      r#"Usage: {} [options] [source.txt]
+      r#"Usage: {exe} [options] [source.txt]{commands}
 
 Options:
 {compiler_options}
-    -a       Show AST
+    -a       Show AST, or only parse and show it without
+             another mode
+    --ast-format=json|sexpr|debug
+             Show AST as JSON, S-expressions or Rust's
+             Debug output (default)
     -t       Typecheck only, no execution of the code
+    -i       Start an interactive session (REPL)
+    -f       Format the source file in place
+    --check  With -f, only check that the file is formatted
+    --indent n
+             Number of spaces per indentation level (default 4)
+    --max-width n
+             Width of the formatted lines (default 80)
+    --max-errors n
+             Maximum number of type errors to report
+    --strict Forbid implicit conversions between i64 and f64
+             and types that are not fully known
+    --lint name=level
+             Set the level of a lint to allow, warn or deny.
+             The name all sets every lint. Lints are
+             {lint_names}
     -h       Display help
-"#,
-      exe.unwrap_or_else(|| "29-full-stmt".to_string())
+"#
     );
     return None;
   }
@@ -131,7 +313,17 @@ Options:
       .unwrap_or_else(|| "bytecode.bin".to_string()),
     disasm,
     show_ast,
+    ast_format,
     debug_output,
+    trace,
+    debugger,
+    profile,
+    folded,
+    max_errors,
+    strict,
+    lints,
+    format,
+    check,
     additional_funcs: HashMap::new(),
   })
 }
@@ -141,13 +333,13 @@ macro_rules! dprintln {
     ($fmt:literal) => {
         #[cfg(not(target_arch = "wasm32"))]
         if ::ruscal::DEBUG.load(std::sync::atomic::Ordering::Relaxed) {
-            println!($fmt);
+            eprintln!($fmt);
         }
     };
     ($fmt:literal, $($args:expr),*) => {
         #[cfg(not(target_arch = "wasm32"))]
         if ::ruscal::DEBUG.load(std::sync::atomic::Ordering::Relaxed) {
-            println!($fmt, $($args),*);
+            eprintln!($fmt, $($args),*);
         }
     };
 }
@@ -0,0 +1,744 @@
+//! Lint pass over the AST, reporting code that is valid but most
+//! likely a mistake, like variables that are never used.
+//!
+//! Each lint has a name and a level, which can be changed from the
+//! command line with `--lint name=level`. Names starting with an
+//! underscore are exempt from the unused lints, like in Rust.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+  ast::{
+    ExprEnum, Expression, Span, Statement, Statements, StrPart,
+  },
+  diagnostics::{Diagnostic, Level},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+  /// A variable, parameter or loop variable that is never read
+  UnusedVariable,
+  /// A function that is never called from the top level code
+  UnusedFunction,
+  /// A variable defined again with the same name in a function
+  Shadowing,
+  /// An assigned value that is overwritten or dropped before read
+  UnusedAssignment,
+  /// Statements after `return`, `break`, `continue` or `throw`
+  UnreachableCode,
+}
+
+impl Lint {
+  pub const ALL: [Lint; 5] = [
+    Lint::UnusedVariable,
+    Lint::UnusedFunction,
+    Lint::Shadowing,
+    Lint::UnusedAssignment,
+    Lint::UnreachableCode,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::UnusedVariable => "unused_variable",
+      Self::UnusedFunction => "unused_function",
+      Self::Shadowing => "shadowing",
+      Self::UnusedAssignment => "unused_assignment",
+      Self::UnreachableCode => "unreachable_code",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+  Allow,
+  Warn,
+  Deny,
+}
+
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+  levels: HashMap<Lint, LintLevel>,
+}
+
+impl Default for LintConfig {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl LintConfig {
+  /// All lints are warnings by default.
+  pub fn new() -> Self {
+    Self {
+      levels: Lint::ALL
+        .iter()
+        .map(|lint| (*lint, LintLevel::Warn))
+        .collect(),
+    }
+  }
+
+  pub fn level(&self, lint: Lint) -> LintLevel {
+    self.levels.get(&lint).copied().unwrap_or(LintLevel::Warn)
+  }
+
+  pub fn set_level(&mut self, lint: Lint, level: LintLevel) {
+    self.levels.insert(lint, level);
+  }
+
+  /// Parse a setting like `unused_variable=deny`. The lint name
+  /// `all` applies the level to every lint.
+  pub fn parse_setting(
+    &mut self,
+    s: &str,
+  ) -> Result<(), String> {
+    let (name, level) = s.split_once('=').ok_or_else(|| {
+      format!("Lint setting should be name=level: {s:?}")
+    })?;
+    let level = match level {
+      "allow" => LintLevel::Allow,
+      "warn" => LintLevel::Warn,
+      "deny" => LintLevel::Deny,
+      _ => {
+        return Err(format!(
+          "Unknown lint level {level:?}, expected allow, warn \
+          or deny"
+        ))
+      }
+    };
+    if name == "all" {
+      for lint in Lint::ALL {
+        self.set_level(lint, level);
+      }
+      return Ok(());
+    }
+    let lint = Lint::ALL
+      .into_iter()
+      .find(|lint| lint.name() == name)
+      .ok_or_else(|| format!("Unknown lint {name:?}"))?;
+    self.set_level(lint, level);
+    Ok(())
+  }
+}
+
+#[derive(Debug)]
+pub struct LintWarning<'src> {
+  pub lint: Lint,
+  pub level: LintLevel,
+  pub msg: String,
+  pub span: Span<'src>,
+}
+
+impl<'src> std::fmt::Display for LintWarning<'src> {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    let level = match self.level {
+      LintLevel::Deny => "error",
+      _ => "warning",
+    };
+    write!(f, "{level}[{}]: {}", self.lint.name(), self.msg)
+  }
+}
+
+impl<'src> From<&LintWarning<'src>> for Diagnostic {
+  fn from(warning: &LintWarning<'src>) -> Self {
+    let level = match warning.level {
+      LintLevel::Deny => Level::Error,
+      _ => Level::Warning,
+    };
+    Diagnostic::new(level, &warning.msg)
+      .with_code(warning.lint.name())
+      .with_span(warning.span)
+  }
+}
+
+/// Run all lints on the program and return the findings that are not
+/// allowed, sorted by their positions.
+pub fn lint<'src>(
+  stmts: &Statements<'src>,
+  config: &LintConfig,
+) -> Vec<LintWarning<'src>> {
+  let mut linter = Linter {
+    config,
+    warnings: vec![],
+    fns: vec![],
+  };
+  let calls = linter.lint_fn(stmts, &[]);
+  linter.check_unused_fns(calls);
+  let mut warnings = linter.warnings;
+  warnings.sort_by_key(|w| w.span.location_offset());
+  warnings
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BindingKind {
+  Var,
+  Param,
+  LoopVar,
+}
+
+struct Binding<'src> {
+  name: Span<'src>,
+  kind: BindingKind,
+  used: bool,
+}
+
+/// A value written to a variable that has not been read yet.
+struct Pending<'src> {
+  span: Span<'src>,
+  /// Whether the value was written by `var` rather than an
+  /// assignment.
+  def: bool,
+}
+
+/// Variables are scoped to the function like in the type checker,
+/// so a block does not introduce a new scope. Loop variables and
+/// the bindings of `if let` and `catch` are the exception, see
+/// `Linter::lint_block_with`.
+#[derive(Default)]
+struct FnScope<'src> {
+  vars: HashMap<&'src str, Binding<'src>>,
+  /// Variables of loops that have ended. They can still be read
+  /// after the loop, like the index where a search stopped, but
+  /// defining the name again does not shadow them.
+  ended_loop_vars: HashMap<&'src str, Binding<'src>>,
+  pending: HashMap<&'src str, Pending<'src>>,
+  /// Values overwritten before being read. They are reported at
+  /// the end of the function unless the variable is never used at
+  /// all, which is reported as an unused variable instead.
+  overwritten: Vec<(&'src str, Span<'src>)>,
+  calls: Vec<&'src str>,
+}
+
+struct FnInfo<'src> {
+  name: Span<'src>,
+  calls: Vec<&'src str>,
+  test: bool,
+}
+
+struct Linter<'src, 'cfg> {
+  config: &'cfg LintConfig,
+  warnings: Vec<LintWarning<'src>>,
+  fns: Vec<FnInfo<'src>>,
+}
+
+impl<'src, 'cfg> Linter<'src, 'cfg> {
+  fn warn(
+    &mut self,
+    lint: Lint,
+    msg: String,
+    span: Span<'src>,
+  ) {
+    let level = self.config.level(lint);
+    if level != LintLevel::Allow {
+      self.warnings.push(LintWarning {
+        lint,
+        level,
+        msg,
+        span,
+      });
+    }
+  }
+
+  /// Lint a function body (or the top level code) and return the
+  /// names of the functions it calls.
+  fn lint_fn(
+    &mut self,
+    stmts: &Statements<'src>,
+    params: &[Span<'src>],
+  ) -> Vec<&'src str> {
+    let mut scope = FnScope::default();
+    for param in params {
+      self.define(&mut scope, *param, BindingKind::Param);
+    }
+    self.lint_stmts(stmts, &mut scope);
+    let dropped = std::mem::take(&mut scope.pending)
+      .into_iter()
+      .filter(|(_, pending)| !pending.def)
+      .map(|(name, pending)| (name, pending.span));
+    let mut unread: Vec<_> =
+      std::mem::take(&mut scope.overwritten)
+        .into_iter()
+        .chain(dropped)
+        .filter(|(name, _)| {
+          scope
+            .vars
+            .get(name)
+            .or_else(|| scope.ended_loop_vars.get(name))
+            .is_some_and(|b| b.used)
+        })
+        .collect();
+    unread.sort_by_key(|(_, span)| span.location_offset());
+    for (name, span) in unread {
+      self.unused_assignment(name, span);
+    }
+    let bindings =
+      std::mem::take(&mut scope.vars).into_values().chain(
+        std::mem::take(&mut scope.ended_loop_vars)
+          .into_values(),
+      );
+    for binding in bindings {
+      self.check_unused_binding(&binding);
+    }
+    scope.calls
+  }
+
+  fn check_unused_fns(&mut self, root_calls: Vec<&'src str>) {
+    let mut called: HashSet<&str> = HashSet::new();
+    // Test functions are called by the test runner.
+    let mut queue = root_calls;
+    queue.extend(
+      self
+        .fns
+        .iter()
+        .filter(|f| f.test)
+        .map(|f| *f.name.fragment()),
+    );
+    while let Some(name) = queue.pop() {
+      if !called.insert(name) {
+        continue;
+      }
+      for info in self.fns.iter().filter(|f| *f.name == name) {
+        queue.extend(info.calls.iter().copied());
+      }
+    }
+    let unused: Vec<_> = self
+      .fns
+      .iter()
+      .map(|f| f.name)
+      .filter(|name| {
+        !called.contains(**name) && !name.starts_with('_')
+      })
+      .collect();
+    for name in unused {
+      self.warn(
+        Lint::UnusedFunction,
+        format!("Function \"{name}\" is never called"),
+        name,
+      );
+    }
+  }
+
+  fn check_unused_binding(&mut self, binding: &Binding<'src>) {
+    if binding.used || binding.name.starts_with('_') {
+      return;
+    }
+    let kind = match binding.kind {
+      BindingKind::Var => "Variable",
+      BindingKind::Param => "Parameter",
+      BindingKind::LoopVar => "Loop variable",
+    };
+    self.warn(
+      Lint::UnusedVariable,
+      format!("{kind} \"{}\" is never used", binding.name),
+      binding.name,
+    );
+  }
+
+  fn unused_assignment(
+    &mut self,
+    name: &str,
+    span: Span<'src>,
+  ) {
+    self.warn(
+      Lint::UnusedAssignment,
+      format!("Value assigned to \"{name}\" is never read"),
+      span,
+    );
+  }
+
+  fn define(
+    &mut self,
+    scope: &mut FnScope<'src>,
+    name: Span<'src>,
+    kind: BindingKind,
+  ) {
+    let binding = Binding {
+      name,
+      kind,
+      used: false,
+    };
+    if let Some(prev) = scope.vars.insert(*name, binding) {
+      self.shadowing(name, &prev);
+      self.check_unused_binding(&prev);
+    }
+    if let Some(prev) = scope.ended_loop_vars.remove(*name) {
+      self.check_unused_binding(&prev);
+    }
+    scope.pending.remove(*name);
+  }
+
+  fn shadowing(
+    &mut self,
+    name: Span<'src>,
+    prev: &Binding<'src>,
+  ) {
+    self.warn(
+      Lint::Shadowing,
+      format!(
+        "Variable \"{}\" shadows the definition at {}:{}",
+        name,
+        prev.name.location_line(),
+        prev.name.get_utf8_column()
+      ),
+      name,
+    );
+  }
+
+  fn read(&mut self, scope: &mut FnScope<'src>, name: &str) {
+    if let Some(binding) = scope
+      .vars
+      .get_mut(name)
+      .or_else(|| scope.ended_loop_vars.get_mut(name))
+    {
+      binding.used = true;
+    }
+    scope.pending.remove(name);
+  }
+
+  fn write(
+    &mut self,
+    scope: &mut FnScope<'src>,
+    name: &'src str,
+    span: Span<'src>,
+    def: bool,
+  ) {
+    if let Some(prev) =
+      scope.pending.insert(name, Pending { span, def })
+    {
+      scope.overwritten.push((name, prev.span));
+    }
+  }
+
+  fn lint_stmts(
+    &mut self,
+    stmts: &Statements<'src>,
+    scope: &mut FnScope<'src>,
+  ) {
+    let mut diverged = false;
+    let mut reported = false;
+    for stmt in stmts {
+      if diverged && !reported {
+        if let (false, Some(span)) =
+          (matches!(stmt, Statement::FnDef { .. }), stmt.span())
+        {
+          self.warn(
+            Lint::UnreachableCode,
+            "Unreachable statement".to_string(),
+            span,
+          );
+          reported = true;
+        }
+      }
+      self.lint_stmt(stmt, scope);
+      diverged |= matches!(
+        stmt,
+        Statement::Return(_)
+          | Statement::Break(_)
+          | Statement::Continue(_)
+          | Statement::Throw(_)
+      );
+    }
+  }
+
+  /// Lint a block that may run zero or more times. Pending writes
+  /// inside the block are not tracked beyond it because they may
+  /// be read in another iteration or after a branch.
+  fn lint_block(
+    &mut self,
+    stmts: &Statements<'src>,
+    scope: &mut FnScope<'src>,
+  ) {
+    let outer = std::mem::take(&mut scope.pending);
+    self.lint_stmts(stmts, scope);
+    scope.pending = outer;
+  }
+
+  fn lint_stmt(
+    &mut self,
+    stmt: &Statement<'src>,
+    scope: &mut FnScope<'src>,
+  ) {
+    match stmt {
+      Statement::Expression(ex) => self.lint_expr(ex, scope),
+      Statement::VarDef { name, ex, .. } => {
+        self.lint_expr(ex, scope);
+        self.define(scope, *name, BindingKind::Var);
+        self.write(scope, name, *name, true);
+      }
+      Statement::VarAssign { span, name, ex } => {
+        self.lint_expr(ex, scope);
+        self.write(scope, name, *span, false);
+      }
+      Statement::For {
+        loop_var,
+        start,
+        end,
+        stmts,
+        ..
+      } => {
+        self.lint_expr(start, scope);
+        self.lint_expr(end, scope);
+        self.read_in_block(stmts, scope);
+        self.lint_block_with(
+          *loop_var,
+          BindingKind::LoopVar,
+          stmts,
+          scope,
+        );
+      }
+      Statement::Break(_) | Statement::Continue(_) => (),
+      Statement::FnDef {
+        name,
+        args,
+        stmts,
+        test,
+        ..
+      } => {
+        let params: Vec<_> =
+          args.iter().map(|(name, _)| *name).collect();
+        let calls = self.lint_fn(stmts, &params);
+        self.fns.push(FnInfo {
+          name: *name,
+          calls,
+          test: *test,
+        });
+      }
+      Statement::Return(ex)
+      | Statement::Yield(ex)
+      | Statement::Throw(ex) => self.lint_expr(ex, scope),
+    }
+  }
+
+  /// Mark every variable read anywhere in the block as read before
+  /// entering it, since the block may run after later writes.
+  fn read_in_block(
+    &mut self,
+    stmts: &Statements<'src>,
+    scope: &mut FnScope<'src>,
+  ) {
+    let mut names = vec![];
+    collect_reads_stmts(stmts, &mut names);
+    for name in names {
+      scope.pending.remove(name);
+    }
+  }
+
+  fn lint_expr(
+    &mut self,
+    ex: &Expression<'src>,
+    scope: &mut FnScope<'src>,
+  ) {
+    use ExprEnum::*;
+    match &ex.expr {
+      Ident(name) => self.read(scope, name),
+      NumLiteral(_) | StrLiteral(_) | NoneLiteral => (),
+      Interpolated(parts) => {
+        for part in parts {
+          if let StrPart::Expr(ex, _) = part {
+            self.lint_expr(ex, scope);
+          }
+        }
+      }
+      SomeValue(ex) | Await(ex) => self.lint_expr(ex, scope),
+      FnInvoke(name, args) => {
+        scope.calls.push(name);
+        for arg in args {
+          self.lint_expr(arg, scope);
+        }
+      }
+      Add(lhs, rhs)
+      | Sub(lhs, rhs)
+      | Mul(lhs, rhs)
+      | Div(lhs, rhs)
+      | Gt(lhs, rhs)
+      | Lt(lhs, rhs)
+      | Coalesce(lhs, rhs) => {
+        self.lint_expr(lhs, scope);
+        self.lint_expr(rhs, scope);
+      }
+      If(cond, true_branch, false_branch) => {
+        self.lint_expr(cond, scope);
+        self.lint_branches(
+          None,
+          true_branch,
+          false_branch,
+          scope,
+        );
+      }
+      IfLet(name, ex, true_branch, false_branch) => {
+        self.lint_expr(ex, scope);
+        self.lint_branches(
+          Some(*name),
+          true_branch,
+          false_branch,
+          scope,
+        );
+      }
+      Try(body, name, handler) => {
+        self.read_in_block(body, scope);
+        self.read_in_block(handler, scope);
+        self.lint_block(body, scope);
+        self.lint_block_with(
+          *name,
+          BindingKind::Var,
+          handler,
+          scope,
+        );
+      }
+    }
+  }
+
+  /// Lint a block with a variable that is only defined inside it,
+  /// like a loop variable or the one bound by `if let` or `catch`.
+  /// Only a loop variable is reported for shadowing, since
+  /// `if let x = x` is the usual way to unwrap an optional, and it
+  /// is moved to `ended_loop_vars` after the loop.
+  fn lint_block_with(
+    &mut self,
+    name: Span<'src>,
+    kind: BindingKind,
+    stmts: &Statements<'src>,
+    scope: &mut FnScope<'src>,
+  ) {
+    let outer = scope.vars.remove(*name);
+    if kind == BindingKind::LoopVar {
+      if let Some(outer) = &outer {
+        self.shadowing(name, outer);
+      }
+      if let Some(prev) = scope.ended_loop_vars.remove(*name) {
+        self.check_unused_binding(&prev);
+      }
+    }
+    scope.vars.insert(
+      *name,
+      Binding {
+        name,
+        kind,
+        used: false,
+      },
+    );
+    self.lint_block(stmts, scope);
+    if let Some(binding) = scope.vars.remove(*name) {
+      if kind == BindingKind::LoopVar && outer.is_none() {
+        scope.ended_loop_vars.insert(*name, binding);
+      } else {
+        self.check_unused_binding(&binding);
+      }
+    }
+    if let Some(outer) = outer {
+      scope.vars.insert(*name, outer);
+    }
+  }
+
+  fn lint_branches(
+    &mut self,
+    binding: Option<Span<'src>>,
+    true_branch: &Statements<'src>,
+    false_branch: &Option<Box<Statements<'src>>>,
+    scope: &mut FnScope<'src>,
+  ) {
+    self.read_in_block(true_branch, scope);
+    if let Some(name) = binding {
+      self.lint_block_with(
+        name,
+        BindingKind::Var,
+        true_branch,
+        scope,
+      );
+    } else {
+      self.lint_block(true_branch, scope);
+    }
+    if let Some(false_branch) = false_branch {
+      self.read_in_block(false_branch, scope);
+      self.lint_block(false_branch, scope);
+    }
+  }
+}
+
+fn collect_reads_stmts<'src>(
+  stmts: &Statements<'src>,
+  names: &mut Vec<&'src str>,
+) {
+  for stmt in stmts {
+    collect_reads_stmt(stmt, names);
+  }
+}
+
+fn collect_reads_stmt<'src>(
+  stmt: &Statement<'src>,
+  names: &mut Vec<&'src str>,
+) {
+  match stmt {
+    Statement::Expression(ex)
+    | Statement::VarDef { ex, .. }
+    | Statement::VarAssign { ex, .. }
+    | Statement::Return(ex)
+    | Statement::Yield(ex)
+    | Statement::Throw(ex) => collect_reads_expr(ex, names),
+    Statement::For {
+      start, end, stmts, ..
+    } => {
+      collect_reads_expr(start, names);
+      collect_reads_expr(end, names);
+      collect_reads_stmts(stmts, names);
+    }
+    // A function body has its own variables.
+    Statement::FnDef { .. }
+    | Statement::Break(_)
+    | Statement::Continue(_) => (),
+  }
+}
+
+fn collect_reads_expr<'src>(
+  ex: &Expression<'src>,
+  names: &mut Vec<&'src str>,
+) {
+  use ExprEnum::*;
+  match &ex.expr {
+    Ident(name) => names.push(name),
+    NumLiteral(_) | StrLiteral(_) | NoneLiteral => (),
+    Interpolated(parts) => {
+      for part in parts {
+        if let StrPart::Expr(ex, _) = part {
+          collect_reads_expr(ex, names);
+        }
+      }
+    }
+    SomeValue(ex) | Await(ex) => collect_reads_expr(ex, names),
+    FnInvoke(_, args) => {
+      for arg in args {
+        collect_reads_expr(arg, names);
+      }
+    }
+    Add(lhs, rhs)
+    | Sub(lhs, rhs)
+    | Mul(lhs, rhs)
+    | Div(lhs, rhs)
+    | Gt(lhs, rhs)
+    | Lt(lhs, rhs)
+    | Coalesce(lhs, rhs) => {
+      collect_reads_expr(lhs, names);
+      collect_reads_expr(rhs, names);
+    }
+    If(cond, true_branch, false_branch) => {
+      collect_reads_expr(cond, names);
+      collect_reads_stmts(true_branch, names);
+      if let Some(false_branch) = false_branch {
+        collect_reads_stmts(false_branch, names);
+      }
+    }
+    IfLet(_, ex, true_branch, false_branch) => {
+      collect_reads_expr(ex, names);
+      collect_reads_stmts(true_branch, names);
+      if let Some(false_branch) = false_branch {
+        collect_reads_stmts(false_branch, names);
+      }
+    }
+    Try(body, _, handler) => {
+      collect_reads_stmts(body, names);
+      collect_reads_stmts(handler, names);
+    }
+  }
+}
@@ -0,0 +1,659 @@
+//! A Language Server Protocol server speaking JSON-RPC over a pair
+//! of streams, usually stdin and stdout.
+//!
+//! It publishes the syntax and type errors and the lint warnings of
+//! the open documents, shows the types of the names under the
+//! cursor, jumps to their definitions and completes the names in
+//! scope. Documents are synchronized as a whole on every change.
+
+use std::{
+  collections::HashMap,
+  io::{BufRead, Read, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+  ast::{ExprEnum, Expression, Span, Statement, StrPart},
+  bytecode::standard_functions,
+  diagnostics::{Diagnostic, Label, Level},
+  lint::{lint, LintConfig},
+  parser::statements_recover,
+  type_checker::{type_check, SymbolKind, TypeCheckContext},
+};
+
+/// JSON-RPC error code for a message that is not valid JSON
+const PARSE_ERROR: i64 = -32700;
+/// JSON-RPC error code for an unknown method
+const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code for invalid parameters
+const INVALID_PARAMS: i64 = -32602;
+
+/// Largest body accepted in a message. Larger bodies are skipped
+/// instead of being read into memory.
+const MAX_CONTENT_LENGTH: usize = 16 << 20;
+
+/// `CompletionItemKind` values in the protocol
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_VARIABLE: u32 = 6;
+
+/// Read a message framed by the `Content-Length` header, or `None`
+/// at the end of the input.
+pub fn read_message(
+  reader: &mut impl BufRead,
+) -> std::io::Result<Option<Value>> {
+  read_frame(reader)?
+    .map(|frame| {
+      frame.map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+      })
+    })
+    .transpose()
+}
+
+/// Read a message like `read_message`, but return a frame without
+/// the header, with a body that is too large or with a malformed
+/// body as an inner error, after which the next frame can still be
+/// read.
+///
+/// The body of a frame without the header cannot be skipped, so
+/// the header is also looked for after other text on a line, where
+/// it follows such a body.
+fn read_frame(
+  reader: &mut impl BufRead,
+) -> std::io::Result<Option<Result<Value, String>>> {
+  let mut len = None;
+  let mut in_header = false;
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+      return Ok(None);
+    }
+    let line = line.trim_end();
+    if line.is_empty() {
+      if in_header {
+        break;
+      }
+      continue;
+    }
+    in_header = true;
+    if let Some((_, value)) = line.split_once("Content-Length:")
+    {
+      len = value.trim().parse().ok();
+    }
+  }
+  let Some(len) = len else {
+    return Ok(Some(Err(
+      "Missing Content-Length header".to_string(),
+    )));
+  };
+  if len > MAX_CONTENT_LENGTH {
+    std::io::copy(
+      &mut reader.take(len as u64),
+      &mut std::io::sink(),
+    )?;
+    return Ok(Some(Err(format!(
+      "Content-Length {len} is larger than the limit of \
+      {MAX_CONTENT_LENGTH}"
+    ))));
+  }
+  let mut buf = vec![0; len];
+  reader.read_exact(&mut buf)?;
+  Ok(Some(
+    serde_json::from_slice(&buf).map_err(|e| e.to_string()),
+  ))
+}
+
+pub fn write_message(
+  writer: &mut impl Write,
+  msg: &Value,
+) -> std::io::Result<()> {
+  let body = msg.to_string();
+  write!(
+    writer,
+    "Content-Length: {}\r\n\r\n{body}",
+    body.len()
+  )?;
+  writer.flush()
+}
+
+/// Serve the requests read from `reader` until the client sends
+/// `exit` or closes the stream. A malformed message is answered
+/// with a parse error and the server keeps going.
+pub fn run(
+  mut reader: impl BufRead,
+  mut writer: impl Write,
+) -> std::io::Result<()> {
+  let mut server = Server::default();
+  while let Some(frame) = read_frame(&mut reader)? {
+    let msg = match frame {
+      Ok(msg) => msg,
+      Err(e) => {
+        // The id of a message that cannot be parsed is unknown.
+        let response = json!({
+          "jsonrpc": "2.0",
+          "id": null,
+          "error": { "code": PARSE_ERROR, "message": e },
+        });
+        write_message(&mut writer, &response)?;
+        continue;
+      }
+    };
+    let Some(method) = msg["method"].as_str() else {
+      // A response to a request from the server, which sends none
+      continue;
+    };
+    if method == "exit" {
+      break;
+    }
+    let params = &msg["params"];
+    let Some(id) = msg.get("id") else {
+      for notification in server.notify(method, params) {
+        write_message(&mut writer, &notification)?;
+      }
+      continue;
+    };
+    let response = match server.request(method, params) {
+      Ok(result) => json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "result": result,
+      }),
+      Err((code, message)) => json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+      }),
+    };
+    write_message(&mut writer, &response)?;
+  }
+  Ok(())
+}
+
+#[derive(Default)]
+struct Server {
+  documents: HashMap<String, Document>,
+}
+
+struct Document {
+  text: String,
+  analysis: Analysis,
+}
+
+impl Server {
+  fn request(
+    &mut self,
+    method: &str,
+    params: &Value,
+  ) -> Result<Value, (i64, String)> {
+    Ok(match method {
+      "initialize" => json!({
+        "capabilities": {
+          // Full document sync
+          "textDocumentSync": 1,
+          "hoverProvider": true,
+          "definitionProvider": true,
+          "completionProvider": {},
+        },
+        "serverInfo": {
+          "name": "ruscal-lsp",
+          "version": env!("CARGO_PKG_VERSION"),
+        },
+      }),
+      "shutdown" => Value::Null,
+      "textDocument/hover" => {
+        let (_, doc, offset) = self.position(params)?;
+        doc.hover(offset).map_or(
+          Value::Null,
+          |(text, range)| {
+            json!({
+              "contents": {
+                "kind": "markdown",
+                "value": format!("```ruscal\n{text}\n```"),
+              },
+              "range": range,
+            })
+          },
+        )
+      }
+      "textDocument/definition" => {
+        let (uri, doc, offset) = self.position(params)?;
+        doc.definition(offset).map_or(
+          Value::Null,
+          |range| json!({ "uri": uri, "range": range }),
+        )
+      }
+      "textDocument/completion" => {
+        let (_, doc, offset) = self.position(params)?;
+        Value::Array(doc.completion(offset))
+      }
+      _ => {
+        return Err((
+          METHOD_NOT_FOUND,
+          format!("Method {method} is not supported"),
+        ))
+      }
+    })
+  }
+
+  /// The document and the byte offset of a text document position.
+  fn position<'a>(
+    &'a self,
+    params: &'a Value,
+  ) -> Result<(&'a str, &'a Document, usize), (i64, String)> {
+    let uri = params["textDocument"]["uri"].as_str().ok_or(
+      (INVALID_PARAMS, "Missing document".to_string()),
+    )?;
+    let doc = self.documents.get(uri).ok_or_else(|| {
+      (INVALID_PARAMS, format!("Document {uri} is not open"))
+    })?;
+    let offset = offset(&doc.text, &params["position"]).ok_or(
+      (INVALID_PARAMS, "Invalid position".to_string()),
+    )?;
+    Ok((uri, doc, offset))
+  }
+
+  /// Handle a notification and return the notifications to send
+  /// back.
+  fn notify(
+    &mut self,
+    method: &str,
+    params: &Value,
+  ) -> Vec<Value> {
+    let uri = params["textDocument"]["uri"].as_str();
+    let text = match method {
+      "textDocument/didOpen" => {
+        params["textDocument"]["text"].as_str()
+      }
+      // The whole text is sent, so the last change has it.
+      "textDocument/didChange" => params["contentChanges"]
+        .as_array()
+        .and_then(|changes| changes.last())
+        .and_then(|change| change["text"].as_str()),
+      "textDocument/didClose" => {
+        let Some(uri) = uri else {
+          return vec![];
+        };
+        self.documents.remove(uri);
+        return vec![publish_diagnostics(uri, vec![])];
+      }
+      _ => return vec![],
+    };
+    let (Some(uri), Some(text)) = (uri, text) else {
+      return vec![];
+    };
+    let doc = Document::new(text.to_string());
+    let diagnostics = doc
+      .analysis
+      .diagnostics
+      .iter()
+      .map(|diag| lsp_diagnostic(uri, &doc.text, diag))
+      .collect();
+    self.documents.insert(uri.to_string(), doc);
+    vec![publish_diagnostics(uri, diagnostics)]
+  }
+}
+
+fn publish_diagnostics(
+  uri: &str,
+  diagnostics: Vec<Value>,
+) -> Value {
+  json!({
+    "jsonrpc": "2.0",
+    "method": "textDocument/publishDiagnostics",
+    "params": { "uri": uri, "diagnostics": diagnostics },
+  })
+}
+
+fn lsp_diagnostic(
+  uri: &str,
+  text: &str,
+  diag: &Diagnostic,
+) -> Value {
+  let primary = diag.primary.as_ref().map_or_else(
+    || range(text, 0, 0),
+    |label| label_range(text, label),
+  );
+  let mut message = diag.msg.clone();
+  for note in &diag.notes {
+    message += &format!("\nnote: {note}");
+  }
+  let related: Vec<_> = diag
+    .secondary
+    .iter()
+    .filter(|label| !label.msg.is_empty())
+    .map(|label| {
+      json!({
+        "location": {
+          "uri": uri,
+          "range": label_range(text, label),
+        },
+        "message": label.msg,
+      })
+    })
+    .collect();
+  let mut res = json!({
+    "range": primary,
+    "severity": match diag.level {
+      Level::Error => 1,
+      Level::Warning => 2,
+    },
+    "source": "ruscal",
+    "message": message,
+    "relatedInformation": related,
+  });
+  if let Some(code) = &diag.code {
+    res["code"] = json!(code);
+  }
+  res
+}
+
+/// A name in a document, with byte offsets into its text.
+struct Occurrence {
+  start: usize,
+  end: usize,
+  def: Option<(usize, usize)>,
+  kind: SymbolKind,
+}
+
+impl Occurrence {
+  fn is_def(&self) -> bool {
+    self.def == Some((self.start, self.end))
+  }
+}
+
+/// A function definition, which limits the scope of the variables
+/// defined in it.
+struct FnRange {
+  start: usize,
+  end: usize,
+  name: usize,
+}
+
+/// Results of checking a document.
+struct Analysis {
+  diagnostics: Vec<Diagnostic>,
+  occurrences: Vec<Occurrence>,
+  fns: Vec<FnRange>,
+}
+
+impl Document {
+  fn new(text: String) -> Self {
+    let analysis = Analysis::new(&text);
+    Self { text, analysis }
+  }
+
+  /// Text to show and the range of the name at the offset.
+  fn hover(&self, offset: usize) -> Option<(String, Value)> {
+    let occ = self.analysis.occurrence_at(offset)?;
+    let name = &self.text[occ.start..occ.end];
+    let text = match &occ.kind {
+      SymbolKind::Variable(ty) => format!("{name}: {ty:?}"),
+      SymbolKind::Function(signature) => signature.clone(),
+    };
+    Some((text, range(&self.text, occ.start, occ.end)))
+  }
+
+  fn definition(&self, offset: usize) -> Option<Value> {
+    let (start, end) =
+      self.analysis.occurrence_at(offset)?.def?;
+    Some(range(&self.text, start, end))
+  }
+
+  /// Names that can be used at the offset.
+  fn completion(&self, offset: usize) -> Vec<Value> {
+    let analysis = &self.analysis;
+    let scope = analysis.enclosing_fn(offset).map(|f| f.start);
+    // Later definitions shadow the earlier ones.
+    let mut items: HashMap<String, Value> = HashMap::new();
+    for (name, f) in standard_functions() {
+      let item = completion_item(
+        &name,
+        COMPLETION_FUNCTION,
+        &f.signature(&name),
+      );
+      items.insert(name, item);
+    }
+    for occ in &analysis.occurrences {
+      if !occ.is_def() || offset <= occ.start {
+        continue;
+      }
+      let name = &self.text[occ.start..occ.end];
+      let item = match &occ.kind {
+        SymbolKind::Variable(ty) => {
+          let def_scope =
+            analysis.enclosing_fn(occ.start).map(|f| f.start);
+          if def_scope != scope {
+            continue;
+          }
+          completion_item(
+            name,
+            COMPLETION_VARIABLE,
+            &format!("{ty:?}"),
+          )
+        }
+        SymbolKind::Function(signature) => {
+          // A function is visible in the scope it is defined in.
+          let outer = analysis
+            .fns
+            .iter()
+            .find(|f| f.name == occ.start)
+            .and_then(|f| analysis.enclosing_fn(f.start));
+          if outer.is_some_and(|outer| !outer.contains(offset))
+          {
+            continue;
+          }
+          completion_item(name, COMPLETION_FUNCTION, signature)
+        }
+      };
+      items.insert(name.to_string(), item);
+    }
+    let mut items: Vec<_> = items.into_iter().collect();
+    items.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+    items.into_iter().map(|(_, item)| item).collect()
+  }
+}
+
+fn completion_item(
+  name: &str,
+  kind: u32,
+  detail: &str,
+) -> Value {
+  json!({ "label": name, "kind": kind, "detail": detail })
+}
+
+impl FnRange {
+  fn contains(&self, offset: usize) -> bool {
+    self.start < offset && offset < self.end
+  }
+}
+
+impl Analysis {
+  fn new(text: &str) -> Self {
+    let (stmts, syntax_errors) =
+      statements_recover(Span::new(text));
+    let mut ctx = TypeCheckContext::new();
+    let type_errors: Vec<_> = type_check(&stmts, &mut ctx)
+      .err()
+      .unwrap_or_default()
+      .iter()
+      .map(Diagnostic::from)
+      .collect();
+    // Type errors in a program with syntax errors are mostly caused
+    // by the parts that failed to parse, and lints are only run on
+    // well typed programs like in the compiler.
+    let diagnostics = if !syntax_errors.is_empty() {
+      syntax_errors
+    } else if !type_errors.is_empty() {
+      type_errors
+    } else {
+      lint(&stmts, &LintConfig::new())
+        .iter()
+        .map(Diagnostic::from)
+        .collect()
+    };
+
+    let offsets = |span: Span| {
+      (
+        span.location_offset(),
+        span.location_offset() + span.len(),
+      )
+    };
+    let occurrences = ctx
+      .symbols()
+      .iter()
+      .map(|symbol| {
+        let (start, end) = offsets(symbol.span);
+        Occurrence {
+          start,
+          end,
+          def: symbol.def.map(offsets),
+          kind: symbol.kind.clone(),
+        }
+      })
+      .collect();
+
+    let mut fns = vec![];
+    collect_fns(&stmts, &mut fns);
+    Self {
+      diagnostics,
+      occurrences,
+      fns,
+    }
+  }
+
+  fn occurrence_at(
+    &self,
+    offset: usize,
+  ) -> Option<&Occurrence> {
+    self
+      .occurrences
+      .iter()
+      .find(|occ| occ.start <= offset && offset < occ.end)
+  }
+
+  /// The innermost function containing the offset, or `None` at
+  /// the top level.
+  fn enclosing_fn(&self, offset: usize) -> Option<&FnRange> {
+    self
+      .fns
+      .iter()
+      .filter(|f| f.contains(offset))
+      .min_by_key(|f| f.end - f.start)
+  }
+}
+
+fn collect_fns(stmts: &[Statement], fns: &mut Vec<FnRange>) {
+  for stmt in stmts {
+    match stmt {
+      Statement::Expression(ex)
+      | Statement::VarDef { ex, .. }
+      | Statement::VarAssign { ex, .. }
+      | Statement::Return(ex)
+      | Statement::Yield(ex)
+      | Statement::Throw(ex) => collect_fns_expr(ex, fns),
+      Statement::For {
+        start, end, stmts, ..
+      } => {
+        collect_fns_expr(start, fns);
+        collect_fns_expr(end, fns);
+        collect_fns(stmts, fns);
+      }
+      Statement::FnDef {
+        span, name, stmts, ..
+      } => {
+        fns.push(FnRange {
+          start: span.location_offset(),
+          end: span.location_offset() + span.len(),
+          name: name.location_offset(),
+        });
+        collect_fns(stmts, fns);
+      }
+      Statement::Break(_) | Statement::Continue(_) => (),
+    }
+  }
+}
+
+fn collect_fns_expr(ex: &Expression, fns: &mut Vec<FnRange>) {
+  use ExprEnum::*;
+  match &ex.expr {
+    Ident(_) | NumLiteral(_) | StrLiteral(_) | NoneLiteral => {}
+    Interpolated(parts) => {
+      for part in parts {
+        if let StrPart::Expr(ex, _) = part {
+          collect_fns_expr(ex, fns);
+        }
+      }
+    }
+    SomeValue(ex) | Await(ex) => collect_fns_expr(ex, fns),
+    FnInvoke(_, args) => {
+      for arg in args {
+        collect_fns_expr(arg, fns);
+      }
+    }
+    Add(lhs, rhs)
+    | Sub(lhs, rhs)
+    | Mul(lhs, rhs)
+    | Div(lhs, rhs)
+    | Gt(lhs, rhs)
+    | Lt(lhs, rhs)
+    | Coalesce(lhs, rhs) => {
+      collect_fns_expr(lhs, fns);
+      collect_fns_expr(rhs, fns);
+    }
+    If(cond, true_branch, false_branch)
+    | IfLet(_, cond, true_branch, false_branch) => {
+      collect_fns_expr(cond, fns);
+      collect_fns(true_branch, fns);
+      if let Some(false_branch) = false_branch {
+        collect_fns(false_branch, fns);
+      }
+    }
+    Try(body, _, handler) => {
+      collect_fns(body, fns);
+      collect_fns(handler, fns);
+    }
+  }
+}
+
+/// LSP position of a byte offset. The protocol counts characters
+/// in UTF-16 code units.
+fn position(text: &str, offset: usize) -> Value {
+  let offset = offset.min(text.len());
+  let line_start =
+    text[..offset].rfind('\n').map_or(0, |i| i + 1);
+  let line = text[..line_start].matches('\n').count();
+  let character =
+    text[line_start..offset].encode_utf16().count();
+  json!({ "line": line, "character": character })
+}
+
+fn label_range(text: &str, label: &Label) -> Value {
+  range(text, label.offset, label.offset + label.len)
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+  json!({
+    "start": position(text, start),
+    "end": position(text, end),
+  })
+}
+
+/// Byte offset of an LSP position, or `None` if the line is out of
+/// the text. A character past the end of the line is clamped to it.
+fn offset(text: &str, position: &Value) -> Option<usize> {
+  let line = position["line"].as_u64()? as usize;
+  let character = position["character"].as_u64()? as usize;
+  let line_start = match line {
+    0 => 0,
+    _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+  };
+  let mut units = 0;
+  for (i, c) in text[line_start..].char_indices() {
+    if units >= character || c == '\n' {
+      return Some(line_start + i);
+    }
+    units += c.len_utf16();
+  }
+  Some(text.len())
+}
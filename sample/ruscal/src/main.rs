@@ -1,22 +1,46 @@
 use std::{
-  io::{BufReader, BufWriter},
+  cell::RefCell,
+  error::Error,
+  io::{BufReader, BufWriter, Write},
   rc::Rc,
 };
 
 use ruscal::{
-  file_io::{compile, read_program},
+  asm::{assemble, disasm_file, write_funcs},
+  ast::TypeDecl,
+  bytecode::ByteCode,
+  debugger::{Debugger, Quit},
+  diagnostics::Aborted,
+  file_io::{compile, format_file, read_program, show_ast},
   parse_args,
+  profiler::Profiler,
+  repl::Repl,
+  test_runner::run_tests,
+  tracer::{JsonTracer, TextTracer},
   value::Value,
-  vm::{debugger, Vm, YieldResult},
-  RunMode,
+  vm::{debugger, UncaughtError, Vm, YieldResult},
+  Args, RunMode,
 };
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn Error>> {
   let Some(args) = parse_args(true) else {
     return Ok(());
   };
 
-  let run_coro = |mut vm: Vm| {
+  let run_coro = |bytecode: Rc<ByteCode>| {
+    let mut vm = Vm::new(bytecode.clone(), Box::new(()));
+    if let Err(e) = add_tracers(&mut vm, &args) {
+      eprintln!("Trace error: {e}");
+      return;
+    }
+    if args.debugger {
+      vm.add_tracer(start_debugger(&args, &bytecode));
+    }
+    let profiler = args.profile.then(|| {
+      let profiler = Rc::new(RefCell::new(Profiler::new()));
+      vm.add_tracer(profiler.clone());
+      profiler
+    });
     if let Err(e) = vm.init_fn("main", &[]) {
       eprintln!("init_fn error: {e:?}");
     }
@@ -34,14 +58,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
           }
         }
         Err(e) => {
-          eprintln!("Runtime error: {e:?}");
+          if !e.is::<Quit>() {
+            eprintln!("Runtime error: {e:?}");
+          }
+          if e.is::<UncaughtError>() {
+            for frame in vm.error_backtrace() {
+              eprintln!("    at {frame}");
+            }
+          }
           break;
         }
       }
     }
+    if let Some(profiler) = profiler {
+      if let Err(e) =
+        write_profile(&args, &mut profiler.borrow_mut())
+      {
+        eprintln!("Profile error: {e}");
+      }
+    }
   };
 
-  match args.run_mode {
+  match &args.run_mode {
     RunMode::TypeCheck => {
       if let Err(e) =
         compile(&mut std::io::sink(), &args, &args.output)
@@ -58,37 +96,252 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
       }
     }
     RunMode::Run(code_file) => {
-      let reader = std::fs::File::open(&code_file)?;
+      let reader = std::fs::File::open(code_file)?;
       let mut reader = BufReader::new(reader);
-      let bytecode = Rc::new(read_program(&mut reader)?);
-      run_coro(Vm::new(
-        bytecode,
-        Box::new(()),
-        args.debug_output,
-      ));
-    }
-    RunMode::CompileAndRun => {
-      let mut buf = vec![];
-      if let Err(e) = compile(
-        &mut std::io::Cursor::new(&mut buf),
-        &args,
-        "<Memory>",
-      ) {
-        eprintln!("Compile Error: {e}");
-        return Ok(());
+      let bytecode = read_program(&mut reader)?;
+      if args.disasm {
+        bytecode.disasm(&mut std::io::stdout())?;
+      }
+      run_coro(Rc::new(bytecode));
+    }
+    RunMode::CompileAndRun => match compile_to_memory(&args) {
+      Ok(bytecode) => run_coro(Rc::new(bytecode)),
+      Err(e) => eprintln!("Compile Error: {e}"),
+    },
+    RunMode::Test => {
+      let passed = match compile_to_memory(&args) {
+        Ok(bytecode) => {
+          let src = args.source.as_deref().unwrap_or_default();
+          run_tests(src, Rc::new(bytecode))
+        }
+        Err(e) => {
+          eprintln!("Compile Error: {e}");
+          false
+        }
+      };
+      if !passed {
+        std::process::exit(1);
+      }
+    }
+    RunMode::Asm => {
+      let src = args
+        .source
+        .as_ref()
+        .ok_or("Please specify the file to assemble")?;
+      let funcs = match assemble(&std::fs::read_to_string(src)?)
+      {
+        Ok(funcs) => funcs,
+        Err(e) => {
+          eprintln!("{src}: {e}");
+          std::process::exit(1);
+        }
+      };
+      let mut writer =
+        BufWriter::new(std::fs::File::create(&args.output)?);
+      write_funcs(&funcs, &mut writer)?;
+      writer.flush()?;
+    }
+    RunMode::Disasm => {
+      let src = args
+        .source
+        .as_ref()
+        .ok_or("Please specify the file to disassemble")?;
+      let mut reader =
+        BufReader::new(std::fs::File::open(src)?);
+      disasm_file(&mut reader, &mut std::io::stdout())?;
+    }
+    RunMode::Repl => run_repl(&args)?,
+    RunMode::Format => match format_file(&args) {
+      Ok(false) if args.check => {
+        let src = args.source.as_deref().unwrap_or_default();
+        eprintln!("{src} is not formatted");
+        std::process::exit(1);
+      }
+      Ok(_) => (),
+      Err(e) => {
+        if !e.is::<Aborted>() {
+          eprintln!("Format error: {e}");
+        }
+        std::process::exit(1);
+      }
+    },
+    RunMode::None if args.show_ast => {
+      if let Err(e) = show_ast(&args) {
+        if !e.is::<Aborted>() {
+          eprintln!("Parse error: {e}");
+        }
+        std::process::exit(1);
       }
-      let bytecode = Rc::new(read_program(
-        &mut std::io::Cursor::new(&mut buf),
-      )?);
-      run_coro(Vm::new(
-        bytecode,
-        Box::new(()),
-        args.debug_output,
-      ));
     }
     _ => {
-      println!("Please specify -c, -r, -t or -R as an argument")
+      println!(
+        "Please specify -c, -r, -t, -R, -T, -i or -f as an argument"
+      )
+    }
+  }
+  Ok(())
+}
+
+/// Compile the source to bytecode without writing it to a file.
+fn compile_to_memory(
+  args: &Args,
+) -> Result<ByteCode, Box<dyn Error>> {
+  let mut buf = vec![];
+  compile(
+    &mut std::io::Cursor::new(&mut buf),
+    args,
+    "<Memory>",
+  )?;
+  Ok(read_program(&mut std::io::Cursor::new(&mut buf))?)
+}
+
+/// Add the tracers writing the execution, requested by `-D` and
+/// `--trace`.
+fn add_tracers(
+  vm: &mut Vm,
+  args: &Args,
+) -> std::io::Result<()> {
+  if args.debug_output {
+    vm.add_tracer(Rc::new(RefCell::new(TextTracer(
+      std::io::stderr(),
+    ))));
+  }
+  if let Some(trace) = &args.trace {
+    let writer = BufWriter::new(std::fs::File::create(trace)?);
+    vm.add_tracer(Rc::new(RefCell::new(JsonTracer::new(
+      writer,
+    ))));
+  }
+  Ok(())
+}
+
+/// Create a debugger showing the source file, if it can be read.
+fn start_debugger(
+  args: &Args,
+  bytecode: &ByteCode,
+) -> Rc<RefCell<Debugger>> {
+  let source_name =
+    args.source.as_deref().unwrap_or("<unknown>");
+  let source =
+    std::fs::read_to_string(source_name).unwrap_or_default();
+  println!(
+    "Running under the debugger; type help for commands"
+  );
+  Rc::new(RefCell::new(Debugger::new(
+    source_name,
+    &source,
+    bytecode,
+  )))
+}
+
+/// Print the profile to stderr, to keep it apart from the output
+/// of the program, and write the folded stacks if requested.
+fn write_profile(
+  args: &Args,
+  profiler: &mut Profiler,
+) -> std::io::Result<()> {
+  profiler.report(&mut std::io::stderr())?;
+  if let Some(folded) = &args.folded {
+    let mut writer =
+      BufWriter::new(std::fs::File::create(folded)?);
+    profiler.write_folded(&mut writer)?;
+    writer.flush()?;
+  }
+  Ok(())
+}
+
+const REPL_HELP: &str = r#"Enter statements to run them. The input continues
+while braces are open. Commands:
+    :type expr   Show the type of an expression
+    :disasm fn   Disassemble a function
+    :reset       Forget every definition
+    :help        Show this help
+    :quit        Exit"#;
+
+/// Read statements line by line and print the value and the type of
+/// each expression.
+fn run_repl(args: &Args) -> std::io::Result<()> {
+  let mut repl = Repl::new(args);
+  let mut input = String::new();
+  println!("Type :help for help");
+  loop {
+    print!("{}", if input.is_empty() { ">> " } else { ".. " });
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    if std::io::stdin().read_line(&mut line)? == 0 {
+      println!();
+      break;
+    }
+    input += &line;
+    if open_braces(&input) > 0 {
+      continue;
+    }
+    let src = std::mem::take(&mut input);
+    let src = src.trim();
+    if src.is_empty() {
+      continue;
+    }
+    let Some(command) = src.strip_prefix(':') else {
+      match repl.eval(src) {
+        // Values of untyped natives like print are not worth
+        // showing.
+        Ok(Some((value, ty))) if ty != TypeDecl::Any => {
+          println!("{value}: {ty:?}")
+        }
+        Ok(_) => (),
+        Err(e) => report_repl_error(e),
+      }
+      continue;
+    };
+    let (command, arg) =
+      command.split_once(' ').unwrap_or((command, ""));
+    let arg = arg.trim();
+    match command {
+      "type" | "t" => match repl.type_of(arg) {
+        Ok(ty) => println!("{ty:?}"),
+        Err(e) => report_repl_error(e),
+      },
+      "disasm" | "d" => {
+        if let Err(e) = repl.disasm(arg, &mut std::io::stdout())
+        {
+          report_repl_error(e);
+        }
+      }
+      "reset" => repl.reset(),
+      "help" | "h" => println!("{REPL_HELP}"),
+      "quit" | "q" => break,
+      _ => println!("Unknown command :{command}; try :help"),
     }
   }
   Ok(())
 }
+
+/// Print an error unless it was already shown as diagnostics.
+fn report_repl_error(e: Box<dyn Error>) {
+  if !e.is::<Aborted>() {
+    eprintln!("{e}");
+  }
+}
+
+/// Number of braces left open in the input, ignoring the ones in
+/// string literals and comments.
+fn open_braces(input: &str) -> i32 {
+  let mut depth = 0;
+  let mut in_str = false;
+  let mut comment = false;
+  let mut prev = None;
+  for (pos, c) in input.char_indices() {
+    match c {
+      '\n' if comment => comment = false,
+      _ if comment => (),
+      '"' if prev != Some('\\') => in_str = !in_str,
+      _ if in_str => (),
+      '/' if input[pos..].starts_with("//") => comment = true,
+      '{' => depth += 1,
+      '}' => depth -= 1,
+      _ => (),
+    }
+    prev = Some(c);
+  }
+  depth
+}
@@ -1,234 +1,639 @@
+use std::cell::{Cell, RefCell};
+
 use nom::{
   branch::alt,
   bytes::complete::tag,
   character::complete::{
-    alpha1, alphanumeric1, char, multispace0, multispace1,
-    none_of,
+    alpha1, alphanumeric1, char, multispace1, none_of,
+    not_line_ending, satisfy,
+  },
+  combinator::{
+    cut, map, map_res, not, opt, peek, recognize, value,
   },
-  combinator::{cut, map_res, opt, recognize},
-  error::ParseError,
-  multi::{fold_many0, many0, separated_list0},
+  error::{ErrorKind, FromExternalError},
+  multi::{fold_many1, many0, many0_count, separated_list0},
   number::complete::recognize_float,
-  sequence::{delimited, pair, preceded, terminated},
-  Finish, IResult, InputTake, Offset, Parser,
+  sequence::{delimited, pair, preceded, terminated, tuple},
+  InputTake, Offset, Parser,
 };
 
-use crate::ast::{
-  ExprEnum, Expression, Span, Statement, Statements, TypeDecl,
+use nom::error::ParseError as _;
+
+use crate::{
+  ast::{
+    ExprEnum, Expression, Span, Statement, Statements, StrPart,
+    TypeDecl,
+  },
+  diagnostics::{Diagnostic, Label},
 };
 
+type IResult<'a, O> = nom::IResult<Span<'a>, O, ParseError<'a>>;
+
+/// Error type of the parser, which records what was expected at the
+/// position to produce readable messages.
+#[derive(Debug, PartialEq)]
+pub struct ParseError<'src> {
+  pub input: Span<'src>,
+  pub kind: ErrorKind,
+  /// Description of what was expected, like "';' after expression"
+  pub expected: Option<String>,
+}
+
+impl<'src> ParseError<'src> {
+  fn expected(input: Span<'src>, expected: &str) -> Self {
+    Self {
+      input,
+      kind: ErrorKind::Verify,
+      expected: Some(expected.to_string()),
+    }
+  }
+
+  fn too_deep(input: Span<'src>) -> Self {
+    Self {
+      input,
+      kind: ErrorKind::TooLarge,
+      expected: None,
+    }
+  }
+
+  /// A missing `;` is recovered by pretending it was there.
+  fn is_missing_semicolon(&self) -> bool {
+    self
+      .expected
+      .as_ref()
+      .is_some_and(|expected| expected.starts_with("';'"))
+  }
+
+  /// Convert into a diagnostic, with the enclosing constructs being
+  /// parsed as secondary labels.
+  fn to_diagnostic(
+    &self,
+    context: &[(String, Label)],
+  ) -> Diagnostic {
+    let rest = &self.input[trivia_len(&self.input)..];
+    let token_len = if rest.is_empty() {
+      0
+    } else {
+      let word = rest
+        .find(|c: char| !c.is_alphanumeric() && c != '_')
+        .unwrap_or(rest.len());
+      word.max(rest.chars().next().map_or(0, char::len_utf8))
+    };
+    let found = if rest.is_empty() {
+      "end of input".to_string()
+    } else {
+      format!("`{}`", &rest[..token_len])
+    };
+    let msg = match &self.expected {
+      Some(expected) => {
+        format!("expected {expected}, found {found}")
+      }
+      None if self.kind == ErrorKind::TooLarge => format!(
+        "{found} is nested deeper than {MAX_DEPTH} levels"
+      ),
+      None => format!("unexpected {found}"),
+    };
+    // Point at the position right after the previous token if
+    // something was expected there, otherwise at the found token.
+    let span = if self.expected.is_some()
+      && rest.len() < self.input.len()
+    {
+      self.input.take(0)
+    } else {
+      let skip = self.input.len() - rest.len();
+      self.input.take_split(skip).0.take(token_len)
+    };
+    let mut diag = Diagnostic::error(msg).with_span(span);
+    for (msg, label) in context.iter().rev() {
+      let mut label = label.clone();
+      label.msg = msg.clone();
+      diag.secondary.push(label);
+    }
+    diag
+  }
+}
+
+impl<'src> nom::error::ParseError<Span<'src>>
+  for ParseError<'src>
+{
+  fn from_error_kind(
+    input: Span<'src>,
+    kind: ErrorKind,
+  ) -> Self {
+    Self {
+      input,
+      kind,
+      expected: None,
+    }
+  }
+
+  fn append(_: Span<'src>, _: ErrorKind, other: Self) -> Self {
+    other
+  }
+
+  fn or(self, other: Self) -> Self {
+    // Prefer the alternative that knows what it expected, then the
+    // one that got further.
+    match (&self.expected, &other.expected) {
+      (Some(_), None) => self,
+      (None, Some(_)) => other,
+      _ if self.input.location_offset()
+        > other.input.location_offset() =>
+      {
+        self
+      }
+      _ => other,
+    }
+  }
+}
+
+impl<'src, E> FromExternalError<Span<'src>, E>
+  for ParseError<'src>
+{
+  fn from_external_error(
+    input: Span<'src>,
+    kind: ErrorKind,
+    _: E,
+  ) -> Self {
+    <Self as nom::error::ParseError<_>>::from_error_kind(
+      input, kind,
+    )
+  }
+}
+
+thread_local! {
+  /// Syntax errors recovered from so far in `statements_finish`.
+  static ERRORS: RefCell<Vec<Diagnostic>> = const { RefCell::new(vec![]) };
+  /// Descriptions and locations of the constructs being parsed, like
+  /// the function whose body is being parsed, outermost first.
+  static CONTEXT: RefCell<Vec<(String, Label)>> = const { RefCell::new(vec![]) };
+  /// How deeply the expressions, blocks and types being parsed are
+  /// nested.
+  static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The deepest nesting accepted, so that neither the parser nor the
+/// passes that recurse over the AST can overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Go one level deeper in nesting at `i`, or fail if it is too deep.
+/// Every successful call is paired with a call to `leave`.
+fn enter(i: Span) -> Result<(), nom::Err<ParseError>> {
+  let depth = DEPTH.with(|depth| depth.get());
+  if depth >= MAX_DEPTH {
+    return Err(nom::Err::Failure(ParseError::too_deep(i)));
+  }
+  DEPTH.with(|d| d.set(depth + 1));
+  Ok(())
+}
+
+fn leave(levels: usize) {
+  DEPTH.with(|depth| depth.set(depth.get() - levels));
+}
+
+/// Run the parser one level deeper in nesting.
+fn nested<'a, O>(
+  mut f: impl FnMut(Span<'a>) -> IResult<'a, O>,
+) -> impl FnMut(Span<'a>) -> IResult<'a, O> {
+  move |i| {
+    enter(i)?;
+    let res = f(i);
+    leave(1);
+    res
+  }
+}
+
+fn record_error(e: &ParseError) {
+  let diag = CONTEXT.with(|ctx| e.to_diagnostic(&ctx.borrow()));
+  ERRORS.with(|errors| {
+    let mut errors = errors.borrow_mut();
+    // The same error can be found again when the parser backtracks.
+    let offset =
+      diag.primary.as_ref().map(|label| label.offset);
+    if !errors.iter().any(|e| {
+      e.primary.as_ref().map(|label| label.offset) == offset
+    }) {
+      errors.push(diag);
+    }
+  });
+}
+
+/// Run the parser with a description of the construct being parsed,
+/// which is attached to the syntax errors found in it.
+fn with_context<'a, O>(
+  desc: String,
+  span: Span<'a>,
+  f: impl FnOnce() -> IResult<'a, O>,
+) -> IResult<'a, O> {
+  CONTEXT.with(|ctx| {
+    ctx.borrow_mut().push((desc, Label::new(span, "")))
+  });
+  let res = f();
+  CONTEXT.with(|ctx| ctx.borrow_mut().pop());
+  res
+}
+
+/// Turn an error of the parser into a failure that says what was
+/// expected, so that the alternatives are not tried any further.
+fn expect<'a, O>(
+  mut f: impl Parser<Span<'a>, O, ParseError<'a>>,
+  expected: &'static str,
+) -> impl FnMut(Span<'a>) -> IResult<'a, O> {
+  move |i| {
+    f.parse(i).map_err(|e| match e {
+      nom::Err::Error(_) => {
+        nom::Err::Failure(ParseError::expected(i, expected))
+      }
+      e => e,
+    })
+  }
+}
+
+/// A keyword, which shall not be followed by identifier characters
+/// so that `for` does not match the start of `format`.
+fn keyword<'a>(
+  kw: &'static str,
+) -> impl FnMut(Span<'a>) -> IResult<'a, Span<'a>> {
+  space_delimited(terminated(
+    tag(kw),
+    not(satisfy(|c: char| c.is_alphanumeric() || c == '_')),
+  ))
+}
+
 pub trait GetSpan<'a> {
-  fn span(&self) -> Span<'a>;
+  fn span(&self) -> Option<Span<'a>>;
 }
 
 impl<'a> GetSpan<'a> for Statements<'a> {
-  fn span(&self) -> Span<'a> {
-    self.iter().find_map(|stmt| stmt.span()).unwrap()
+  /// The span of the first statement that has one, if any.
+  fn span(&self) -> Option<Span<'a>> {
+    self.iter().find_map(|stmt| stmt.span())
   }
 }
 
-fn space_delimited<'src, O, E>(
-  f: impl Parser<Span<'src>, O, E>,
-) -> impl FnMut(Span<'src>) -> IResult<Span<'src>, O, E>
-where
-  E: ParseError<Span<'src>>,
-{
-  delimited(multispace0, f, multispace0)
+/// Whitespace and `//` comments, which may appear between any
+/// tokens.
+fn ws(i: Span) -> IResult<Span> {
+  recognize(many0_count(alt((
+    multispace1,
+    recognize(pair(tag("//"), not_line_ending)),
+  ))))(i)
+}
+
+fn space_delimited<'src, O>(
+  f: impl Parser<Span<'src>, O, ParseError<'src>>,
+) -> impl FnMut(Span<'src>) -> IResult<'src, O> {
+  delimited(ws, f, ws)
+}
+
+/// Length of the whitespace and comments at the start of the text.
+pub(crate) fn trivia_len(text: &str) -> usize {
+  let mut rest = text;
+  loop {
+    let trimmed = rest.trim_start();
+    let Some(comment) = trimmed.strip_prefix("//") else {
+      return text.len() - trimmed.len();
+    };
+    rest = comment.find('\n').map_or("", |pos| &comment[pos..]);
+  }
+}
+
+/// Length of the text without the whitespace and comments at the
+/// end. The text shall start outside of a string literal.
+pub(crate) fn code_len(text: &str) -> usize {
+  let mut end = 0;
+  let mut in_str = false;
+  let mut comment = false;
+  for (pos, c) in text.char_indices() {
+    match c {
+      '\n' => comment = false,
+      _ if comment => (),
+      '"' => in_str = !in_str,
+      '/' if !in_str && text[pos..].starts_with("//") => {
+        comment = true;
+      }
+      _ => (),
+    }
+    if in_str || !(comment || c.is_whitespace()) {
+      end = pos + c.len_utf8();
+    }
+  }
+  end
 }
 
 /// Calculate offset between the start positions of the input spans and return a span between them.
+/// Whitespace and comments at either end are excluded.
 ///
 /// Note: `i` shall start earlier than `r`, otherwise wrapping would occur.
 pub(crate) fn calc_offset<'a>(
   i: Span<'a>,
   r: Span<'a>,
 ) -> Span<'a> {
-  i.take(i.offset(&r))
+  let span = i.take(i.offset(&r));
+  let (span, _) = span.take_split(trivia_len(&span));
+  span.take(code_len(&span))
+}
+
+/// Return a span from the start of `first` to the end of `last`,
+/// both of which shall be within `input`.
+fn merge_span<'a>(
+  input: Span<'a>,
+  first: Span<'a>,
+  last: Span<'a>,
+) -> Span<'a> {
+  let (first, _) = input.take_split(input.offset(&first));
+  first.take(first.offset(&last) + last.len())
 }
 
-fn factor(i: Span) -> IResult<Span, Expression> {
-  alt((str_literal, num_literal, func_call, ident, parens))(i)
+fn factor(i: Span) -> IResult<Expression> {
+  alt((
+    str_literal,
+    num_literal,
+    none_literal,
+    some_expr,
+    func_call,
+    ident,
+    parens,
+  ))(i)
 }
 
-fn func_call(i: Span) -> IResult<Span, Expression> {
+/// A function call, which it must be once a `(` follows the name, so
+/// that the arguments are not parsed again as something else.
+fn func_call(i: Span) -> IResult<Expression> {
   let (r, ident) = space_delimited(identifier)(i)?;
-  let (r, args) = space_delimited(delimited(
-    tag("("),
-    many0(delimited(
-      multispace0,
-      expr,
-      space_delimited(opt(tag(","))),
-    )),
-    tag(")"),
-  ))(r)?;
+  let (r, _) = space_delimited(char('('))(r)?;
+  let (r, args) =
+    many0(delimited(ws, expr, space_delimited(opt(tag(",")))))(
+      r,
+    )?;
+  let (r, _) = expect(
+    space_delimited(char(')')),
+    "',' or ')' after an argument",
+  )(r)?;
   Ok((
     r,
     Expression {
       expr: ExprEnum::FnInvoke(ident, args),
-      span: i,
+      span: calc_offset(i, r),
     },
   ))
 }
 
-fn ident(input: Span) -> IResult<Span, Expression> {
+fn ident(input: Span) -> IResult<Expression> {
   let (r, res) = space_delimited(identifier)(input)?;
   Ok((
     r,
     Expression {
       expr: ExprEnum::Ident(res),
-      span: input,
+      span: res,
     },
   ))
 }
 
-fn identifier(input: Span) -> IResult<Span, Span> {
+fn none_literal(input: Span) -> IResult<Expression> {
+  let (r, res) = space_delimited(identifier)(input)?;
+  if *res.fragment() != "none" {
+    return Err(nom::Err::Error(
+      nom::error::ParseError::from_error_kind(
+        input,
+        ErrorKind::Tag,
+      ),
+    ));
+  }
+  Ok((r, Expression::new(ExprEnum::NoneLiteral, res)))
+}
+
+fn some_expr(i: Span) -> IResult<Expression> {
+  let (r, _) = keyword("some")(i)?;
+  let (r, _) = space_delimited(char('('))(r)?;
+  let (r, ex) =
+    expect(space_delimited(expr), "an expression")(r)?;
+  let (r, _) = expect(space_delimited(char(')')), "')'")(r)?;
+  Ok((
+    r,
+    Expression::new(
+      ExprEnum::SomeValue(Box::new(ex)),
+      calc_offset(i, r),
+    ),
+  ))
+}
+
+fn identifier(input: Span) -> IResult<Span> {
   recognize(pair(
     alt((alpha1, tag("_"))),
     many0(alt((alphanumeric1, tag("_")))),
   ))(input)
 }
 
-fn str_literal(i: Span) -> IResult<Span, Expression> {
-  let (r0, _) = preceded(multispace0, char('\"'))(i)?;
-  let (r, val) = many0(none_of("\""))(r0)?;
-  let (r, _) = terminated(char('"'), multispace0)(r)?;
+fn str_literal(i: Span) -> IResult<Expression> {
+  let (r0, _) = preceded(ws, char('\"'))(i)?;
+  let (r, parts) = many0(alt((str_text, str_interp)))(r0)?;
+  let (r, _) = terminated(char('"'), ws)(r)?;
+  let expr = match &parts[..] {
+    [] => ExprEnum::StrLiteral(String::new()),
+    [StrPart::Text(text)] => ExprEnum::StrLiteral(text.clone()),
+    _ => ExprEnum::Interpolated(parts),
+  };
+  Ok((r, Expression::new(expr, calc_offset(i, r))))
+}
+
+/// A plain text segment of a string literal. The text is kept as
+/// it is written, braces included, so that the string can be used
+/// as a format string, which unescapes `{{` and `}}`. A `{` that
+/// does not start an embedded expression is plain text, and so are
+/// the placeholders without an expression, like `{}` or `{:.3}`.
+fn str_text(i: Span) -> IResult<StrPart> {
+  let (r, val) = fold_many1(
+    alt((
+      map(tag("{{"), |s: Span| s.to_string()),
+      map(
+        recognize(tuple((
+          char('{'),
+          opt(preceded(char(':'), many0(none_of("\"{}")))),
+          char('}'),
+        ))),
+        |s: Span| s.to_string(),
+      ),
+      map(preceded(not(str_interp), char('{')), |c| {
+        c.to_string()
+      }),
+      map(none_of("\"{"), |c| c.to_string()),
+    )),
+    String::new,
+    |acc, s| acc + &s,
+  )(i)?;
   Ok((
     r,
-    Expression::new(
-      ExprEnum::StrLiteral(
-        val
-          .iter()
-          .collect::<String>()
-          .replace("\\\\", "\\")
-          .replace("\\n", "\n"),
-      ),
-      i,
+    StrPart::Text(
+      val.replace("\\\\", "\\").replace("\\n", "\n"),
     ),
   ))
 }
 
-fn num_literal(input: Span) -> IResult<Span, Expression> {
+/// An embedded expression like `{x}` or `{x:.3}` in a string literal.
+fn str_interp(i: Span) -> IResult<StrPart> {
+  let (i, _) = char('{')(i)?;
+  // A brace before something that is not an expression is plain
+  // text, unless the nesting is too deep.
+  let (i, ex) =
+    space_delimited(expr)(i).map_err(|e| match e {
+      nom::Err::Failure(e) if e.kind != ErrorKind::TooLarge => {
+        nom::Err::Error(e)
+      }
+      e => e,
+    })?;
+  let (i, spec) = opt(preceded(
+    char(':'),
+    recognize(many0(none_of("\"{}"))),
+  ))(i)?;
+  let (i, _) = char('}')(i)?;
+  Ok((i, StrPart::Expr(ex, spec)))
+}
+
+fn num_literal(input: Span) -> IResult<Expression> {
   let (r, v) = space_delimited(recognize_float)(input)?;
   Ok((
     r,
     Expression::new(
       ExprEnum::NumLiteral(v.parse().map_err(|_| {
-        nom::Err::Error(nom::error::Error {
-          input,
-          code: nom::error::ErrorKind::Digit,
-        })
+        nom::Err::Error(
+          nom::error::ParseError::from_error_kind(
+            input,
+            ErrorKind::Digit,
+          ),
+        )
       })?),
       v,
     ),
   ))
 }
 
-fn parens(i: Span) -> IResult<Span, Expression> {
+fn parens(i: Span) -> IResult<Expression> {
   space_delimited(delimited(tag("("), expr, tag(")")))(i)
 }
 
-fn term(i: Span) -> IResult<Span, Expression> {
-  let (r, init) = factor(i)?;
+/// The constructor of a binary operator's expression.
+type BinOp<'a> =
+  fn(Box<Expression<'a>>, Box<Expression<'a>>) -> ExprEnum<'a>;
 
-  let res = fold_many0(
-    pair(space_delimited(alt((char('*'), char('/')))), factor),
-    move || init.clone(),
-    |acc, (op, val): (char, Expression)| {
-      let span = calc_offset(i, acc.span);
-      match op {
-        '*' => Expression::new(
-          ExprEnum::Mul(Box::new(acc), Box::new(val)),
-          span,
-        ),
-        '/' => Expression::new(
-          ExprEnum::Div(Box::new(acc), Box::new(val)),
-          span,
-        ),
-        _ => panic!(
-          "Multiplicative expression should have '*' \
-              or '/' operator"
-        ),
-      }
-    },
-  )(r);
+/// The operator `op`, which yields the constructor `f`.
+fn bin_op<'a>(
+  op: char,
+  f: BinOp<'a>,
+) -> impl FnMut(Span<'a>) -> IResult<'a, BinOp<'a>> {
+  value(f, space_delimited(char(op)))
+}
+
+/// Left associative operators between the operands. Each operator
+/// nests the expression one level deeper.
+fn fold_bin_ops<'a>(
+  i0: Span<'a>,
+  operand: fn(Span<'a>) -> IResult<'a, Expression<'a>>,
+  mut op: impl FnMut(Span<'a>) -> IResult<'a, BinOp<'a>>,
+) -> IResult<'a, Expression<'a>> {
+  let (mut i, mut acc) = operand(i0)?;
+  let mut levels = 0;
+  let res = loop {
+    let (r, (op, val)) = match pair(&mut op, operand)(i) {
+      Ok(res) => res,
+      Err(nom::Err::Error(_)) => break Ok((i, acc)),
+      Err(e) => break Err(e),
+    };
+    if let Err(e) = enter(i) {
+      break Err(e);
+    }
+    levels += 1;
+    let span = merge_span(i0, acc.span, val.span);
+    acc =
+      Expression::new(op(Box::new(acc), Box::new(val)), span);
+    i = r;
+  };
+  leave(levels);
   res
 }
 
-fn num_expr(i: Span) -> IResult<Span, Expression> {
-  let (r, init) = term(i)?;
+fn term(i: Span) -> IResult<Expression> {
+  fold_bin_ops(
+    i,
+    factor,
+    alt((
+      bin_op('*', ExprEnum::Mul),
+      bin_op('/', ExprEnum::Div),
+    )),
+  )
+}
 
-  let res = fold_many0(
-    pair(space_delimited(alt((char('+'), char('-')))), term),
-    move || init.clone(),
-    |acc, (op, val): (char, Expression)| {
-      let span = calc_offset(i, acc.span);
-      match op {
-        '+' => Expression::new(
-          ExprEnum::Add(Box::new(acc), Box::new(val)),
-          span,
-        ),
-        '-' => Expression::new(
-          ExprEnum::Sub(Box::new(acc), Box::new(val)),
-          span,
-        ),
-        _ => panic!(
-          "Additive expression should have '+' or '-' operator"
-        ),
-      }
-    },
-  )(r);
-  res
+fn num_expr(i: Span) -> IResult<Expression> {
+  fold_bin_ops(
+    i,
+    term,
+    alt((
+      bin_op('+', ExprEnum::Add),
+      bin_op('-', ExprEnum::Sub),
+    )),
+  )
 }
 
-fn cond_expr(i0: Span) -> IResult<Span, Expression> {
+/// A comparison, or just its left operand if there is none, which is
+/// parsed only once.
+fn cond_expr(i0: Span) -> IResult<Expression> {
   let (i, first) = num_expr(i0)?;
-  let (i, cond) =
-    space_delimited(alt((char('<'), char('>'))))(i)?;
-  let (i, second) = num_expr(i)?;
-  let span = calc_offset(i0, i);
+  let (i, rhs) = opt(pair(
+    alt((bin_op('<', ExprEnum::Lt), bin_op('>', ExprEnum::Gt))),
+    num_expr,
+  ))(i)?;
+  let Some((op, second)) = rhs else {
+    return Ok((i, first));
+  };
+  let span = merge_span(i0, first.span, second.span);
   Ok((
     i,
-    match cond {
-      '<' => Expression::new(
-        ExprEnum::Lt(Box::new(first), Box::new(second)),
-        span,
-      ),
-      '>' => Expression::new(
-        ExprEnum::Gt(Box::new(first), Box::new(second)),
-        span,
-      ),
-      _ => unreachable!(),
-    },
+    Expression::new(
+      op(Box::new(first), Box::new(second)),
+      span,
+    ),
   ))
 }
 
-fn open_brace(i: Span) -> IResult<Span, ()> {
+/// A block of statements enclosed in braces.
+fn block(i: Span) -> IResult<Statements> {
+  delimited(
+    open_brace,
+    nested(statements),
+    expect(close_brace, "'}'"),
+  )(i)
+}
+
+fn open_brace(i: Span) -> IResult<()> {
   let (i, _) = space_delimited(char('{'))(i)?;
   Ok((i, ()))
 }
 
-fn close_brace(i: Span) -> IResult<Span, ()> {
+fn close_brace(i: Span) -> IResult<()> {
   let (i, _) = space_delimited(char('}'))(i)?;
   Ok((i, ()))
 }
 
-fn if_expr(i0: Span) -> IResult<Span, Expression> {
-  let (i, _) = space_delimited(tag("if"))(i0)?;
-  let (i, cond) = expr(i)?;
-  let (i, t_case) =
-    delimited(open_brace, statements, close_brace)(i)?;
-  let (i, f_case) = opt(preceded(
-    space_delimited(tag("else")),
+fn else_clause(i: Span) -> IResult<Option<Statements>> {
+  nested(opt(preceded(
+    keyword("else"),
     alt((
-      delimited(open_brace, statements, close_brace),
+      block,
       map_res(
-        if_expr,
+        alt((if_let_expr, if_expr)),
         |v| -> Result<Vec<Statement>, nom::error::Error<&str>> {
           Ok(vec![Statement::Expression(v)])
         },
       ),
     )),
-  ))(i)?;
+  )))(i)
+}
+
+fn if_expr(i0: Span) -> IResult<Expression> {
+  let (i, _) = keyword("if")(i0)?;
+  let (i, cond) = expr(i)?;
+  let (i, t_case) = block(i)?;
+  let (i, f_case) = else_clause(i)?;
 
   Ok((
     i,
@@ -243,9 +648,35 @@ fn if_expr(i0: Span) -> IResult<Span, Expression> {
   ))
 }
 
-fn await_expr(i: Span) -> IResult<Span, Expression> {
+fn if_let_expr(i0: Span) -> IResult<Expression> {
+  let (i, _) = keyword("if")(i0)?;
+  let (i, _) = keyword("let")(i)?;
+  let (i, (name, ex, t_case, f_case)) = cut(|i| {
+    let (i, name) = space_delimited(identifier)(i)?;
+    let (i, _) = space_delimited(char('='))(i)?;
+    let (i, ex) = expr(i)?;
+    let (i, t_case) = block(i)?;
+    let (i, f_case) = else_clause(i)?;
+    Ok((i, (name, ex, t_case, f_case)))
+  })(i)?;
+
+  Ok((
+    i,
+    Expression::new(
+      ExprEnum::IfLet(
+        name,
+        Box::new(ex),
+        Box::new(t_case),
+        f_case.map(Box::new),
+      ),
+      calc_offset(i0, i),
+    ),
+  ))
+}
+
+fn await_expr(i: Span) -> IResult<Expression> {
   let i0 = i;
-  let (i, _) = space_delimited(tag("await"))(i)?;
+  let (i, _) = keyword("await")(i)?;
   let (i, ex) = cut(space_delimited(expr))(i)?;
   Ok((
     i,
@@ -256,21 +687,73 @@ fn await_expr(i: Span) -> IResult<Span, Expression> {
   ))
 }
 
-fn expr(i: Span) -> IResult<Span, Expression> {
-  alt((await_expr, if_expr, cond_expr, num_expr))(i)
+fn try_expr(i0: Span) -> IResult<Expression> {
+  let (i, _) = keyword("try")(i0)?;
+  let (i, (body, name, handler)) = cut(|i| {
+    let (i, body) = block(i)?;
+    let (i, _) = keyword("catch")(i)?;
+    let (i, name) = space_delimited(identifier)(i)?;
+    let (i, handler) = block(i)?;
+    Ok((i, (body, name, handler)))
+  })(i)?;
+  Ok((
+    i,
+    Expression::new(
+      ExprEnum::Try(Box::new(body), name, Box::new(handler)),
+      calc_offset(i0, i),
+    ),
+  ))
+}
+
+/// `lhs ?? rhs`, which is right associative and binds looser than
+/// comparisons.
+fn coalesce_expr(i0: Span) -> IResult<Expression> {
+  let (i, lhs) = cond_expr(i0)?;
+  let (i, rhs) = opt(preceded(
+    space_delimited(tag("??")),
+    nested(coalesce_expr),
+  ))(i)?;
+  Ok(match rhs {
+    Some(rhs) => {
+      let span = merge_span(i0, lhs.span, rhs.span);
+      (
+        i,
+        Expression::new(
+          ExprEnum::Coalesce(Box::new(lhs), Box::new(rhs)),
+          span,
+        ),
+      )
+    }
+    None => (i, lhs),
+  })
+}
+
+fn expr(i: Span) -> IResult<Expression> {
+  nested(alt((
+    await_expr,
+    if_let_expr,
+    if_expr,
+    try_expr,
+    coalesce_expr,
+  )))(i)
 }
 
-fn var_def(i: Span) -> IResult<Span, Statement> {
+fn var_def(i: Span) -> IResult<Statement> {
   let span = i;
-  let (i, _) =
-    delimited(multispace0, tag("var"), multispace1)(i)?;
+  let (i, _) = keyword("var")(i)?;
   let (i, (name, td, ex)) = cut(|i| {
-    let (i, name) = space_delimited(identifier)(i)?;
-    let (i, _) = space_delimited(char(':'))(i)?;
-    let (i, td) = type_decl(i)?;
-    let (i, _) = space_delimited(char('='))(i)?;
-    let (i, ex) = space_delimited(expr)(i)?;
-    let (i, _) = space_delimited(char(';'))(i)?;
+    let (i, name) = expect(
+      space_delimited(identifier),
+      "a variable name after `var`",
+    )(i)?;
+    let (i, td) =
+      opt(preceded(space_delimited(char(':')), type_decl))(i)?;
+    let (i, _) = expect(space_delimited(char('=')), "'='")(i)?;
+    let (i, ex) = expect(
+      space_delimited(expr),
+      "an initializer expression",
+    )(i)?;
+    let (i, _) = semicolon("';' after variable definition")(i)?;
     Ok((i, (name, td, ex)))
   })(i)?;
   Ok((
@@ -284,12 +767,15 @@ fn var_def(i: Span) -> IResult<Span, Statement> {
   ))
 }
 
-fn var_assign(i: Span) -> IResult<Span, Statement> {
+fn var_assign(i: Span) -> IResult<Statement> {
   let span = i;
   let (i, name) = space_delimited(identifier)(i)?;
   let (i, _) = space_delimited(char('='))(i)?;
-  let (i, ex) = space_delimited(expr)(i)?;
-  let (i, _) = space_delimited(char(';'))(i)?;
+  let (i, ex) = expect(
+    space_delimited(expr),
+    "an expression after '='",
+  )(i)?;
+  let (i, _) = semicolon("';' after assignment")(i)?;
   Ok((
     i,
     Statement::VarAssign {
@@ -300,22 +786,30 @@ fn var_assign(i: Span) -> IResult<Span, Statement> {
   ))
 }
 
-fn expr_statement(i: Span) -> IResult<Span, Statement> {
+fn expr_statement(i: Span) -> IResult<Statement> {
   let (i, res) = expr(i)?;
   Ok((i, Statement::Expression(res)))
 }
 
-fn for_statement(i: Span) -> IResult<Span, Statement> {
+fn for_statement(i: Span) -> IResult<Statement> {
   let i0 = i;
-  let (i, _) = space_delimited(tag("for"))(i)?;
+  let (i, _) = keyword("for")(i)?;
   let (i, (loop_var, start, end, stmts)) = cut(|i| {
-    let (i, loop_var) = space_delimited(identifier)(i)?;
-    let (i, _) = space_delimited(tag("in"))(i)?;
-    let (i, start) = space_delimited(expr)(i)?;
-    let (i, _) = space_delimited(tag("to"))(i)?;
-    let (i, end) = space_delimited(expr)(i)?;
-    let (i, stmts) =
-      delimited(open_brace, statements, close_brace)(i)?;
+    let (i, loop_var) = expect(
+      space_delimited(identifier),
+      "a loop variable",
+    )(i)?;
+    let (i, _) = expect(keyword("in"), "`in`")(i)?;
+    let (i, start) =
+      expect(space_delimited(expr), "a start expression")(i)?;
+    let (i, _) = expect(keyword("to"), "`to`")(i)?;
+    let (i, end) =
+      expect(space_delimited(expr), "an end expression")(i)?;
+    let (i, stmts) = with_context(
+      "in this for loop".to_string(),
+      calc_offset(i0, i),
+      || expect(block, "'{' to start the loop body")(i),
+    )?;
     Ok((i, (loop_var, start, end, stmts)))
   })(i)?;
   Ok((
@@ -330,133 +824,458 @@ fn for_statement(i: Span) -> IResult<Span, Statement> {
   ))
 }
 
-fn type_decl(i: Span) -> IResult<Span, TypeDecl> {
-  let (i, td) = space_delimited(identifier)(i)?;
+fn type_decl(i: Span) -> IResult<TypeDecl> {
+  let (mut i, td) = space_delimited(identifier)(i)?;
+  let td = match *td.fragment() {
+    "i64" => TypeDecl::I64,
+    "f64" => TypeDecl::F64,
+    "str" => TypeDecl::Str,
+    "cofn" => {
+      let (r, yield_type) = opt(delimited(
+        space_delimited(char('<')),
+        nested(type_decl),
+        space_delimited(char('>')),
+      ))(i)?;
+      i = r;
+      TypeDecl::Coro(Box::new(
+        yield_type.unwrap_or(TypeDecl::Any),
+      ))
+    }
+    "err" => TypeDecl::Error,
+    _ => {
+      return Err(nom::Err::Failure(ParseError::expected(
+        td,
+        "a type name (i64, f64, str, cofn or err)",
+      )));
+    }
+  };
+  let (i, optional) = opt(space_delimited(char('?')))(i)?;
   Ok((
     i,
-    match *td.fragment() {
-      "i64" => TypeDecl::I64,
-      "f64" => TypeDecl::F64,
-      "str" => TypeDecl::Str,
-      "cofn" => TypeDecl::Coro,
-      _ => {
-        return Err(nom::Err::Failure(nom::error::Error::new(
-          td,
-          nom::error::ErrorKind::Verify,
-        )));
-      }
+    if optional.is_some() {
+      TypeDecl::Opt(Box::new(td))
+    } else {
+      td
     },
   ))
 }
 
-fn argument(i: Span) -> IResult<Span, (Span, TypeDecl)> {
+fn argument(i: Span) -> IResult<(Span, TypeDecl)> {
   let (i, ident) = space_delimited(identifier)(i)?;
-  let (i, _) = char(':')(i)?;
+  let (i, _) =
+    expect(char(':'), "':' and the type of the argument")(i)?;
   let (i, td) = type_decl(i)?;
 
   Ok((i, (ident, td)))
 }
 
-fn fn_def_statement(i: Span) -> IResult<Span, Statement> {
-  let (i, fn_kw) =
-    space_delimited(alt((tag("cofn"), tag("fn"))))(i)?;
+fn fn_def_statement(i0: Span) -> IResult<Statement> {
+  let (i, test) =
+    opt(terminated(keyword("test"), peek(keyword("fn"))))(i0)?;
+  let (i, fn_kw) = if test.is_some() {
+    keyword("fn")(i)?
+  } else {
+    alt((keyword("cofn"), keyword("fn")))(i)?
+  };
   let (i, (name, args, ret_type, stmts)) = cut(|i| {
-    let (i, name) = space_delimited(identifier)(i)?;
-    let (i, _) = space_delimited(tag("("))(i)?;
-    let (i, args) =
-      separated_list0(char(','), space_delimited(argument))(i)?;
-    let (i, _) = space_delimited(tag(")"))(i)?;
-    let (i, _) = space_delimited(tag("->"))(i)?;
-    let (i, ret_type) = type_decl(i)?;
+    let (i, name) = expect(
+      space_delimited(identifier),
+      "a function name",
+    )(i)?;
+    let (i, _) = expect(space_delimited(tag("(")), "'('")(i)?;
+    // Test functions are called without arguments and their
+    // values are discarded.
+    let (i, args, ret_type) = if test.is_some() {
+      let (i, _) = expect(
+        space_delimited(tag(")")),
+        "')', since test functions take no arguments",
+      )(i)?;
+      (i, vec![], TypeDecl::Any)
+    } else {
+      let (i, args) = separated_list0(
+        char(','),
+        space_delimited(argument),
+      )(i)?;
+      let (i, _) = expect(
+        space_delimited(tag(")")),
+        "')' after the arguments",
+      )(i)?;
+      let (i, _) = expect(
+        space_delimited(tag("->")),
+        "'->' and the return type",
+      )(i)?;
+      let (i, ret_type) = type_decl(i)?;
+      (i, args, ret_type)
+    };
     let (i, stmts) =
-      delimited(open_brace, statements, close_brace)(i)?;
+      with_context(format!("in fn `{name}`"), name, || {
+        expect(block, "'{' to start the function body")(i)
+      })?;
     Ok((i, (name, args, ret_type, stmts)))
   })(i)?;
   Ok((
     i,
     Statement::FnDef {
+      span: calc_offset(i0, i),
       name,
       args,
       ret_type,
       stmts,
       cofn: *fn_kw == "cofn",
+      test: test.is_some(),
     },
   ))
 }
 
-fn return_statement(i: Span) -> IResult<Span, Statement> {
-  let (i, _) = space_delimited(tag("return"))(i)?;
+fn return_statement(i: Span) -> IResult<Statement> {
+  let (i, _) = keyword("return")(i)?;
   let (i, ex) = space_delimited(expr)(i)?;
   Ok((i, Statement::Return(ex)))
 }
 
-fn break_statement(i: Span) -> IResult<Span, Statement> {
-  let (i, _) = space_delimited(tag("break"))(i)?;
-  Ok((i, Statement::Break))
+fn throw_statement(i: Span) -> IResult<Statement> {
+  let (i, _) = keyword("throw")(i)?;
+  let (i, ex) = cut(space_delimited(expr))(i)?;
+  Ok((i, Statement::Throw(ex)))
+}
+
+fn break_statement(i: Span) -> IResult<Statement> {
+  let (i, span) = keyword("break")(i)?;
+  Ok((i, Statement::Break(span)))
 }
 
-fn continue_statement(i: Span) -> IResult<Span, Statement> {
-  let (i, _) = space_delimited(tag("continue"))(i)?;
-  Ok((i, Statement::Continue))
+fn continue_statement(i: Span) -> IResult<Statement> {
+  let (i, span) = keyword("continue")(i)?;
+  Ok((i, Statement::Continue(span)))
 }
 
-fn yield_statement(i: Span) -> IResult<Span, Statement> {
-  let (i, _) = space_delimited(tag("yield"))(i)?;
+fn yield_statement(i: Span) -> IResult<Statement> {
+  let (i, _) = keyword("yield")(i)?;
   let (i, ex) = cut(space_delimited(expr))(i)?;
   Ok((i, Statement::Yield(ex)))
 }
 
-fn general_statement<'a>(
-  last: bool,
-) -> impl Fn(Span<'a>) -> IResult<Span<'a>, Statement> {
-  let terminator = move |i| -> IResult<Span, ()> {
-    let mut semicolon = pair(tag(";"), multispace0);
-    if last {
-      Ok((opt(semicolon)(i)?.0, ()))
+/// A `;` terminating a statement.
+fn semicolon<'a>(
+  expected: &'static str,
+) -> impl FnMut(Span<'a>) -> IResult<'a, ()> {
+  map(expect(space_delimited(char(';')), expected), |_| ())
+}
+
+/// A statement, and whether it is properly terminated. Statements
+/// that end with an expression need a `;`, except the last one in a
+/// block, which the caller decides.
+fn statement(input: Span) -> IResult<(Statement, bool)> {
+  fn terminated<'a>(
+    f: impl Fn(Span<'a>) -> IResult<'a, Statement>,
+  ) -> impl FnMut(Span<'a>) -> IResult<'a, (Statement, bool)>
+  {
+    pair(f, map(opt(pair(tag(";"), ws)), |t| t.is_some()))
+  }
+  fn complete<'a>(
+    f: impl Fn(Span<'a>) -> IResult<'a, Statement>,
+  ) -> impl FnMut(Span<'a>) -> IResult<'a, (Statement, bool)>
+  {
+    map(f, |stmt| (stmt, true))
+  }
+  alt((
+    complete(var_def),
+    complete(var_assign),
+    complete(fn_def_statement),
+    complete(for_statement),
+    terminated(return_statement),
+    terminated(break_statement),
+    terminated(continue_statement),
+    terminated(yield_statement),
+    terminated(throw_statement),
+    terminated(expr_statement),
+  ))(input)
+}
+
+/// Whether the input is at the end of a block or the program.
+fn at_block_end(i: Span) -> bool {
+  let rest = &i[trivia_len(&i)..];
+  rest.is_empty() || rest.starts_with('}')
+}
+
+/// Statements until the end of the block or the program. A syntax
+/// error in a statement is recorded and the parser resumes at the
+/// next statement, so this never fails.
+fn statements(i: Span) -> IResult<Statements> {
+  let mut stmts = vec![];
+  let (mut i, _) = ws(i)?;
+  while !at_block_end(i) {
+    let e = match statement(i) {
+      Ok((r, (stmt, terminated))) => {
+        stmts.push(stmt);
+        // The last statement in a block can omit `;`, so it is
+        // missing if there are more statements.
+        if terminated || at_block_end(r) {
+          i = r;
+          continue;
+        }
+        ParseError::expected(r, "';' after expression")
+      }
+      Err(nom::Err::Error(e) | nom::Err::Failure(e)) => e,
+      Err(nom::Err::Incomplete(_)) => break,
+    };
+    if e.is_missing_semicolon() {
+      record_error(&ParseError {
+        input: after_last_token(i, e.input),
+        expected: e.expected.clone(),
+        ..e
+      });
     } else {
-      Ok((semicolon(i)?.0, ()))
+      record_error(&e);
     }
-  };
-  move |input| {
-    alt((
-      var_def,
-      var_assign,
-      fn_def_statement,
-      for_statement,
-      terminated(return_statement, terminator),
-      terminated(break_statement, terminator),
-      terminated(continue_statement, terminator),
-      terminated(yield_statement, terminator),
-      terminated(expr_statement, terminator),
-    ))(input)
+    // A missing `;` at the end of a line is most likely just
+    // forgotten, so resume at the next line.
+    i = if e.is_missing_semicolon()
+      && after_line_break(i, e.input)
+    {
+      e.input
+    } else {
+      synchronize(i, e.input)
+    };
+    i = ws(i)?.0;
   }
+  Ok((i, stmts))
 }
 
-pub(crate) fn last_statement(
-  input: Span,
-) -> IResult<Span, Statement> {
-  general_statement(true)(input)
+/// The position right after the last token before `pos` in the
+/// statement starting at `start`, where a missing `;` belongs.
+fn after_last_token<'a>(
+  start: Span<'a>,
+  pos: Span<'a>,
+) -> Span<'a> {
+  start.take_split(code_len(&start[..start.offset(&pos)])).0
 }
 
-pub(crate) fn statement(
-  input: Span,
-) -> IResult<Span, Statement> {
-  general_statement(false)(input)
+/// Whether there is a line break right before `pos`, ignoring
+/// other whitespace.
+fn after_line_break(start: Span, pos: Span) -> bool {
+  let before = &start[..start.offset(&pos)];
+  before[before.trim_end().len()..].contains('\n')
 }
 
-fn statements(i: Span) -> IResult<Span, Statements> {
-  let (i, mut stmts) = many0(statement)(i)?;
-  let (i, last) = opt(last_statement)(i)?;
-  let (i, _) = opt(multispace0)(i)?;
-  if let Some(last) = last {
-    stmts.push(last);
+/// Skip the input from the error position to the start of the next
+/// statement, which is after a `;` or before a `}` at the nesting
+/// level of `start`, where the erroneous statement began.
+fn synchronize<'a>(
+  start: Span<'a>,
+  error: Span<'a>,
+) -> Span<'a> {
+  let mut depth = 0i32;
+  let mut in_str = false;
+  let mut comment = false;
+  let mut prev = None;
+  for (pos, c) in start.char_indices() {
+    let at_error = pos >= start.offset(&error);
+    match c {
+      '\n' if comment => comment = false,
+      _ if comment => (),
+      '"' if prev != Some('\\') => in_str = !in_str,
+      _ if in_str => (),
+      '/' if start[pos..].starts_with("//") => comment = true,
+      // An unclosed parenthesis does not hide the `;` after it.
+      '{' => depth += 1,
+      '}' if depth <= 0 && pos > 0 => {
+        return start.take_split(pos).0;
+      }
+      '}' => depth -= 1,
+      ';' if depth <= 0 && at_error => {
+        return start.take_split(pos + 1).0;
+      }
+      _ => (),
+    }
+    prev = Some(c);
   }
-  Ok((i, stmts))
+  start.take_split(start.len()).0
 }
 
+/// Parse the whole program, reporting all the syntax errors found.
 pub fn statements_finish(
   i: Span,
-) -> Result<Statements, nom::error::Error<Span>> {
-  let (_, res) = statements(i).finish()?;
-  Ok(res)
+) -> Result<Statements, Vec<Diagnostic>> {
+  let (stmts, errors) = statements_recover(i);
+  if errors.is_empty() {
+    Ok(stmts)
+  } else {
+    Err(errors)
+  }
+}
+
+/// Parse the whole program and return the statements that could be
+/// parsed along with the syntax errors, for tools that work on
+/// incomplete programs.
+pub fn statements_recover(
+  i: Span,
+) -> (Statements, Vec<Diagnostic>) {
+  ERRORS.with(|errors| errors.borrow_mut().clear());
+  CONTEXT.with(|ctx| ctx.borrow_mut().clear());
+  DEPTH.with(|depth| depth.set(0));
+  let mut stmts = vec![];
+  let mut i = i;
+  loop {
+    let (r, mut res) = match statements(i) {
+      Ok(res) => res,
+      Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+        record_error(&e);
+        break;
+      }
+      Err(nom::Err::Incomplete(_)) => break,
+    };
+    stmts.append(&mut res);
+    if r.is_empty() {
+      break;
+    }
+    // A `}` without the matching `{`
+    record_error(&ParseError::from_error_kind(
+      r,
+      ErrorKind::Char,
+    ));
+    i = r.take_split(1).0;
+  }
+  let errors = ERRORS.with(|errors| errors.take());
+  (stmts, errors)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  /// The parts of a string literal, with the embedded expressions
+  /// in angle brackets.
+  fn str_parts(src: &str) -> Vec<String> {
+    let (_, ex) = str_literal(Span::new(src)).unwrap();
+    match ex.expr {
+      ExprEnum::StrLiteral(text) => vec![text],
+      ExprEnum::Interpolated(parts) => parts
+        .iter()
+        .map(|part| match part {
+          StrPart::Text(text) => text.clone(),
+          StrPart::Expr(ex, _) => {
+            format!("<{}>", ex.span.trim())
+          }
+        })
+        .collect(),
+      ex => panic!("{src}: {ex:?}"),
+    }
+  }
+
+  #[test]
+  fn test_lone_braces() {
+    assert_eq!(str_parts(r#""a { b""#), ["a { b"]);
+    assert_eq!(str_parts(r#""a } b""#), ["a } b"]);
+    assert_eq!(str_parts(r#""{1 +} {""#), ["{1 +} {"]);
+    assert_eq!(str_parts(r#""{x} {""#), ["<x>", " {"]);
+    assert!(statements_finish(Span::new(r#"print("a { b");"#))
+      .is_ok());
+  }
+
+  #[test]
+  fn test_escaped_braces() {
+    assert_eq!(str_parts(r#""x {{ y }}""#), ["x {{ y }}"]);
+    assert_eq!(str_parts(r#""{} {:.3}""#), ["{} {:.3}"]);
+  }
+
+  #[test]
+  fn test_escaped_braces_with_interpolation() {
+    assert_eq!(
+      str_parts(r#""{n} {{ y }}""#),
+      ["<n>", " {{ y }}"]
+    );
+    assert_eq!(str_parts(r#""{{n}} {n}""#), ["{{n}} ", "<n>"]);
+  }
+
+  /// The line, column and message of each syntax error.
+  fn errors(src: &str) -> Vec<(u32, usize, String)> {
+    statements_finish(Span::new(src))
+      .unwrap_err()
+      .into_iter()
+      .map(|diag| {
+        let label = diag.primary.unwrap();
+        (label.line, label.column, diag.msg)
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_missing_semicolon_after_previous_token() {
+    assert_eq!(
+      errors("var x = 1 // one\nprint(x);"),
+      [(
+        1,
+        10,
+        "expected ';' after variable definition, found `print`"
+          .to_string()
+      )]
+    );
+    assert_eq!(
+      errors("x = 3\n\n  print(x);"),
+      [(
+        1,
+        6,
+        "expected ';' after assignment, found `print`"
+          .to_string()
+      )]
+    );
+  }
+
+  #[test]
+  fn test_recovery_across_statements() {
+    let src = "var x = 1
+print(x);
+var z = (1 + ;
+var y = ;
+x = 3
+print(y);
+for i 0 to 3 { print(i); }
+print(x);";
+    let found: Vec<_> = errors(src)
+      .into_iter()
+      .map(|(line, column, _)| (line, column))
+      .collect();
+    assert_eq!(
+      found,
+      [(1, 10), (3, 9), (4, 9), (5, 6), (7, 7)]
+    );
+  }
+
+  #[test]
+  fn test_context_labels() {
+    let src = "fn foo(a: f64) -> f64 {
+  for i in 0 to 3 {
+    print(i)
+    a
+  }
+  var c = a
+  c
+}
+print(foo(1)) print(1);
+";
+    let diags = statements_finish(Span::new(src)).unwrap_err();
+    let labels: Vec<Vec<_>> = diags
+      .iter()
+      .map(|diag| {
+        diag
+          .secondary
+          .iter()
+          .map(|label| (label.line, label.msg.as_str()))
+          .collect()
+      })
+      .collect();
+    assert_eq!(
+      labels,
+      [
+        vec![(2, "in this for loop"), (1, "in fn `foo`")],
+        vec![(1, "in fn `foo`")],
+        vec![],
+      ]
+    );
+    assert_eq!(
+      diags[0].msg,
+      "expected ';' after expression, found `a`"
+    );
+  }
 }
@@ -0,0 +1,362 @@
+//! Execution profiler attached to a `Vm` by `--profile`.
+//!
+//! Before each instruction the `Vm` tells the profiler where it is,
+//! which counts the instruction and charges the time elapsed since
+//! the previous one to that previous instruction. So the time of an
+//! instruction includes the native functions it calls, and the time
+//! of a function is its self time. Coroutines share the profiler
+//! with the `Vm` that created them, like the debugger, so that their
+//! stacks are seen below the `await` that runs them.
+
+use std::{
+  collections::HashMap,
+  error::Error,
+  io::Write,
+  rc::Rc,
+  time::{Duration, Instant},
+};
+
+use crate::{
+  bytecode::FnByteCode, tracer::Tracer, value::Value, vm::Vm,
+};
+
+/// Number of instructions shown in the report.
+const HOT_INSTRUCTIONS: usize = 20;
+
+struct FnProfile {
+  fn_def: Rc<FnByteCode>,
+  /// Executions of each instruction
+  counts: Vec<u64>,
+  /// Time spent in each instruction
+  times: Vec<Duration>,
+}
+
+impl FnProfile {
+  fn instructions(&self) -> u64 {
+    self.counts.iter().sum()
+  }
+
+  fn time(&self) -> Duration {
+    self.times.iter().sum()
+  }
+}
+
+/// A node of the call tree, for the folded stacks.
+struct Node {
+  func: usize,
+  parent: Option<usize>,
+  instructions: u64,
+}
+
+pub struct Profiler {
+  funcs: Vec<FnProfile>,
+  /// Index into `funcs` by the address of the function
+  fn_index: HashMap<*const FnByteCode, usize>,
+  /// Calls from a function in `funcs` to a function by name
+  calls: HashMap<(usize, String), u64>,
+  nodes: Vec<Node>,
+  /// Index into `nodes` by the parent and the function
+  children: HashMap<(Option<usize>, usize), usize>,
+  /// Functions and nodes of the stack seen at the last
+  /// instruction, outermost first
+  path: Vec<(*const FnByteCode, usize)>,
+  /// Functions of the `Vm`s waiting for coroutines, outermost first
+  outer: Vec<Vec<Rc<FnByteCode>>>,
+  /// The function and the instruction running, with when it
+  /// started
+  current: Option<(usize, usize, Instant)>,
+}
+
+impl Profiler {
+  pub fn new() -> Self {
+    Self {
+      funcs: vec![],
+      fn_index: HashMap::new(),
+      calls: HashMap::new(),
+      nodes: vec![],
+      children: HashMap::new(),
+      path: vec![],
+      outer: vec![],
+      current: None,
+    }
+  }
+
+  /// Charge the time until now to the instruction running.
+  fn stop(&mut self, now: Instant) {
+    if let Some((func, ip, start)) = self.current.take() {
+      if let Some(time) = self.funcs[func].times.get_mut(ip) {
+        *time += now - start;
+      }
+    }
+  }
+
+  fn fn_index(&mut self, fn_def: &Rc<FnByteCode>) -> usize {
+    if let Some(&i) = self.fn_index.get(&Rc::as_ptr(fn_def)) {
+      return i;
+    }
+    let len = fn_def.instructions.len();
+    self.funcs.push(FnProfile {
+      fn_def: fn_def.clone(),
+      counts: vec![0; len],
+      times: vec![Duration::ZERO; len],
+    });
+    self
+      .fn_index
+      .insert(Rc::as_ptr(fn_def), self.funcs.len() - 1);
+    self.funcs.len() - 1
+  }
+
+  /// The node of the call tree for the stack of the `Vm`.
+  fn node(&mut self, vm: &Vm) -> usize {
+    let frames = vm.frames();
+    let depth = self.outer.iter().map(Vec::len).sum::<usize>()
+      + frames.len();
+    // Most instructions do not change the stack of calls.
+    if let (Some(top), Some(&(ptr, node))) =
+      (frames.last(), self.path.last())
+    {
+      if self.path.len() == depth
+        && ptr == Rc::as_ptr(&top.fn_def)
+      {
+        return node;
+      }
+    }
+    let fns: Vec<_> = self
+      .outer
+      .iter()
+      .flatten()
+      .cloned()
+      .chain(frames.iter().map(|f| f.fn_def.clone()))
+      .collect();
+    let same = self
+      .path
+      .iter()
+      .zip(&fns)
+      .take_while(|((ptr, _), f)| *ptr == Rc::as_ptr(f))
+      .count();
+    self.path.truncate(same);
+    for f in &fns[same..] {
+      let func = self.fn_index(f);
+      let parent = self.path.last().map(|(_, node)| *node);
+      let node = *self
+        .children
+        .entry((parent, func))
+        .or_insert_with(|| {
+          self.nodes.push(Node {
+            func,
+            parent,
+            instructions: 0,
+          });
+          self.nodes.len() - 1
+        });
+      self.path.push((Rc::as_ptr(f), node));
+    }
+    self.path.last().map_or(0, |(_, node)| *node)
+  }
+
+  /// Print the functions by their time, the instructions that took
+  /// the most time and the calls between the functions.
+  pub fn report(
+    &mut self,
+    writer: &mut impl Write,
+  ) -> std::io::Result<()> {
+    self.stop(Instant::now());
+    let total: Duration =
+      self.funcs.iter().map(FnProfile::time).sum();
+    let instructions: u64 =
+      self.funcs.iter().map(FnProfile::instructions).sum();
+    writeln!(
+      writer,
+      "Profile: {instructions} instructions in {}",
+      format_duration(total)
+    )?;
+    let percent = |time: Duration| {
+      if total.is_zero() {
+        0.
+      } else {
+        time.as_secs_f64() / total.as_secs_f64() * 100.
+      }
+    };
+
+    let mut calls: HashMap<&str, u64> = HashMap::new();
+    for ((_, callee), count) in &self.calls {
+      *calls.entry(callee).or_default() += count;
+    }
+    let mut funcs: Vec<_> = self.funcs.iter().collect();
+    funcs.sort_by(|a, b| {
+      b.time()
+        .cmp(&a.time())
+        .then_with(|| a.fn_def.name.cmp(&b.fn_def.name))
+    });
+    writeln!(writer)?;
+    writeln!(
+      writer,
+      "{:>10} {:>6} {:>10} {:>8}  function",
+      "time", "%", "instrs", "calls"
+    )?;
+    for func in funcs {
+      let name = &func.fn_def.name;
+      writeln!(
+        writer,
+        "{:>10} {:>5.1}% {:>10} {:>8}  {name}",
+        format_duration(func.time()),
+        percent(func.time()),
+        func.instructions(),
+        calls.get(name as &str).copied().unwrap_or(0),
+      )?;
+    }
+
+    let mut hot: Vec<_> = self
+      .funcs
+      .iter()
+      .flat_map(|func| {
+        (0..func.counts.len())
+          .filter(|&ip| func.counts[ip] != 0)
+          .map(move |ip| (func, ip))
+      })
+      .collect();
+    hot.sort_by(|(a, a_ip), (b, b_ip)| {
+      b.times[*b_ip]
+        .cmp(&a.times[*a_ip])
+        .then_with(|| a.fn_def.name.cmp(&b.fn_def.name))
+        .then_with(|| a_ip.cmp(b_ip))
+    });
+    writeln!(writer)?;
+    writeln!(
+      writer,
+      "{:>10} {:>6} {:>10}  instruction",
+      "time", "%", "count"
+    )?;
+    for (func, ip) in hot.into_iter().take(HOT_INSTRUCTIONS) {
+      let inst = &func.fn_def.instructions[ip];
+      let line = func
+        .fn_def
+        .debug
+        .line(ip)
+        .map(|line| format!(" (line {line})"))
+        .unwrap_or_default();
+      writeln!(
+        writer,
+        "{:>10} {:>5.1}% {:>10}  {}[{ip}] {:?} {}{line}",
+        format_duration(func.times[ip]),
+        percent(func.times[ip]),
+        func.counts[ip],
+        func.fn_def.name,
+        inst.op,
+        inst.arg0,
+      )?;
+    }
+
+    let mut calls: Vec<_> = self
+      .calls
+      .iter()
+      .map(|((caller, callee), count)| {
+        (&self.funcs[*caller].fn_def.name, callee, *count)
+      })
+      .collect();
+    calls.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.cmp(b)));
+    writeln!(writer)?;
+    writeln!(writer, "{:>10}  caller -> callee", "calls")?;
+    for (caller, callee, count) in calls {
+      writeln!(writer, "{count:>10}  {caller} -> {callee}")?;
+    }
+    Ok(())
+  }
+
+  /// Write the instructions executed by each stack of calls, one
+  /// stack per line with the functions separated by semicolons, as
+  /// read by flamegraph tools.
+  pub fn write_folded(
+    &self,
+    writer: &mut impl Write,
+  ) -> std::io::Result<()> {
+    let mut lines = vec![];
+    for node in &self.nodes {
+      if node.instructions == 0 {
+        continue;
+      }
+      let mut names = vec![];
+      let mut next = Some(node);
+      while let Some(node) = next {
+        names.push(self.funcs[node.func].fn_def.name.as_str());
+        next = node.parent.map(|parent| &self.nodes[parent]);
+      }
+      names.reverse();
+      lines.push((names.join(";"), node.instructions));
+    }
+    lines.sort();
+    for (stack, count) in lines {
+      writeln!(writer, "{stack} {count}")?;
+    }
+    Ok(())
+  }
+}
+
+impl Tracer for Profiler {
+  fn on_instruction(
+    &mut self,
+    vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    self.stop(Instant::now());
+    let Some(frame) = vm.frames().last() else {
+      return Ok(());
+    };
+    let func = self.fn_index(&frame.fn_def);
+    let node = self.node(vm);
+    let ip = frame.ip;
+    if let Some(count) = self.funcs[func].counts.get_mut(ip) {
+      *count += 1;
+    }
+    self.nodes[node].instructions += 1;
+
+    // Start the clock after the bookkeeping, which is not charged
+    // to the program.
+    self.current = Some((func, ip, Instant::now()));
+    Ok(())
+  }
+
+  fn on_call(
+    &mut self,
+    vm: &Vm,
+    callee: &str,
+    _args: &[Value],
+  ) -> Result<(), Box<dyn Error>> {
+    if let Some(frame) = vm.frames().last() {
+      let caller = self.fn_index(&frame.fn_def);
+      *self
+        .calls
+        .entry((caller, callee.to_string()))
+        .or_default() += 1;
+    }
+    Ok(())
+  }
+
+  fn on_await(
+    &mut self,
+    vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    self.outer.push(
+      vm.frames().iter().map(|f| f.fn_def.clone()).collect(),
+    );
+    Ok(())
+  }
+
+  fn on_resume(
+    &mut self,
+    _vm: &Vm,
+    _value: Option<&Value>,
+  ) -> Result<(), Box<dyn Error>> {
+    self.outer.pop();
+    Ok(())
+  }
+}
+
+impl Default for Profiler {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+fn format_duration(time: Duration) -> String {
+  format!("{:.3}ms", time.as_secs_f64() * 1000.)
+}
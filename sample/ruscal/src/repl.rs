@@ -0,0 +1,284 @@
+//! State of an interactive session.
+//!
+//! Each input is parsed, type checked against the definitions of
+//! the earlier inputs and compiled into a function that runs on the
+//! stack left by them, so that the variables stay live between
+//! inputs. An input is applied as a whole: if it fails to compile
+//! or throws, the session is left as it was before.
+
+use std::{
+  cell::RefCell, collections::HashMap, error::Error, rc::Rc,
+};
+
+use crate::{
+  ast::{Span, Statement, Statements, TypeDecl},
+  ast_export::write_ast,
+  bytecode::{
+    standard_functions, ByteCode, FnByteCode, FnDecl, FnDef,
+  },
+  compiler::Compiler,
+  diagnostics::{Aborted, Diagnostic},
+  file_io::abort_on,
+  parser::statements_finish,
+  tracer::TextTracer,
+  type_checker::{type_check, TypeCheckContext},
+  value::Value,
+  vm::{Vm, YieldResult},
+  Args,
+};
+
+/// Name of the file shown in the diagnostics.
+const SOURCE_NAME: &str = "<repl>";
+
+/// Name of the function compiled from each input.
+const INPUT_FN: &str = "<input>";
+
+pub struct Repl<'a> {
+  args: &'a Args,
+  /// Inputs accepted so far. They and the type checker keep spans
+  /// into `sources`.
+  inputs: Vec<Statements<'static>>,
+  tc_ctx: TypeCheckContext<'static, 'static>,
+  compiler: Compiler,
+  funcs: HashMap<String, Rc<FnByteCode>>,
+  /// Values of the variables defined so far. The temporary values
+  /// of each input are popped before it yields.
+  stack: Vec<Value>,
+  /// Sources of the accepted inputs. Declared last so that they
+  /// are dropped after the spans into them.
+  sources: Vec<Box<str>>,
+}
+
+/// Extend the lifetime of an input so that the type checker of
+/// the session can keep spans into it.
+///
+/// # Safety
+///
+/// The source must outlive every span into it. The text of a
+/// `Box<str>` does not move, so it is enough to keep the box.
+unsafe fn extend_lifetime(source: &str) -> &'static str {
+  unsafe { &*(source as *const str) }
+}
+
+impl<'a> Repl<'a> {
+  pub fn new(args: &'a Args) -> Self {
+    Self {
+      args,
+      inputs: vec![],
+      tc_ctx: Self::new_tc_ctx(args),
+      compiler: Compiler::new(),
+      funcs: HashMap::new(),
+      stack: vec![],
+      sources: vec![],
+    }
+  }
+
+  fn new_tc_ctx(
+    args: &Args,
+  ) -> TypeCheckContext<'static, 'static> {
+    let mut tc_ctx = TypeCheckContext::new();
+    for (fname, f) in &args.additional_funcs {
+      tc_ctx.add_fn(fname.clone(), f());
+    }
+    if let Some(max_errors) = args.max_errors {
+      tc_ctx.set_max_errors(max_errors);
+    }
+    tc_ctx.set_strict(args.strict);
+    tc_ctx
+  }
+
+  /// Forget every definition.
+  pub fn reset(&mut self) {
+    *self = Self::new(self.args);
+  }
+
+  /// Type check the accepted inputs again, to drop the definitions
+  /// made by an input that was rejected.
+  fn rollback_types(&mut self) {
+    self.tc_ctx = Self::new_tc_ctx(self.args);
+    for stmts in &self.inputs {
+      // They were accepted before, so they still type check.
+      let _ = type_check(stmts, &mut self.tc_ctx);
+    }
+  }
+
+  fn parse(
+    &self,
+    src: &'static str,
+  ) -> Result<Statements<'static>, Box<dyn Error>> {
+    let stmts = statements_finish(Span::new(src))
+      .map_err(|errors| Aborted(errors).into())
+      .map_err(|e| abort_on(SOURCE_NAME, src, e))?;
+    if self.args.show_ast {
+      write_ast(
+        &stmts,
+        self.args.ast_format,
+        &mut std::io::stdout(),
+      )?;
+    }
+    Ok(stmts)
+  }
+
+  fn type_check(
+    &mut self,
+    src: &str,
+    stmts: &Statements<'static>,
+  ) -> Result<TypeDecl, Box<dyn Error>> {
+    type_check(stmts, &mut self.tc_ctx).map_err(|errors| {
+      let errors =
+        errors.iter().map(Diagnostic::from).collect();
+      abort_on(SOURCE_NAME, src, Box::new(Aborted(errors)))
+    })
+  }
+
+  /// Run an input and return the value and the type of its last
+  /// statement, if it is an expression.
+  pub fn eval(
+    &mut self,
+    src: &str,
+  ) -> Result<Option<(Value, TypeDecl)>, Box<dyn Error>> {
+    let source: Box<str> = src.into();
+    // SAFETY: the spans into the source are dropped before it,
+    // either by keeping it in `sources` with the spans or by
+    // rolling back the type checker if the input is rejected.
+    let src = unsafe { extend_lifetime(&source) };
+    let stmts = self.parse(src)?;
+    let depth = self.compiler.stack_len();
+    match self.eval_stmts(src, &stmts) {
+      Ok(value) => {
+        self.inputs.push(stmts);
+        self.sources.push(source);
+        Ok(value)
+      }
+      Err(e) => {
+        self.compiler.truncate_stack(depth);
+        self.rollback_types();
+        Err(e)
+      }
+    }
+  }
+
+  fn eval_stmts(
+    &mut self,
+    src: &'static str,
+    stmts: &Statements<'static>,
+  ) -> Result<Option<(Value, TypeDecl)>, Box<dyn Error>> {
+    let ty = self.type_check(src, stmts)?;
+    self
+      .compiler
+      .compile_repl(INPUT_FN, stmts)
+      .map_err(|e| abort_on(SOURCE_NAME, src, e))?;
+    let mut funcs: HashMap<_, _> = self
+      .compiler
+      .take_funcs()
+      .into_iter()
+      .map(|(name, func)| (name, Rc::new(func)))
+      .collect();
+    let input = funcs
+      .remove(INPUT_FN)
+      .ok_or("Input was not compiled to a function")?;
+    if self.args.disasm {
+      let mut out = std::io::stdout();
+      for func in funcs.values() {
+        func.disasm(&mut out)?;
+      }
+      input.disasm(&mut out)?;
+    }
+
+    let mut bytecode = self.bytecode();
+    for (name, func) in &funcs {
+      bytecode
+        .funcs
+        .insert(name.clone(), FnDef::User(func.clone()));
+    }
+    bytecode
+      .funcs
+      .insert(INPUT_FN.to_string(), FnDef::User(input));
+
+    let value = self.run(bytecode)?;
+    self.funcs.extend(funcs);
+    let is_expr =
+      matches!(stmts.last(), Some(Statement::Expression(_)));
+    Ok(is_expr.then_some((value, ty)))
+  }
+
+  /// The functions defined so far with the native functions.
+  fn bytecode(&self) -> ByteCode {
+    let mut bytecode = ByteCode::new();
+    for (name, f) in standard_functions() {
+      if let FnDecl::Native(f) = f {
+        bytecode.add_fn(name, f);
+      }
+    }
+    for (name, f) in &self.args.additional_funcs {
+      bytecode.add_fn(name.clone(), f());
+    }
+    for (name, func) in &self.funcs {
+      bytecode
+        .funcs
+        .insert(name.clone(), FnDef::User(func.clone()));
+    }
+    bytecode
+  }
+
+  /// Run the compiled input on the stack and keep the stack it
+  /// leaves if it succeeds.
+  fn run(
+    &mut self,
+    bytecode: ByteCode,
+  ) -> Result<Value, Box<dyn Error>> {
+    let mut vm = Vm::new(Rc::new(bytecode), Box::new(()));
+    if self.args.debug_output {
+      vm.add_tracer(Rc::new(RefCell::new(TextTracer(
+        std::io::stderr(),
+      ))));
+    }
+    vm.init_fn(INPUT_FN, &self.stack)?;
+    match vm.interpret()? {
+      YieldResult::Suspend(value) => {
+        self.stack = vm.top()?.stack().to_vec();
+        Ok(value)
+      }
+      YieldResult::Finished(_) => Err(
+        "return cannot be used outside of a function".into(),
+      ),
+    }
+  }
+
+  /// Type of an expression, without running it.
+  pub fn type_of(
+    &mut self,
+    src: &str,
+  ) -> Result<TypeDecl, Box<dyn Error>> {
+    let source: Box<str> = src.into();
+    // SAFETY: the type checker is rolled back before the source
+    // is dropped.
+    let src = unsafe { extend_lifetime(&source) };
+    let stmts = self.parse(src)?;
+    let [Statement::Expression(_)] = &stmts[..] else {
+      return Err("Expected an expression".into());
+    };
+    let ty = self.type_check(src, &stmts);
+    // Forget the variables bound in the expression, like by
+    // `if let`.
+    self.rollback_types();
+    ty
+  }
+
+  /// Disassemble a function defined in the session.
+  pub fn disasm(
+    &self,
+    name: &str,
+    writer: &mut impl std::io::Write,
+  ) -> Result<(), Box<dyn Error>> {
+    if let Some(func) = self.funcs.get(name) {
+      func.disasm(writer)?;
+      return Ok(());
+    }
+    if self.bytecode().funcs.contains_key(name) {
+      Err(format!("{name} is a native function").into())
+    } else {
+      Err(format!("Function {name} is not defined").into())
+    }
+  }
+}
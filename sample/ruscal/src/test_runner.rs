@@ -0,0 +1,98 @@
+//! Runner of the `test fn`s of a script, by `-T`.
+//!
+//! Each test runs in a fresh `Vm`, and fails if it throws an error
+//! that it does not catch, like by `assert` or `assert_eq`. The
+//! results are printed to stdout along with the output of the tests.
+
+use std::{
+  rc::Rc,
+  time::{Duration, Instant},
+};
+
+use crate::{
+  bytecode::{ByteCode, FnDef},
+  vm::{UncaughtError, Vm, YieldResult},
+};
+
+/// Run the tests in the order of their names and print the results.
+/// Returns whether all of them passed.
+pub fn run_tests(
+  source_name: &str,
+  bytecode: Rc<ByteCode>,
+) -> bool {
+  let mut tests: Vec<_> = bytecode
+    .funcs
+    .iter()
+    .filter_map(|(name, f)| match f {
+      FnDef::User(f) if f.test => Some(name.as_str()),
+      _ => None,
+    })
+    .collect();
+  tests.sort();
+  let plural = if tests.len() == 1 { "" } else { "s" };
+  println!("running {} test{plural}", tests.len());
+
+  let start = Instant::now();
+  let mut failures = vec![];
+  for name in &tests {
+    let test_start = Instant::now();
+    let result = run_test(source_name, &bytecode, name);
+    let time = format_duration(test_start.elapsed());
+    match result {
+      Ok(()) => println!("test {name} ... ok ({time})"),
+      Err(msg) => {
+        println!("test {name} ... FAILED ({time})");
+        failures.push((name, msg));
+      }
+    }
+  }
+
+  if !failures.is_empty() {
+    println!();
+    println!("failures:");
+    for (name, msg) in &failures {
+      println!();
+      println!("---- {name} ----");
+      println!("{msg}");
+    }
+  }
+  println!();
+  println!(
+    "test result: {}. {} passed; {} failed; finished in {}",
+    if failures.is_empty() { "ok" } else { "FAILED" },
+    tests.len() - failures.len(),
+    failures.len(),
+    format_duration(start.elapsed())
+  );
+  failures.is_empty()
+}
+
+/// Run a test and return the failure message with where it failed.
+fn run_test(
+  source_name: &str,
+  bytecode: &Rc<ByteCode>,
+  name: &str,
+) -> Result<(), String> {
+  let mut vm = Vm::new(bytecode.clone(), Box::new(()));
+  vm.init_fn(name, &[]).map_err(|e| e.to_string())?;
+  let msg = match vm.interpret() {
+    Ok(YieldResult::Finished(_)) => return Ok(()),
+    Ok(YieldResult::Suspend(_)) => {
+      "A test function cannot yield".to_string()
+    }
+    Err(e) => match e.downcast::<UncaughtError>() {
+      Ok(e) => e.0,
+      Err(e) => e.to_string(),
+    },
+  };
+  Err(match vm.error_location() {
+    Some((fn_name, line)) => {
+      format!("{source_name}:{line} in {fn_name}\n{msg}")
+    }
+    None => msg,
+  })
+}
+
+fn format_duration(time: Duration) -> String {
+  format!("{:.3}ms", time.as_secs_f64() * 1000.)
+}
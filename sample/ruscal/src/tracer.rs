@@ -0,0 +1,239 @@
+//! Hooks into the execution of a `Vm`.
+//!
+//! A `Tracer` added to a `Vm` is told about each instruction before
+//! it runs, and about the calls, returns, yields and awaits. The
+//! debugger and the profiler are tracers, and so are the tracers
+//! here that write the execution as text (`-D`) or as JSON lines
+//! (`--trace file`). Coroutines run in their own `Vm`s, which share
+//! the tracers of the `Vm` that created them, so a tracer sees an
+//! awaited coroutine between `on_await` and `on_resume`.
+
+use std::{error::Error, io::Write};
+
+use serde_json::json;
+
+use crate::{value::Value, vm::Vm};
+
+/// All the methods do nothing by default. They can return an error
+/// to stop the execution, which `Vm::interpret` returns.
+pub trait Tracer {
+  /// Before each instruction, which is the one at the ip of the top
+  /// frame.
+  fn on_instruction(
+    &mut self,
+    _vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    Ok(())
+  }
+
+  /// Before calling a function, either user defined or native, with
+  /// the caller at the top frame.
+  fn on_call(
+    &mut self,
+    _vm: &Vm,
+    _callee: &str,
+    _args: &[Value],
+  ) -> Result<(), Box<dyn Error>> {
+    Ok(())
+  }
+
+  /// When a function returns, while it is still at the top frame.
+  fn on_return(
+    &mut self,
+    _vm: &Vm,
+    _value: &Value,
+  ) -> Result<(), Box<dyn Error>> {
+    Ok(())
+  }
+
+  /// When a coroutine yields a value.
+  fn on_yield(
+    &mut self,
+    _vm: &Vm,
+    _value: &Value,
+  ) -> Result<(), Box<dyn Error>> {
+    Ok(())
+  }
+
+  /// Before running a coroutine awaited at the top frame.
+  fn on_await(
+    &mut self,
+    _vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    Ok(())
+  }
+
+  /// When the awaited coroutine yields, with the value, or finishes
+  /// or fails, without one.
+  fn on_resume(
+    &mut self,
+    _vm: &Vm,
+    _value: Option<&Value>,
+  ) -> Result<(), Box<dyn Error>> {
+    Ok(())
+  }
+}
+
+/// Writes the instructions with the stacks and the returned values,
+/// for debugging the interpreter.
+pub struct TextTracer<W: Write>(pub W);
+
+impl<W: Write> Tracer for TextTracer<W> {
+  fn on_instruction(
+    &mut self,
+    vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    let Some(frame) = vm.frames().last() else {
+      return Ok(());
+    };
+    if let Some(instruction) = frame.instruction() {
+      writeln!(
+        self.0,
+        "interpret[{}]: {instruction:?} stack: {:?}",
+        frame.ip(),
+        frame.stack()
+      )?;
+    }
+    Ok(())
+  }
+
+  fn on_return(
+    &mut self,
+    _vm: &Vm,
+    value: &Value,
+  ) -> Result<(), Box<dyn Error>> {
+    writeln!(self.0, "Returning {value}")?;
+    Ok(())
+  }
+}
+
+/// Writes an event per line as a JSON object, with the function and
+/// the ip where it happened, the length of the value stack of the
+/// frame and the depth of the calls, including the `Vm`s waiting for
+/// coroutines. Values are written as they are printed. Nothing
+/// depends on the time, so that traces can be compared across
+/// versions of the compiler.
+pub struct JsonTracer<W: Write> {
+  writer: W,
+  /// Depths of the `Vm`s waiting for coroutines
+  outer: Vec<usize>,
+}
+
+impl<W: Write> JsonTracer<W> {
+  pub fn new(writer: W) -> Self {
+    Self {
+      writer,
+      outer: vec![],
+    }
+  }
+
+  fn write(
+    &mut self,
+    vm: &Vm,
+    event: &str,
+    mut fields: serde_json::Value,
+  ) -> Result<(), Box<dyn Error>> {
+    let Some(frame) = vm.frames().last() else {
+      return Ok(());
+    };
+    let depth =
+      self.outer.iter().sum::<usize>() + vm.frames().len();
+    let mut line = json!({
+      "event": event,
+      "fn": frame.fn_name(),
+      "ip": frame.ip(),
+      "stack": frame.stack().len(),
+      "depth": depth,
+    });
+    if let (Some(line), Some(fields)) =
+      (line.as_object_mut(), fields.as_object_mut())
+    {
+      line.append(fields);
+    }
+    serde_json::to_writer(&mut self.writer, &line)?;
+    writeln!(self.writer)?;
+    Ok(())
+  }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+  fn on_instruction(
+    &mut self,
+    vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    let Some(instruction) =
+      vm.frames().last().and_then(|frame| frame.instruction())
+    else {
+      return Ok(());
+    };
+    self.write(
+      vm,
+      "instruction",
+      json!({
+        "op": format!("{:?}", instruction.op()),
+        "arg0": instruction.arg0(),
+      }),
+    )
+  }
+
+  fn on_call(
+    &mut self,
+    vm: &Vm,
+    callee: &str,
+    args: &[Value],
+  ) -> Result<(), Box<dyn Error>> {
+    let args: Vec<_> =
+      args.iter().map(Value::to_string).collect();
+    self.write(
+      vm,
+      "call",
+      json!({ "callee": callee, "args": args }),
+    )
+  }
+
+  fn on_return(
+    &mut self,
+    vm: &Vm,
+    value: &Value,
+  ) -> Result<(), Box<dyn Error>> {
+    self.write(
+      vm,
+      "return",
+      json!({ "value": value.to_string() }),
+    )
+  }
+
+  fn on_yield(
+    &mut self,
+    vm: &Vm,
+    value: &Value,
+  ) -> Result<(), Box<dyn Error>> {
+    self.write(
+      vm,
+      "yield",
+      json!({ "value": value.to_string() }),
+    )
+  }
+
+  fn on_await(
+    &mut self,
+    vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    self.write(vm, "await", json!({}))?;
+    self.outer.push(vm.frames().len());
+    Ok(())
+  }
+
+  fn on_resume(
+    &mut self,
+    vm: &Vm,
+    value: Option<&Value>,
+  ) -> Result<(), Box<dyn Error>> {
+    self.outer.pop();
+    self.write(
+      vm,
+      "resume",
+      json!({ "value": value.map(Value::to_string) }),
+    )
+  }
+}
@@ -1,17 +1,42 @@
 use std::{collections::HashMap, error::Error};
 
+use nom::{InputTake, Offset};
+
 use crate::{
-  ast::{ExprEnum, Expression, Span, Statement, TypeDecl},
+  ast::{
+    ExprEnum, Expression, Span, Statement, StrPart, TypeDecl,
+  },
   bytecode::{standard_functions, FnDecl, NativeFn, UserFn},
+  diagnostics::Diagnostic,
+  format::FormatSpec,
   parser::{calc_offset, GetSpan},
 };
 
+pub const DEFAULT_MAX_ERRORS: usize = 100;
+
 pub struct TypeCheckContext<'src, 'ctx> {
-  /// Variables table for type checking.
-  vars: HashMap<&'src str, TypeDecl>,
+  /// Variables table for type checking, with the span where each
+  /// variable was defined.
+  vars: HashMap<&'src str, (TypeDecl, Span<'src>)>,
   /// Function names are owned strings because it can be either from source or native.
   funcs: HashMap<String, FnDecl<'src>>,
   super_context: Option<&'ctx TypeCheckContext<'src, 'ctx>>,
+  /// Errors found so far. The checker keeps going after an error
+  /// to report as many as possible in one run.
+  errors: Vec<TypeCheckError<'src>>,
+  /// Names found so far with what they refer to.
+  symbols: Vec<Symbol<'src>>,
+  /// Maximum number of errors returned from `type_check`.
+  max_errors: usize,
+  /// Declared return type of the enclosing function, checked
+  /// against every `return` statement. `None` at the top level.
+  ret_type: Option<TypeDecl>,
+  /// Declared type of the values yielded by the enclosing
+  /// coroutine, checked against every `yield` statement.
+  yield_type: Option<TypeDecl>,
+  /// Reject implicit numeric conversions and types that are not
+  /// fully known, see `set_strict`.
+  strict: bool,
 }
 
 impl<'src, 'ctx> Default for TypeCheckContext<'src, 'ctx> {
@@ -26,9 +51,28 @@ impl<'src, 'ctx> TypeCheckContext<'src, 'ctx> {
       vars: HashMap::new(),
       funcs: standard_functions(),
       super_context: None,
+      errors: vec![],
+      symbols: vec![],
+      max_errors: DEFAULT_MAX_ERRORS,
+      ret_type: None,
+      yield_type: None,
+      strict: false,
     }
   }
 
+  pub fn set_max_errors(&mut self, max_errors: usize) {
+    self.max_errors = max_errors;
+  }
+
+  /// In strict mode, `i64` and `f64` are not converted to each
+  /// other implicitly, and declared types and the types of
+  /// variables must be fully known. `Any` can then only come
+  /// from native functions declared without types, like `print`.
+  /// An integer literal like `3` can still be used as `i64`.
+  pub fn set_strict(&mut self, strict: bool) {
+    self.strict = strict;
+  }
+
   pub fn add_fn(
     &mut self,
     name: String,
@@ -37,8 +81,37 @@ impl<'src, 'ctx> TypeCheckContext<'src, 'ctx> {
     self.funcs.insert(name, FnDecl::Native(fn_decl));
   }
 
-  fn get_var(&self, name: &str) -> Option<TypeDecl> {
-    self.vars.get(name).copied()
+  /// Occurrences of the names of variables and functions found by
+  /// `type_check`, in the order they were checked.
+  pub fn symbols(&self) -> &[Symbol<'src>] {
+    &self.symbols
+  }
+
+  fn define_var(&mut self, name: Span<'src>, ty: TypeDecl) {
+    self.symbols.push(Symbol {
+      span: name,
+      def: Some(name),
+      kind: SymbolKind::Variable(ty.clone()),
+    });
+    self.vars.insert(*name, (ty, name));
+  }
+
+  /// Define a variable only while checking `f`, like the binding
+  /// of `if let` or `catch`, and restore the variable it shadows.
+  fn with_var<T>(
+    &mut self,
+    name: Span<'src>,
+    ty: TypeDecl,
+    f: impl FnOnce(&mut Self) -> T,
+  ) -> T {
+    let shadowed = self.vars.get(*name).cloned();
+    self.define_var(name, ty);
+    let res = f(self);
+    match shadowed {
+      Some(var) => self.vars.insert(*name, var),
+      None => self.vars.remove(*name),
+    };
+    res
   }
 
   fn get_fn(&self, name: &str) -> Option<&FnDecl<'src>> {
@@ -56,14 +129,41 @@ impl<'src, 'ctx> TypeCheckContext<'src, 'ctx> {
       vars: HashMap::new(),
       funcs: HashMap::new(),
       super_context: Some(super_ctx),
+      errors: vec![],
+      symbols: vec![],
+      max_errors: super_ctx.max_errors,
+      ret_type: None,
+      yield_type: None,
+      strict: super_ctx.strict,
     }
   }
 }
 
+/// What a name in the source refers to.
+#[derive(Debug, Clone)]
+pub enum SymbolKind {
+  Variable(TypeDecl),
+  /// A function with its signature
+  Function(String),
+}
+
+/// An occurrence of a name in the source, recorded by the type
+/// checker for editor support.
+#[derive(Debug, Clone)]
+pub struct Symbol<'src> {
+  pub span: Span<'src>,
+  /// Where the name is defined, or `None` for a native function
+  pub def: Option<Span<'src>>,
+  pub kind: SymbolKind,
+}
+
 #[derive(Debug)]
 pub struct TypeCheckError<'src> {
   pub msg: String,
   pub span: Span<'src>,
+  /// Additional locations related to the error, such as the
+  /// definition of a variable being assigned to.
+  pub notes: Vec<(String, Span<'src>)>,
 }
 
 impl<'src> std::fmt::Display for TypeCheckError<'src> {
@@ -71,13 +171,15 @@ impl<'src> std::fmt::Display for TypeCheckError<'src> {
     &self,
     f: &mut std::fmt::Formatter<'_>,
   ) -> std::fmt::Result {
-    write!(
-      f,
-      "{}\nlocation: {}:{}: {}",
-      self.msg,
-      self.span.location_line(),
-      self.span.get_utf8_column(),
-      self.span.fragment()
+    Diagnostic::from(self).fmt(f)
+  }
+}
+
+impl<'src> From<&TypeCheckError<'src>> for Diagnostic {
+  fn from(e: &TypeCheckError<'src>) -> Self {
+    e.notes.iter().fold(
+      Diagnostic::error(&e.msg).with_span(e.span),
+      |diag, (note, span)| diag.with_label(*span, note),
     )
   }
 }
@@ -86,7 +188,20 @@ impl<'src> Error for TypeCheckError<'src> {}
 
 impl<'src> TypeCheckError<'src> {
   fn new(msg: String, span: Span<'src>) -> Self {
-    Self { msg, span }
+    Self {
+      msg,
+      span,
+      notes: vec![],
+    }
+  }
+
+  fn with_note(
+    mut self,
+    note: String,
+    span: Span<'src>,
+  ) -> Self {
+    self.notes.push((note, span));
+    self
   }
 }
 
@@ -94,16 +209,50 @@ fn tc_coerce_type<'src>(
   value: &TypeDecl,
   target: &TypeDecl,
   span: Span<'src>,
+  strict: bool,
 ) -> Result<TypeDecl, TypeCheckError<'src>> {
   use TypeDecl::*;
   Ok(match (value, target) {
-    (_, Any) => *value,
-    (Any, _) => *target,
+    (_, Any) => value.clone(),
+    (Any, _) => target.clone(),
+    (Opt(value), Opt(target)) => Opt(Box::new(tc_coerce_type(
+      value, target, span, strict,
+    )?)),
+    (Opt(_), _) => {
+      return Err(optional_error(value, target, span));
+    }
+    (_, Opt(target)) => Opt(Box::new(tc_coerce_type(
+      value, target, span, strict,
+    )?)),
+    (I64, F64) | (F64, I64) if strict => {
+      return Err(TypeCheckError::new(
+        format!(
+          "{:?} is not converted to {:?} implicitly in strict \
+          mode; convert it with {}()",
+          value,
+          target,
+          if *target == F64 { "f64" } else { "i64" }
+        ),
+        span,
+      ))
+    }
     (F64 | I64, F64) => F64,
     (F64, I64) => F64,
     (I64, I64) => I64,
     (Str, Str) => Str,
-    (Coro, Coro) => Coro,
+    (Coro(value_yield), Coro(target_yield)) => Coro(Box::new(
+      tc_coerce_type(value_yield, target_yield, span, strict)
+        .map_err(|_| {
+        TypeCheckError::new(
+          format!(
+            "{:?} cannot be assigned to {:?}",
+            value, target
+          ),
+          span,
+        )
+      })?,
+    )),
+    (Error, Error) => Error,
     _ => {
       return Err(TypeCheckError::new(
         format!(
@@ -116,34 +265,165 @@ fn tc_coerce_type<'src>(
   })
 }
 
+fn optional_error<'src>(
+  value: &TypeDecl,
+  target: &TypeDecl,
+  span: Span<'src>,
+) -> TypeCheckError<'src> {
+  TypeCheckError::new(
+    format!(
+      "Optional value of type {:?} is used where {:?} is required; \
+      unwrap it with `??` or `if let`",
+      value, target
+    ),
+    span,
+  )
+}
+
+/// Reject an optional value where its inner type is required, such
+/// as an operand of an arithmetic operator.
+fn tc_require_unwrapped<'src>(
+  ty: &TypeDecl,
+  span: Span<'src>,
+) -> Result<(), TypeCheckError<'src>> {
+  if let TypeDecl::Opt(inner) = ty {
+    return Err(optional_error(ty, inner, span));
+  }
+  Ok(())
+}
+
+/// Reject a type which is not fully known in strict mode, such as
+/// a bare `cofn`.
+fn tc_require_concrete<'src>(
+  ty: &TypeDecl,
+  desc: &str,
+  span: Span<'src>,
+) -> Result<(), TypeCheckError<'src>> {
+  fn is_concrete(ty: &TypeDecl) -> bool {
+    match ty {
+      TypeDecl::Any => false,
+      TypeDecl::Coro(inner) | TypeDecl::Opt(inner) => {
+        is_concrete(inner)
+      }
+      _ => true,
+    }
+  }
+  if is_concrete(ty) {
+    return Ok(());
+  }
+  let hint = if matches!(ty, TypeDecl::Coro(_)) {
+    "; write the yield type as `cofn<T>`"
+  } else {
+    "; add a type annotation"
+  };
+  Err(TypeCheckError::new(
+    format!(
+      "Type of {desc} must be fully known in strict mode, got \
+      {ty:?}{hint}"
+    ),
+    span,
+  ))
+}
+
+/// Whether the expression is an integer literal like `3`, or
+/// `+`, `-` or `*` of them.
+fn is_int_literal(ex: &Expression) -> bool {
+  use ExprEnum::*;
+  match &ex.expr {
+    NumLiteral(_) => !ex.span.contains(['.', 'e', 'E']),
+    Add(lhs, rhs) | Sub(lhs, rhs) | Mul(lhs, rhs) => {
+      is_int_literal(lhs) && is_int_literal(rhs)
+    }
+    _ => false,
+  }
+}
+
+/// Type of `ex`, whose own type is `ty`, where a value of type
+/// `expected` is wanted. Number literals are `f64`, but in strict
+/// mode an integer literal is `i64` where `i64` is expected, so that
+/// `for i in 0 to 3` needs no conversion.
+fn literal_type(
+  ex: &Expression,
+  ty: TypeDecl,
+  expected: &TypeDecl,
+  strict: bool,
+) -> TypeDecl {
+  use TypeDecl::*;
+  match (&ex.expr, ty, expected) {
+    (ExprEnum::SomeValue(inner), Opt(ty), Opt(expected)) => {
+      Opt(Box::new(literal_type(inner, *ty, expected, strict)))
+    }
+    (_, ty, Opt(expected)) => {
+      literal_type(ex, ty, expected, strict)
+    }
+    (_, _, I64) if strict && is_int_literal(ex) => I64,
+    (_, ty, _) => ty,
+  }
+}
+
+/// Types of both operands of a binary operator, with an integer
+/// literal taking the type of the other operand in strict mode.
+fn tc_operands<'src>(
+  lhs: &Expression<'src>,
+  rhs: &Expression<'src>,
+  ctx: &mut TypeCheckContext<'src, '_>,
+) -> Result<(TypeDecl, TypeDecl), TypeCheckError<'src>> {
+  let lhst = tc_expr(lhs, ctx)?;
+  let rhst = tc_expr(rhs, ctx)?;
+  let strict = ctx.strict;
+  Ok((
+    literal_type(lhs, lhst.clone(), &rhst, strict),
+    literal_type(rhs, rhst, &lhst, strict),
+  ))
+}
+
 fn tc_binary_op<'src>(
   lhs: &Expression<'src>,
   rhs: &Expression<'src>,
   ctx: &mut TypeCheckContext<'src, '_>,
   op: &str,
+  span: Span<'src>,
 ) -> Result<TypeDecl, TypeCheckError<'src>> {
-  let lhst = tc_expr(lhs, ctx)?;
-  let rhst = tc_expr(rhs, ctx)?;
-  binary_op_type(&lhst, &rhst).map_err(|_| {
-      TypeCheckError::new(
-        format!(
-        "Operation {op} between incompatible type: {:?} and {:?}",
-        lhst, rhst,
-      ),
-        lhs.span,
-      )
-    })
+  let (lhst, rhst) = tc_operands(lhs, rhs, ctx)?;
+  tc_require_unwrapped(&lhst, lhs.span)?;
+  tc_require_unwrapped(&rhst, rhs.span)?;
+  binary_op_type(&lhst, &rhst, ctx.strict).map_err(|_| {
+    incompatible_operands(op, span, (lhs, &lhst), (rhs, &rhst))
+  })
+}
+
+fn incompatible_operands<'src>(
+  op: &str,
+  span: Span<'src>,
+  (lhs, lhst): (&Expression<'src>, &TypeDecl),
+  (rhs, rhst): (&Expression<'src>, &TypeDecl),
+) -> TypeCheckError<'src> {
+  TypeCheckError::new(
+    format!(
+      "Operation {op} between incompatible type: {:?} and {:?}",
+      lhst, rhst,
+    ),
+    span,
+  )
+  .with_note(format!("this is {lhst:?}"), lhs.span)
+  .with_note(format!("this is {rhst:?}"), rhs.span)
 }
 
+/// Type of a binary operation or of a value which can come from
+/// either of two branches. Mixing `i64` and `f64` gives `f64`,
+/// unless in strict mode.
 fn binary_op_type(
   lhs: &TypeDecl,
   rhs: &TypeDecl,
+  strict: bool,
 ) -> Result<TypeDecl, ()> {
   use TypeDecl::*;
   Ok(match (lhs, rhs) {
     (Any, _) => Any,
     (_, Any) => Any,
     (I64, I64) => I64,
+    (F64, F64) => F64,
+    (F64 | I64, F64 | I64) if strict => return Err(()),
     (F64 | I64, F64 | I64) => F64,
     (Str, Str) => Str,
     _ => return Err(()),
@@ -155,42 +435,95 @@ fn tc_binary_cmp<'src>(
   rhs: &Expression<'src>,
   ctx: &mut TypeCheckContext<'src, '_>,
   op: &str,
+  span: Span<'src>,
 ) -> Result<TypeDecl, TypeCheckError<'src>> {
   use TypeDecl::*;
-  let lhst = tc_expr(lhs, ctx)?;
-  let rhst = tc_expr(rhs, ctx)?;
+  let (lhst, rhst) = tc_operands(lhs, rhs, ctx)?;
+  tc_require_unwrapped(&lhst, lhs.span)?;
+  tc_require_unwrapped(&rhst, rhs.span)?;
   Ok(match (&lhst, &rhst) {
-      (Any, _) => I64,
-      (_, Any) => I64,
-      (F64, F64) => I64,
-      (I64, I64) => I64,
-      (Str, Str) => I64,
-      _ => {
-        return Err(TypeCheckError::new(
-          format!(
-        "Operation {op} between incompatible type: {:?} and {:?}",
-        lhst, rhst,
-      ),
-          lhs.span,
-        ))
-      }
-    })
+    (Any, _) => I64,
+    (_, Any) => I64,
+    (F64, F64) => I64,
+    (I64, I64) => I64,
+    (Str, Str) => I64,
+    _ => {
+      return Err(incompatible_operands(
+        op,
+        span,
+        (lhs, &lhst),
+        (rhs, &rhst),
+      ))
+    }
+  })
 }
 
+/// Type check an expression. An error is recorded in the context and
+/// the expression is given the type `Any`, so that the parent
+/// expressions can still be checked without cascading errors.
 fn tc_expr<'src>(
   e: &Expression<'src>,
   ctx: &mut TypeCheckContext<'src, '_>,
+) -> Result<TypeDecl, TypeCheckError<'src>> {
+  Ok(tc_expr_inner(e, ctx).unwrap_or_else(|err| {
+    ctx.errors.push(err);
+    TypeDecl::Any
+  }))
+}
+
+fn tc_expr_inner<'src>(
+  e: &Expression<'src>,
+  ctx: &mut TypeCheckContext<'src, '_>,
 ) -> Result<TypeDecl, TypeCheckError<'src>> {
   use ExprEnum::*;
   Ok(match &e.expr {
     NumLiteral(_val) => TypeDecl::F64,
     StrLiteral(_val) => TypeDecl::Str,
-    Ident(str) => ctx.get_var(str).ok_or_else(|| {
-      TypeCheckError::new(
-        format!("Variable \"{}\" not found in scope", str),
-        e.span,
-      )
-    })?,
+    Interpolated(parts) => {
+      for part in parts {
+        let StrPart::Expr(ex, spec) = part else {
+          continue;
+        };
+        let ty = tc_expr(ex, ctx)?;
+        let Some(spec) = spec else {
+          continue;
+        };
+        let spec = FormatSpec::parse(spec)
+          .map_err(|e| TypeCheckError::new(e, *spec))?;
+        if spec.hex.is_some()
+          && !matches!(
+            ty,
+            TypeDecl::Any | TypeDecl::I64 | TypeDecl::F64
+          )
+        {
+          return Err(TypeCheckError::new(
+            format!("Hex format requires a number, got {ty:?}"),
+            ex.span,
+          ));
+        }
+      }
+      TypeDecl::Str
+    }
+    NoneLiteral => TypeDecl::Opt(Box::new(TypeDecl::Any)),
+    SomeValue(ex) => match tc_expr(ex, ctx)? {
+      TypeDecl::Opt(inner) => TypeDecl::Opt(inner),
+      ty => TypeDecl::Opt(Box::new(ty)),
+    },
+    Ident(str) => {
+      let (ty, def) =
+        ctx.vars.get(**str).cloned().ok_or_else(|| {
+          TypeCheckError::new(
+            format!("Variable \"{}\" not found in scope", str),
+            e.span,
+          )
+        })?;
+      ctx.symbols.push(Symbol {
+        span: *str,
+        def: Some(def),
+        kind: SymbolKind::Variable(ty.clone()),
+      });
+      ty
+    }
     FnInvoke(str, args) => {
       let args_ty = args
         .iter()
@@ -203,32 +536,105 @@ fn tc_expr<'src>(
         )
       })?;
       let args_decl = func.args();
-      for ((arg_ty, arg_span), decl) in
-        args_ty.iter().zip(args_decl.iter())
+      let ret_type = func.ret_type();
+      let variadic = func.variadic();
+      let signature = func.signature(str);
+      ctx.symbols.push(Symbol {
+        span: *str,
+        def: func.def_span(),
+        kind: SymbolKind::Function(signature.clone()),
+      });
+      let arity_ok = if variadic {
+        args_decl.len() <= args.len()
+      } else {
+        args_decl.len() == args.len()
+      };
+      if !arity_ok {
+        return Err(TypeCheckError::new(
+          format!(
+            "Function \"{}\" expects {}{} argument(s), but {} \
+            were given: {}",
+            str,
+            if variadic { "at least " } else { "" },
+            args_decl.len(),
+            args.len(),
+            signature
+          ),
+          e.span,
+        ));
+      }
+      for ((arg, (arg_ty, arg_span)), decl) in
+        args.iter().zip(args_ty).zip(args_decl.iter())
       {
-        tc_coerce_type(arg_ty, &decl.1, *arg_span)?;
+        let arg_ty =
+          literal_type(arg, arg_ty, &decl.1, ctx.strict);
+        if let Err(e) =
+          tc_coerce_type(&arg_ty, &decl.1, arg_span, ctx.strict)
+        {
+          ctx.errors.push(e);
+        }
       }
-      func.ret_type()
-    }
-    Add(lhs, rhs) => tc_binary_op(lhs, rhs, ctx, "Add")?,
-    Sub(lhs, rhs) => tc_binary_op(lhs, rhs, ctx, "Sub")?,
-    Mul(lhs, rhs) => tc_binary_op(lhs, rhs, ctx, "Mult")?,
-    Div(lhs, rhs) => tc_binary_op(lhs, rhs, ctx, "Div")?,
-    Lt(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "LT")?,
-    Gt(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "GT")?,
+      ret_type
+    }
+    Add(lhs, rhs) => {
+      tc_binary_op(lhs, rhs, ctx, "Add", e.span)?
+    }
+    Sub(lhs, rhs) => {
+      tc_binary_op(lhs, rhs, ctx, "Sub", e.span)?
+    }
+    Mul(lhs, rhs) => {
+      tc_binary_op(lhs, rhs, ctx, "Mult", e.span)?
+    }
+    Div(lhs, rhs) => {
+      tc_binary_op(lhs, rhs, ctx, "Div", e.span)?
+    }
+    Lt(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "LT", e.span)?,
+    Gt(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "GT", e.span)?,
+    Coalesce(lhs, rhs) => {
+      let lhst = tc_expr(lhs, ctx)?;
+      let rhst = tc_expr(rhs, ctx)?;
+      match lhst {
+        TypeDecl::Opt(inner) => {
+          let rhst =
+            literal_type(rhs, rhst, &inner, ctx.strict);
+          tc_coerce_type(&rhst, &inner, rhs.span, ctx.strict)?
+        }
+        TypeDecl::Any => rhst,
+        _ => {
+          return Err(TypeCheckError::new(
+            format!(
+              "Left hand side of ?? must be optional, got {:?}",
+              lhst
+            ),
+            lhs.span,
+          ))
+        }
+      }
+    }
     If(cond, true_branch, false_branch) => {
+      let cond_type = tc_expr(cond, ctx)?;
       tc_coerce_type(
-        &tc_expr(cond, ctx)?,
+        &cond_type,
         &TypeDecl::I64,
         cond.span,
+        ctx.strict,
       )?;
-      let true_type = type_check(true_branch, ctx)?;
+      let true_type = tc_stmts(true_branch, ctx);
       if let Some(false_branch) = false_branch {
-        let false_type = type_check(false_branch, ctx)?;
-        binary_op_type(&true_type, &false_type).map_err(
-          |_| {
-            let true_span = true_branch.span();
-            let false_span = false_branch.span();
+        let false_type = tc_stmts(false_branch, ctx);
+        binary_op_type(&true_type, &false_type, ctx.strict)
+          .map_err(|_| {
+            // From the true branch to the false one, or the
+            // whole expression if either is empty.
+            let span =
+              match (true_branch.span(), false_branch.span()) {
+                (Some(t), Some(f)) => {
+                  let (from_t, _) =
+                    e.span.take_split(e.span.offset(&t));
+                  calc_offset(from_t, f)
+                }
+                _ => e.span,
+              };
             TypeCheckError::new(
               format!(
                 "Conditional expression doesn't have the \
@@ -236,98 +642,336 @@ fn tc_expr<'src>(
               {:?} and {:?}",
                 true_type, false_type
               ),
-              calc_offset(true_span, false_span),
+              span,
             )
-          },
-        )?
+          })?
       } else {
         true_type
       }
     }
-    Await(ex) => {
-      let _res = tc_expr(ex, ctx)?;
-      TypeDecl::Any
+    IfLet(name, ex, true_branch, false_branch) => {
+      let ty = tc_expr(ex, ctx)?;
+      let inner = match ty {
+        TypeDecl::Opt(inner) => *inner,
+        TypeDecl::Any => TypeDecl::Any,
+        _ => {
+          return Err(TypeCheckError::new(
+            format!(
+              "if let requires an optional value, got {:?}",
+              ty
+            ),
+            ex.span,
+          ))
+        }
+      };
+      let true_type = ctx.with_var(*name, inner, |ctx| {
+        tc_stmts(true_branch, ctx)
+      });
+      if let Some(false_branch) = false_branch {
+        let false_type = tc_stmts(false_branch, ctx);
+        binary_op_type(&true_type, &false_type, ctx.strict)
+          .map_err(|_| {
+            TypeCheckError::new(
+              format!(
+                "Conditional expression doesn't have the \
+              compatible types in true and false branch: \
+              {:?} and {:?}",
+                true_type, false_type
+              ),
+              e.span,
+            )
+          })?
+      } else {
+        true_type
+      }
+    }
+    // Awaiting a coroutine that finishes instead of yielding
+    // throws, so `await` always gives a yielded value.
+    Await(ex) => match tc_expr(ex, ctx)? {
+      TypeDecl::Coro(yield_type) => *yield_type,
+      TypeDecl::Any => TypeDecl::Any,
+      ty => {
+        return Err(TypeCheckError::new(
+          format!("await requires a coroutine, got {ty:?}"),
+          ex.span,
+        ))
+      }
+    },
+    Try(body, name, handler) => {
+      let body_type = tc_stmts(body, ctx);
+      let handler_type =
+        ctx.with_var(*name, TypeDecl::Error, |ctx| {
+          tc_stmts(handler, ctx)
+        });
+      binary_op_type(&body_type, &handler_type, ctx.strict)
+        .map_err(|_| {
+          TypeCheckError::new(
+            format!(
+            "Try expression doesn't have the compatible types \
+            in the body and the handler: {:?} and {:?}",
+            body_type, handler_type
+          ),
+            e.span,
+          )
+        })?
     }
   })
 }
 
+/// Type check the statements and return the type of the last
+/// expression, or the list of errors sorted by their positions.
 pub fn type_check<'src>(
   stmts: &Vec<Statement<'src>>,
   ctx: &mut TypeCheckContext<'src, '_>,
-) -> Result<TypeDecl, TypeCheckError<'src>> {
+) -> Result<TypeDecl, Vec<TypeCheckError<'src>>> {
+  let res = tc_stmts(stmts, ctx);
+  if ctx.errors.is_empty() {
+    return Ok(res);
+  }
+  let mut errors = std::mem::take(&mut ctx.errors);
+  errors.sort_by_key(|e| e.span.location_offset());
+  errors.truncate(ctx.max_errors);
+  Err(errors)
+}
+
+fn tc_stmts<'src>(
+  stmts: &Vec<Statement<'src>>,
+  ctx: &mut TypeCheckContext<'src, '_>,
+) -> TypeDecl {
   let mut res = TypeDecl::Any;
   for stmt in stmts {
-    match stmt {
-      Statement::VarDef { name, td, ex, .. } => {
-        let init_type = tc_expr(ex, ctx)?;
-        let init_type =
-          tc_coerce_type(&init_type, td, ex.span)?;
-        ctx.vars.insert(**name, init_type);
+    if let Err(e) = tc_stmt(stmt, ctx, &mut res) {
+      ctx.errors.push(e);
+    }
+  }
+  res
+}
+
+/// Type check a statement. The type of an expression statement is
+/// written to `res`.
+fn tc_stmt<'src>(
+  stmt: &Statement<'src>,
+  ctx: &mut TypeCheckContext<'src, '_>,
+  res: &mut TypeDecl,
+) -> Result<(), TypeCheckError<'src>> {
+  match stmt {
+    Statement::VarDef { name, td, ex, .. } => {
+      let init_type = tc_expr(ex, ctx)?;
+      if ctx.strict {
+        // `Any` alone comes from an untyped native function, but
+        // something like `none` needs to be annotated.
+        let ty = td.as_ref().unwrap_or(&init_type);
+        if *ty != TypeDecl::Any {
+          if let Err(e) = tc_require_concrete(
+            ty,
+            &format!("variable \"{name}\""),
+            *name,
+          ) {
+            ctx.errors.push(e);
+          }
+        }
       }
-      Statement::VarAssign { name, ex, .. } => {
-        let init_type = tc_expr(ex, ctx)?;
-        let target =
-          ctx.vars.get(**name).expect("Variable not found");
-        tc_coerce_type(&init_type, target, ex.span)?;
+      let init_type = if let Some(td) = td {
+        let init_type =
+          literal_type(ex, init_type, td, ctx.strict);
+        // Keep the declared type on error to avoid cascading
+        // errors at the usage of the variable.
+        tc_coerce_type(&init_type, td, ex.span, ctx.strict)
+          .unwrap_or_else(|e| {
+            ctx.errors.push(e);
+            td.clone()
+          })
+      } else {
+        init_type
+      };
+      ctx.define_var(*name, init_type);
+    }
+    Statement::VarAssign { name, ex, .. } => {
+      let init_type = tc_expr(ex, ctx)?;
+      let (target, def_span) =
+        ctx.vars.get(**name).cloned().ok_or_else(|| {
+          TypeCheckError::new(
+            format!("Variable \"{}\" not found in scope", name),
+            *name,
+          )
+        })?;
+      ctx.symbols.push(Symbol {
+        span: *name,
+        def: Some(def_span),
+        kind: SymbolKind::Variable(target.clone()),
+      });
+      let init_type =
+        literal_type(ex, init_type, &target, ctx.strict);
+      tc_coerce_type(&init_type, &target, ex.span, ctx.strict)
+        .map_err(|e| {
+          e.with_note(
+            format!(
+              "Variable \"{}\" is defined here with type {:?}",
+              name, target
+            ),
+            def_span,
+          )
+        })?;
+    }
+    Statement::FnDef {
+      span,
+      name,
+      args,
+      ret_type,
+      stmts,
+      cofn,
+      ..
+    } => {
+      if ctx.strict {
+        let ret_desc = if *cofn {
+          format!("the values yielded by \"{name}\"")
+        } else {
+          format!("the return value of \"{name}\"")
+        };
+        let decls = args
+          .iter()
+          .map(|(arg, ty)| {
+            (format!("argument \"{arg}\""), ty, *arg)
+          })
+          .chain(std::iter::once((ret_desc, ret_type, *name)));
+        for (desc, ty, span) in decls {
+          if let Err(e) = tc_require_concrete(ty, &desc, span) {
+            ctx.errors.push(e);
+          }
+        }
       }
-      Statement::FnDef {
-        name,
-        args,
-        ret_type,
-        stmts,
-        cofn,
-      } => {
-        // Function declaration needs to be added first to allow recursive calls
-        ctx.funcs.insert(
-          name.to_string(),
-          FnDecl::User(UserFn::new(
-            args.clone(),
-            *ret_type,
-            *cofn,
-          )),
-        );
+      // Function declaration needs to be added first to allow recursive calls
+      let fn_decl = FnDecl::User(UserFn::new(
+        *name,
+        args.clone(),
+        ret_type.clone(),
+        *cofn,
+      ));
+      ctx.symbols.push(Symbol {
+        span: *name,
+        def: Some(*name),
+        kind: SymbolKind::Function(fn_decl.signature(name)),
+      });
+      ctx.funcs.insert(name.to_string(), fn_decl);
+      let (last_stmt, errors, symbols) = {
         let mut subctx = TypeCheckContext::push_stack(ctx);
+        // The value a coroutine finishes with is discarded, so
+        // only its yields are checked.
+        if *cofn {
+          subctx.yield_type = Some(ret_type.clone());
+        } else {
+          subctx.ret_type = Some(ret_type.clone());
+        }
         for (arg, ty) in args.iter() {
-          subctx.vars.insert(arg, *ty);
+          subctx.define_var(*arg, ty.clone());
         }
-        let last_stmt = type_check(stmts, &mut subctx)?;
-        tc_coerce_type(&last_stmt, ret_type, stmts.span())?;
-      }
-      Statement::Expression(e) => {
-        res = tc_expr(e, ctx)?;
-      }
-      Statement::For {
-        loop_var,
-        start,
-        end,
-        stmts,
-        ..
-      } => {
+        let last_stmt = tc_stmts(stmts, &mut subctx);
+        (
+          last_stmt,
+          std::mem::take(&mut subctx.errors),
+          std::mem::take(&mut subctx.symbols),
+        )
+      };
+      ctx.errors.extend(errors);
+      ctx.symbols.extend(symbols);
+      if !*cofn {
+        // The first statement, or the definition if the body is
+        // empty
+        let body_span = stmts
+          .iter()
+          .find_map(Statement::span)
+          .unwrap_or(*span);
+        let last_stmt = match stmts.last() {
+          Some(Statement::Expression(ex)) => {
+            literal_type(ex, last_stmt, ret_type, ctx.strict)
+          }
+          _ => last_stmt,
+        };
         tc_coerce_type(
-          &tc_expr(start, ctx)?,
-          &TypeDecl::I64,
-          start.span,
+          &last_stmt, ret_type, body_span, ctx.strict,
         )?;
-        tc_coerce_type(
-          &tc_expr(end, ctx)?,
+      }
+    }
+    Statement::Expression(e) => {
+      *res = tc_expr(e, ctx)?;
+    }
+    Statement::For {
+      loop_var,
+      start,
+      end,
+      stmts,
+      ..
+    } => {
+      // The loop body is checked even if the range is wrong.
+      for ex in [start, end] {
+        let ty = tc_expr(ex, ctx)?;
+        let ty =
+          literal_type(ex, ty, &TypeDecl::I64, ctx.strict);
+        if let Err(e) = tc_coerce_type(
+          &ty,
           &TypeDecl::I64,
-          end.span,
-        )?;
-        ctx.vars.insert(loop_var, TypeDecl::I64);
-        res = type_check(stmts, ctx)?;
+          ex.span,
+          ctx.strict,
+        ) {
+          ctx.errors.push(e);
+        }
       }
-      Statement::Return(e) => {
-        return tc_expr(e, ctx);
+      ctx.define_var(*loop_var, TypeDecl::I64);
+      *res = tc_stmts(stmts, ctx);
+    }
+    Statement::Return(e) => {
+      let ty = tc_expr(e, ctx)?;
+      // Control never falls through a return, so it does not
+      // contribute to the type of the block.
+      *res = TypeDecl::Any;
+      if let Some(ret_type) = &ctx.ret_type {
+        let ty =
+          literal_type(e, ty.clone(), ret_type, ctx.strict);
+        tc_coerce_type(&ty, ret_type, e.span, ctx.strict)
+          .map_err(|_| {
+            TypeCheckError::new(
+              format!(
+                "Returned value of type {ty:?} doesn't match \
+              the declared return type {ret_type:?}"
+              ),
+              e.span,
+            )
+          })?;
       }
-      Statement::Break => {
-        // TODO: check types in break out site. For now we disallow break with values like Rust.
+    }
+    Statement::Break(_) => {
+      // TODO: check types in break out site. For now we disallow break with values like Rust.
+    }
+    Statement::Continue(_) => (),
+    Statement::Yield(e) => {
+      let ty = tc_expr(e, ctx)?;
+      if let Some(yield_type) = &ctx.yield_type {
+        let ty =
+          literal_type(e, ty.clone(), yield_type, ctx.strict);
+        tc_coerce_type(&ty, yield_type, e.span, ctx.strict)
+          .map_err(|_| {
+            TypeCheckError::new(
+              format!(
+                "Yielded value of type {ty:?} doesn't match \
+                the declared yield type {yield_type:?}"
+              ),
+              e.span,
+            )
+          })?;
       }
-      Statement::Continue => (),
-      Statement::Yield(e) => {
-        tc_expr(e, ctx)?;
-        // TODO: check type with the return type, but don't escape from this function.
+    }
+    Statement::Throw(e) => {
+      let ty = tc_expr(e, ctx)?;
+      if !matches!(
+        ty,
+        TypeDecl::Any | TypeDecl::Str | TypeDecl::Error
+      ) {
+        return Err(TypeCheckError::new(
+          format!("Only str or err can be thrown, got {ty:?}"),
+          e.span,
+        ));
       }
     }
   }
-  Ok(res)
+  Ok(())
 }
@@ -13,6 +13,8 @@ pub enum ValueKind {
   I64,
   Str,
   Coro,
+  Error,
+  None,
 }
 
 #[derive(Debug, Clone)]
@@ -21,6 +23,11 @@ pub enum Value {
   I64(i64),
   Str(String),
   Coro(Rc<RefCell<Vm>>),
+  /// An error value with its message, either thrown or caught.
+  Error(String),
+  /// An optional value that is not present. A present optional value
+  /// is represented by the inner value itself.
+  None,
 }
 
 impl Default for Value {
@@ -36,6 +43,8 @@ impl PartialEq for Value {
       (F64(lhs), F64(rhs)) => lhs == rhs,
       (I64(lhs), I64(rhs)) => lhs == rhs,
       (Str(lhs), Str(rhs)) => lhs == rhs,
+      (Error(lhs), Error(rhs)) => lhs == rhs,
+      (None, None) => true,
       _ => false,
     }
   }
@@ -51,6 +60,8 @@ impl Display for Value {
       Self::I64(value) => write!(f, "{value}"),
       Self::Str(value) => write!(f, "{value}"),
       Self::Coro(_) => write!(f, "<Coroutine>"),
+      Self::Error(value) => write!(f, "{value}"),
+      Self::None => write!(f, "none"),
     }
   }
 }
@@ -62,6 +73,8 @@ impl Value {
       Self::I64(_) => ValueKind::I64,
       Self::Str(_) => ValueKind::Str,
       Self::Coro(_) => ValueKind::Coro,
+      Self::Error(_) => ValueKind::Error,
+      Self::None => ValueKind::None,
     }
   }
 
@@ -78,12 +91,12 @@ impl Value {
       Self::I64(value) => {
         writer.write_all(&value.to_le_bytes())?;
       }
-      Self::Str(value) => {
+      Self::Str(value) | Self::Error(value) => {
         serialize_str(value, writer)?;
       }
+      Self::None => (),
       Self::Coro(_) => {
-        return Err(std::io::Error::new(
-          std::io::ErrorKind::Other,
+        return Err(std::io::Error::other(
           "Coroutine can't be serialized",
         ))
       }
@@ -98,6 +111,8 @@ impl Value {
     const F64: u8 = ValueKind::F64 as u8;
     const I64: u8 = ValueKind::I64 as u8;
     const Str: u8 = ValueKind::Str as u8;
+    const Error: u8 = ValueKind::Error as u8;
+    const None: u8 = ValueKind::None as u8;
 
     let mut kind_buf = [0u8; 1];
     reader.read_exact(&mut kind_buf)?;
@@ -113,13 +128,12 @@ impl Value {
         Ok(Value::I64(i64::from_le_bytes(buf)))
       }
       Str => Ok(Value::Str(deserialize_str(reader)?)),
-      _ => Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        format!(
-          "ValueKind {} does not match to any known value",
-          kind_buf[0]
-        ),
-      )),
+      Error => Ok(Value::Error(deserialize_str(reader)?)),
+      None => Ok(Value::None),
+      _ => Err(std::io::Error::other(format!(
+        "ValueKind {} does not match to any known value",
+        kind_buf[0]
+      ))),
     }
   }
 
@@ -153,7 +167,7 @@ impl Value {
     Ok(match self {
       Self::F64(value) => format!("{value}"),
       Self::I64(value) => format!("{value}"),
-      Self::Str(value) => value.clone(),
+      Self::Str(value) | Self::Error(value) => value.clone(),
       _ => {
         return Err(format!(
           "Coercion failed: {:?} cannot be coerced to str",
@@ -179,6 +193,20 @@ pub fn deserialize_size(
   Ok(u32::from_le_bytes(buf) as usize)
 }
 
+/// Read a size and then that many items. The size is not trusted to
+/// reserve memory, since it may come from a corrupted file.
+pub fn deserialize_vec<R: Read, T>(
+  reader: &mut R,
+  mut item: impl FnMut(&mut R) -> std::io::Result<T>,
+) -> std::io::Result<Vec<T>> {
+  let len = deserialize_size(reader)?;
+  let mut items = Vec::with_capacity(len.min(1024));
+  for _ in 0..len {
+    items.push(item(reader)?);
+  }
+  Ok(items)
+}
+
 pub fn serialize_str(
   s: &str,
   writer: &mut impl Write,
@@ -191,8 +219,13 @@ pub fn serialize_str(
 pub fn deserialize_str(
   reader: &mut impl Read,
 ) -> std::io::Result<String> {
-  let mut buf = vec![0u8; deserialize_size(reader)?];
-  reader.read_exact(&mut buf)?;
-  let s = String::from_utf8(buf).unwrap();
-  Ok(s)
+  let len = deserialize_size(reader)?;
+  // Read through `take` so that a corrupted length fails at the end
+  // of the input instead of allocating all of it up front.
+  let mut buf = vec![];
+  reader.take(len as u64).read_to_end(&mut buf)?;
+  if buf.len() != len {
+    return Err(std::io::ErrorKind::UnexpectedEof.into());
+  }
+  String::from_utf8(buf).map_err(std::io::Error::other)
 }
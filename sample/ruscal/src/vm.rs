@@ -1,22 +1,68 @@
-use std::{cell::RefCell, error::Error, rc::Rc};
+use std::{any::Any, cell::RefCell, error::Error, rc::Rc};
 
 use crate::{
-  bytecode::{ByteCode, FnByteCode, FnDef},
+  bytecode::{ByteCode, FnByteCode, FnDef, NativeFn},
+  debugger::Quit,
   // dprintln,
   instructions::{Instruction, OpCode},
+  tracer::Tracer,
   value::Value,
 };
 
+/// The error thrown by awaiting a coroutine that has finished.
+const FINISHED_CORO: &str = "The coroutine has finished";
+
 pub enum YieldResult {
   Finished(Value),
   Suspend(Value),
 }
 
+/// An error thrown by a script or a native function that was not
+/// caught by any exception handler.
+#[derive(Debug)]
+pub struct UncaughtError(pub String);
+
+impl std::fmt::Display for UncaughtError {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "Uncaught error: {}", self.0)
+  }
+}
+
+impl Error for UncaughtError {}
+
+/// The data of the host given to the natives, which the coroutines
+/// share with the `Vm` that started them.
+pub type UserData = Rc<RefCell<Box<dyn Any>>>;
+
+/// A function being called when an error was thrown.
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+  pub fn_name: String,
+  /// The line of the statement, if the function has debug
+  /// information
+  pub line: Option<usize>,
+}
+
+impl std::fmt::Display for TraceFrame {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self.line {
+      Some(line) => write!(f, "{} (line {line})", self.fn_name),
+      None => write!(f, "{}", self.fn_name),
+    }
+  }
+}
+
 pub struct StackFrame {
-  fn_def: Rc<FnByteCode>,
+  pub(crate) fn_def: Rc<FnByteCode>,
   args: usize,
   stack: Vec<Value>,
-  ip: usize,
+  pub(crate) ip: usize,
 }
 
 impl StackFrame {
@@ -29,7 +75,21 @@ impl StackFrame {
     }
   }
 
-  fn inst(&self) -> Option<Instruction> {
+  pub fn fn_name(&self) -> &str {
+    &self.fn_def.name
+  }
+
+  pub fn ip(&self) -> usize {
+    self.ip
+  }
+
+  pub fn stack(&self) -> &[Value] {
+    &self.stack
+  }
+
+  /// The instruction at the ip, or `None` at the end of the
+  /// function.
+  pub fn instruction(&self) -> Option<Instruction> {
     let ret = self.fn_def.instructions.get(self.ip)?;
     // dprintln!(
     //   "interpret[{}]: {:?} stack: {:?}",
@@ -44,8 +104,13 @@ impl StackFrame {
 pub struct Vm {
   bytecode: Rc<ByteCode>,
   stack_frames: Vec<StackFrame>,
-  user_data: Box<dyn std::any::Any>,
-  debug_output: bool,
+  user_data: UserData,
+  tracers: Vec<Rc<RefCell<dyn Tracer>>>,
+  /// The functions and the ips of the frames, outermost first,
+  /// where the last error was thrown. An error escaping from a
+  /// coroutine adds the frames of the coroutine after the ones of
+  /// the await site.
+  error_trace: Vec<(Rc<FnByteCode>, usize)>,
 }
 
 impl std::fmt::Debug for Vm {
@@ -58,19 +123,91 @@ impl std::fmt::Debug for Vm {
 }
 
 impl Vm {
+  /// Natives are given mutable access to `user_data`, which they
+  /// can downcast to the type of the host.
   pub fn new(
     bytecode: Rc<ByteCode>,
-    user_data: Box<dyn std::any::Any>,
-    debug_output: bool,
+    user_data: Box<dyn Any>,
+  ) -> Self {
+    Self::with_user_data(
+      bytecode,
+      Rc::new(RefCell::new(user_data)),
+    )
+  }
+
+  /// Create a `Vm` sharing the user data with the host, which can
+  /// see what the natives did to it after the run.
+  pub fn with_user_data(
+    bytecode: Rc<ByteCode>,
+    user_data: UserData,
   ) -> Self {
     Self {
       bytecode,
       stack_frames: vec![],
       user_data,
-      debug_output,
+      tracers: vec![],
+      error_trace: vec![],
     }
   }
 
+  /// Call a native with mutable access to the user data.
+  fn call_native(
+    user_data: &UserData,
+    native: &NativeFn,
+    args: &[Value],
+  ) -> Result<Value, String> {
+    let mut user_data =
+      user_data.try_borrow_mut().map_err(|_| {
+        "The user data is already borrowed".to_string()
+      })?;
+    (native.code)(user_data.as_mut(), args)
+  }
+
+  /// Add a tracer, which is also shared with the coroutines started
+  /// from this `Vm`. Tracers are called in the order they are added.
+  pub fn add_tracer(
+    &mut self,
+    tracer: Rc<RefCell<dyn Tracer>>,
+  ) {
+    self.tracers.push(tracer);
+  }
+
+  fn trace(
+    &self,
+    mut f: impl FnMut(&mut dyn Tracer) -> Result<(), Box<dyn Error>>,
+  ) -> Result<(), Box<dyn Error>> {
+    for tracer in &self.tracers {
+      f(&mut *tracer.borrow_mut())?;
+    }
+    Ok(())
+  }
+
+  /// The function and the source line where the last error was
+  /// thrown, to show where an uncaught error comes from.
+  pub fn error_location(&self) -> Option<(&str, usize)> {
+    let (fn_def, ip) = self.error_trace.last()?;
+    Some((&fn_def.name, fn_def.debug.line(*ip)?))
+  }
+
+  /// The functions being called when the last error was thrown,
+  /// innermost first.
+  pub fn error_backtrace(&self) -> Vec<TraceFrame> {
+    self
+      .error_trace
+      .iter()
+      .rev()
+      .map(|(fn_def, ip)| TraceFrame {
+        fn_name: fn_def.name.clone(),
+        line: fn_def.debug.line(*ip),
+      })
+      .collect()
+  }
+
+  /// Stack frames, outermost first.
+  pub fn frames(&self) -> &[StackFrame] {
+    &self.stack_frames
+  }
+
   pub fn top(&self) -> Result<&StackFrame, String> {
     self
       .stack_frames
@@ -88,8 +225,7 @@ impl Vm {
   /// A convenience function to run a function without the
   /// ability to suspend execution.
   /// An yield instruction would be an error.
-  #[allow(dead_code)]
-  fn run_fn(
+  pub fn run_fn(
     &mut self,
     fn_name: &str,
     args: &[Value],
@@ -101,9 +237,21 @@ impl Vm {
     let fn_def = match fn_def {
       FnDef::User(user) => user.clone(),
       FnDef::Native(n) => {
-        return Ok((*n.code)(self.user_data.as_ref(), args))
+        return Self::call_native(&self.user_data, n, args)
+          .map_err(|e| UncaughtError(e).into())
       }
     };
+    if fn_def.args.len() != args.len() {
+      return Err(
+        format!(
+          "Function {fn_name} expects {} argument(s), but {} were \
+          given",
+          fn_def.args.len(),
+          args.len()
+        )
+        .into(),
+      );
+    }
 
     self
       .stack_frames
@@ -142,111 +290,145 @@ impl Vm {
     &mut self,
     stack_pos: u8,
   ) -> Result<Option<YieldResult>, Box<dyn Error>> {
-    let top_frame = self
-      .stack_frames
-      .pop()
-      .ok_or("Stack frame underflow at Ret")?;
+    let top_frame =
+      self.top().map_err(|_| "Stack frame underflow at Ret")?;
     let res = top_frame
       .stack
-      .get(top_frame.stack.len() - stack_pos as usize - 1)
+      .len()
+      .checked_sub(stack_pos as usize + 1)
+      .and_then(|idx| top_frame.stack.get(idx))
       .ok_or("Stack underflow at Ret")?
       .clone();
     let args = top_frame.args;
+    self.trace(|tracer| tracer.on_return(self, &res))?;
+    self.stack_frames.pop();
 
     if self.stack_frames.is_empty() {
       return Ok(Some(YieldResult::Finished(res)));
     }
 
-    if self.debug_output {
-      println!("Returning {}", res);
-    }
-
     let stack = &mut self.top_mut()?.stack;
-    stack.resize(stack.len() - args - 1, Value::F64(0.));
+    let len = stack
+      .len()
+      .checked_sub(args + 1)
+      .ok_or("Stack underflow at Ret")?;
+    stack.truncate(len);
     stack.push(res);
     self.top_mut()?.ip += 1;
     Ok(None)
   }
 
+  /// Unwind the stack frames to the nearest exception handler and
+  /// resume execution there with the error value on the stack.
+  /// Returns an error if no handler catches it.
+  fn throw(
+    &mut self,
+    value: Value,
+  ) -> Result<(), Box<dyn Error>> {
+    let msg = match value {
+      Value::Str(msg) | Value::Error(msg) => msg,
+      value => value.to_string(),
+    };
+    self.error_trace = self
+      .stack_frames
+      .iter()
+      .map(|frame| (frame.fn_def.clone(), frame.ip))
+      .collect();
+    while let Some(frame) = self.stack_frames.last_mut() {
+      if let Some(handler) = frame.fn_def.find_handler(frame.ip)
+      {
+        let handler = *handler;
+        frame.stack.truncate(handler.stack_depth);
+        frame.stack.push(Value::Error(msg));
+        frame.ip = handler.target;
+        return Ok(());
+      }
+      self.stack_frames.pop();
+    }
+    Err(UncaughtError(msg).into())
+  }
+
   pub fn interpret(
     &mut self,
   ) -> Result<YieldResult, Box<dyn Error>> {
     loop {
-      let (instruction, ip) =
-        if let Some(instruction) = self.top()?.inst() {
-          (instruction, self.top()?.ip)
-        } else {
-          if let Some(res) = self.return_fn(0)? {
-            return Ok(res);
-          }
-          continue;
-        };
+      let Some(instruction) = self.top()?.instruction() else {
+        if let Some(res) = self.return_fn(0)? {
+          return Ok(res);
+        }
+        continue;
+      };
 
-      if self.debug_output {
-        println!(
-          "interpret[{ip}]: {instruction:?} stack: {stack:?}",
-          stack = self.top()?.stack
-        );
-      }
+      self.trace(|tracer| tracer.on_instruction(self))?;
 
       match instruction.op {
         OpCode::LoadLiteral => {
           let stack_frame = self.top_mut()?;
-          stack_frame.stack.push(
-            stack_frame.fn_def.literals
-              [instruction.arg0 as usize]
-              .clone(),
-          );
+          let literal = stack_frame
+            .fn_def
+            .literals
+            .get(instruction.arg0 as usize)
+            .ok_or("Literal index out of range")?;
+          stack_frame.stack.push(literal.clone());
         }
         OpCode::Store => {
           let stack = &mut self.top_mut()?.stack;
-          let idx = stack.len() - instruction.arg0 as usize - 1;
           let value =
-            stack.pop().expect("Store needs an argument");
-          stack[idx] = value;
+            stack.pop().ok_or("Store needs an argument")?;
+          let idx = stack
+            .len()
+            .checked_sub(instruction.arg0 as usize)
+            .ok_or("Stack underflow at Store")?;
+          *stack
+            .get_mut(idx)
+            .ok_or("Stack underflow at Store")? = value;
         }
         OpCode::Copy => {
           let stack = &mut self.top_mut()?.stack;
-          stack.push(
-            stack[stack.len() - instruction.arg0 as usize - 1]
-              .clone(),
-          );
+          let value = stack
+            .len()
+            .checked_sub(instruction.arg0 as usize + 1)
+            .and_then(|idx| stack.get(idx))
+            .ok_or("Stack underflow at Copy")?;
+          stack.push(value.clone());
         }
         OpCode::Dup => {
           let stack = &mut self.top_mut()?.stack;
-          let top = stack.last().unwrap().clone();
+          let top = stack
+            .last()
+            .ok_or("Stack underflow at Dup")?
+            .clone();
           stack
             .extend((0..instruction.arg0).map(|_| top.clone()));
         }
-        OpCode::Add => Self::interpret_bin_op_str(
-          &mut self.top_mut()?.stack,
-          |lhs, rhs| lhs + rhs,
-          |lhs, rhs| lhs + rhs,
-          |lhs, rhs| Some(format!("{lhs}{rhs}")),
-        ),
-        OpCode::Sub => Self::interpret_bin_op(
-          &mut self.top_mut()?.stack,
-          |lhs, rhs| lhs - rhs,
-          |lhs, rhs| lhs - rhs,
-        ),
-        OpCode::Mul => Self::interpret_bin_op(
-          &mut self.top_mut()?.stack,
-          |lhs, rhs| lhs * rhs,
-          |lhs, rhs| lhs * rhs,
-        ),
-        OpCode::Div => Self::interpret_bin_op(
-          &mut self.top_mut()?.stack,
-          |lhs, rhs| lhs / rhs,
-          |lhs, rhs| lhs / rhs,
-        ),
+        OpCode::Add
+        | OpCode::Sub
+        | OpCode::Mul
+        | OpCode::Div
+        | OpCode::Lt => {
+          let stack = &mut self.top_mut()?.stack;
+          if let Err(e) =
+            Self::interpret_arith(stack, instruction.op)
+          {
+            self.throw(Value::Error(e))?;
+            continue;
+          }
+        }
         OpCode::Call => {
           let stack = &self.top()?.stack;
-          let args =
-            &stack[stack.len() - instruction.arg0 as usize..];
-          let fname =
-            &stack[stack.len() - instruction.arg0 as usize - 1];
-          let Value::Str(fname) = fname else {
-            panic!("Function name shall be a string: {fname:?} in fn {:?}", self.top()?.stack);
+          let base = stack
+            .len()
+            .checked_sub(instruction.arg0 as usize + 1)
+            .ok_or("Stack underflow at Call")?;
+          let args = &stack[base + 1..];
+          let Value::Str(fname) = &stack[base] else {
+            return Err(
+              format!(
+                "Function name shall be a string: {:?}",
+                stack[base]
+              )
+              .into(),
+            );
           };
           let fn_def =
             self.bytecode.funcs.get(fname).ok_or_else(
@@ -256,23 +438,34 @@ impl Vm {
                 )
               },
             )?;
+          self.trace(|tracer| {
+            tracer.on_call(self, fname, args)
+          })?;
           match fn_def {
             FnDef::User(user_fn) => {
+              if user_fn.args.len() != args.len() {
+                return Err(
+                  format!(
+                    "Function {fname} expects {} argument(s), \
+                    but {} were given",
+                    user_fn.args.len(),
+                    args.len()
+                  )
+                  .into(),
+                );
+              }
               if user_fn.cofn {
-                let mut vm = Vm::new(
+                let mut vm = Vm::with_user_data(
                   self.bytecode.clone(),
-                  Box::new(()),
-                  self.debug_output,
+                  self.user_data.clone(),
                 );
+                vm.tracers = self.tracers.clone();
                 vm.stack_frames.push(StackFrame::new(
                   user_fn.clone(),
                   args.to_vec(),
                 ));
                 let stack = &mut self.top_mut()?.stack;
-                stack.resize(
-                  stack.len() - instruction.arg0 as usize - 1,
-                  Value::F64(0.),
-                );
+                stack.truncate(base);
                 stack
                   .push(Value::Coro(Rc::new(RefCell::new(vm))));
               } else {
@@ -284,13 +477,19 @@ impl Vm {
               }
             }
             FnDef::Native(native) => {
-              let res =
-                (native.code)(self.user_data.as_ref(), args);
+              let res = match Self::call_native(
+                &self.user_data,
+                native,
+                args,
+              ) {
+                Ok(res) => res,
+                Err(e) => {
+                  self.throw(Value::Error(e))?;
+                  continue;
+                }
+              };
               let stack = &mut self.top_mut()?.stack;
-              stack.resize(
-                stack.len() - instruction.arg0 as usize - 1,
-                Value::F64(0.),
-              );
+              stack.truncate(base);
               stack.push(res);
             }
           }
@@ -301,23 +500,29 @@ impl Vm {
         }
         OpCode::Jf => {
           let stack = &mut self.top_mut()?.stack;
-          let cond = stack.pop().expect("Jf needs an argument");
+          let cond =
+            stack.pop().ok_or("Jf needs an argument")?;
           if cond.coerce_f64() == Ok(0.) {
             self.top_mut()?.ip = instruction.arg0 as usize;
             continue;
           }
         }
-        OpCode::Lt => Self::interpret_bin_op(
-          &mut self.top_mut()?.stack,
-          |lhs, rhs| (lhs < rhs) as i32 as f64,
-          |lhs, rhs| (lhs < rhs) as i64,
-        ),
+        OpCode::Jn => {
+          let stack = &mut self.top_mut()?.stack;
+          let value =
+            stack.pop().ok_or("Jn needs an argument")?;
+          if value == Value::None {
+            self.top_mut()?.ip = instruction.arg0 as usize;
+            continue;
+          }
+        }
         OpCode::Pop => {
           let stack = &mut self.top_mut()?.stack;
-          stack.resize(
-            stack.len() - instruction.arg0 as usize,
-            Value::default(),
-          );
+          let len = stack
+            .len()
+            .checked_sub(instruction.arg0 as usize)
+            .ok_or("Stack underflow at Pop")?;
+          stack.truncate(len);
         }
         OpCode::Ret => {
           if let Some(res) = self.return_fn(instruction.arg0)? {
@@ -326,13 +531,14 @@ impl Vm {
           continue;
         }
         OpCode::Yield => {
-          let top_frame = self.top_mut()?;
-          let res = top_frame
+          let res = self
+            .top_mut()?
             .stack
             .pop()
             .ok_or_else(|| "Stack underflow".to_string())?;
+          self.trace(|tracer| tracer.on_yield(self, &res))?;
           // Increment the ip for the next call
-          top_frame.ip += 1;
+          self.top_mut()?.ip += 1;
           return Ok(YieldResult::Suspend(res));
         }
         OpCode::Await => {
@@ -346,54 +552,147 @@ impl Vm {
               "Await keyword applied to a non-coroutine".into(),
             );
           };
-          match vm.borrow_mut().interpret() {
-            Ok(YieldResult::Finished(_)) => (),
+          let Ok(mut vm) = vm.try_borrow_mut() else {
+            return Err(
+              "A coroutine cannot await itself".into(),
+            );
+          };
+          // A finished coroutine has no more values to give, which
+          // is thrown since `await` is typed as the yielded value.
+          if vm.stack_frames.is_empty() {
+            drop(vm);
+            self
+              .throw(Value::Error(FINISHED_CORO.to_string()))?;
+            continue;
+          }
+          self.trace(|tracer| tracer.on_await(self))?;
+          let res = vm.interpret();
+          let coro_trace = if res.is_err() {
+            std::mem::take(&mut vm.error_trace)
+          } else {
+            vec![]
+          };
+          drop(vm);
+          let value = match &res {
+            Ok(YieldResult::Suspend(value)) => Some(value),
+            _ => None,
+          };
+          self.trace(|tracer| tracer.on_resume(self, value))?;
+          match res {
+            Ok(YieldResult::Finished(_)) => {
+              self.throw(Value::Error(
+                FINISHED_CORO.to_string(),
+              ))?;
+              continue;
+            }
             Ok(YieldResult::Suspend(value)) => {
               self.top_mut()?.stack.push(value);
             }
+            Err(e) if e.is::<Quit>() => return Err(e),
             Err(e) => {
-              eprintln!("Runtime error: {e:?}");
+              // An error escaping from the coroutine is rethrown
+              // at the await site.
+              let msg = match e.downcast::<UncaughtError>() {
+                Ok(e) => e.0,
+                Err(e) => e.to_string(),
+              };
+              let res = self.throw(Value::Error(msg));
+              self.error_trace.extend(coro_trace);
+              res?;
+              continue;
             }
           };
         }
+        OpCode::Throw => {
+          let value = self
+            .top_mut()?
+            .stack
+            .pop()
+            .ok_or_else(|| "Stack underflow".to_string())?;
+          self.throw(value)?;
+          continue;
+        }
       }
       self.top_mut()?.ip += 1;
     }
   }
 
+  /// Pop the operands of an arithmetic or comparison operator and
+  /// push the result, or return the error to throw.
+  fn interpret_arith(
+    stack: &mut Vec<Value>,
+    op: OpCode,
+  ) -> Result<(), String> {
+    match op {
+      OpCode::Add => Self::interpret_bin_op_str(
+        stack,
+        |lhs, rhs| lhs + rhs,
+        |lhs, rhs| Some(lhs.wrapping_add(rhs)),
+        |lhs, rhs| Some(format!("{lhs}{rhs}")),
+      ),
+      OpCode::Sub => Self::interpret_bin_op(
+        stack,
+        |lhs, rhs| lhs - rhs,
+        |lhs, rhs| Some(lhs.wrapping_sub(rhs)),
+      ),
+      OpCode::Mul => Self::interpret_bin_op(
+        stack,
+        |lhs, rhs| lhs * rhs,
+        |lhs, rhs| Some(lhs.wrapping_mul(rhs)),
+      ),
+      OpCode::Div => Self::interpret_bin_op(
+        stack,
+        |lhs, rhs| lhs / rhs,
+        |lhs, rhs| (rhs != 0).then(|| lhs.wrapping_div(rhs)),
+      ),
+      OpCode::Lt => Self::interpret_bin_op(
+        stack,
+        |lhs, rhs| (lhs < rhs) as i32 as f64,
+        |lhs, rhs| Some((lhs < rhs) as i64),
+      ),
+      _ => Err(format!("{op:?} is not a binary operator")),
+    }
+  }
+
+  /// `op_i64` returns `None` for a division by zero.
   fn interpret_bin_op_str(
     stack: &mut Vec<Value>,
     op_f64: impl FnOnce(f64, f64) -> f64,
-    op_i64: impl FnOnce(i64, i64) -> i64,
+    op_i64: impl FnOnce(i64, i64) -> Option<i64>,
     op_str: impl FnOnce(&str, &str) -> Option<String>,
-  ) {
+  ) -> Result<(), String> {
     use Value::*;
-    let rhs = stack.pop().expect("Stack underflow");
-    let lhs = stack.pop().expect("Stack underflow");
+    let rhs = stack.pop().ok_or("Stack underflow")?;
+    let lhs = stack.pop().ok_or("Stack underflow")?;
     let res = match (lhs, rhs) {
       (F64(lhs), F64(rhs)) => F64(op_f64(lhs, rhs)),
-      (I64(lhs), I64(rhs)) => I64(op_i64(lhs, rhs)),
+      (I64(lhs), I64(rhs)) => I64(
+        op_i64(lhs, rhs).ok_or("Integer division by zero")?,
+      ),
       (F64(lhs), I64(rhs)) => F64(op_f64(lhs, rhs as f64)),
       (I64(lhs), F64(rhs)) => F64(op_f64(lhs as f64, rhs)),
       (Str(lhs), Str(rhs)) => {
-        if let Some(res) = op_str(&lhs, &rhs) {
-          Str(res)
-        } else {
-          panic!("Incompatible types in binary op: {lhs:?} and {rhs:?}");
-        }
+        Str(op_str(&lhs, &rhs).ok_or_else(|| {
+          format!(
+            "Incompatible types in binary op: {lhs:?} and {rhs:?}"
+          )
+        })?)
+      }
+      (lhs, rhs) => {
+        return Err(format!(
+          "Incompatible types in binary op: {lhs:?} and {rhs:?}"
+        ))
       }
-      (lhs, rhs) => panic!(
-        "Incompatible types in binary op: {lhs:?} and {rhs:?}"
-      ),
     };
     stack.push(res);
+    Ok(())
   }
 
   fn interpret_bin_op(
     stack: &mut Vec<Value>,
     op_f64: impl FnOnce(f64, f64) -> f64,
-    op_i64: impl FnOnce(i64, i64) -> i64,
-  ) {
+    op_i64: impl FnOnce(i64, i64) -> Option<i64>,
+  ) -> Result<(), String> {
     Self::interpret_bin_op_str(stack, op_f64, op_i64, |_, _| {
       None
     })
@@ -411,18 +710,24 @@ pub fn debugger(vm: &Vm) -> bool {
   println!("[c]ontinue/[p]rint/[e]xit/[bt]race?");
   loop {
     let mut buffer = String::new();
-    if std::io::stdin().read_line(&mut buffer).is_ok() {
-      match buffer.trim() {
-        "c" => return false,
-        "p" => {
-          println!("Stack: {:?}", vm.top().unwrap().stack);
-        }
-        "e" => return true,
-        "bt" => vm.back_trace(),
-        _ => println!(
-          "Please say [c]ontinue/[p]rint/[b]reak/[bt]race"
-        ),
-      }
+    // Without more input, the program cannot be continued.
+    if !matches!(
+      std::io::stdin().read_line(&mut buffer),
+      Ok(1..)
+    ) {
+      return true;
+    }
+    match buffer.trim() {
+      "c" => return false,
+      "p" => match vm.top() {
+        Ok(top) => println!("Stack: {:?}", top.stack),
+        Err(e) => println!("{e}"),
+      },
+      "e" => return true,
+      "bt" => vm.back_trace(),
+      _ => println!(
+        "Please say [c]ontinue/[p]rint/[b]reak/[bt]race"
+      ),
     }
   }
 }
@@ -0,0 +1,52 @@
+//! Every script disassembled and assembled again gives the bytes
+//! it was compiled to.
+
+use std::path::Path;
+
+use ruscal::{
+  asm::{assemble, disasm_file, write_funcs},
+  file_io::write_program,
+  Args,
+};
+
+#[test]
+fn disassembled_scripts_assemble_to_the_same_bytes() {
+  let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let mut paths: Vec<_> =
+    std::fs::read_dir(root.join("scripts"))
+      .unwrap()
+      .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+      .filter(|path| {
+        path.extension().is_some_and(|ext| ext == "rscl")
+      })
+      .collect();
+  paths.sort();
+
+  let args = Args::new();
+  let mut compiled = 0;
+  let mut differ = vec![];
+  for path in paths {
+    let name = path.display().to_string();
+    let src = std::fs::read_to_string(&path).unwrap();
+    let mut bytes = vec![];
+    if write_program(&name, &src, &mut bytes, "", &args)
+      .is_err()
+    {
+      continue;
+    }
+    compiled += 1;
+
+    let mut text = vec![];
+    disasm_file(&mut &bytes[..], &mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    let funcs =
+      assemble(&text).unwrap_or_else(|e| panic!("{name}: {e}"));
+    let mut reassembled = vec![];
+    write_funcs(&funcs, &mut reassembled).unwrap();
+    if reassembled != bytes {
+      differ.push(name);
+    }
+  }
+  assert!(compiled > 0);
+  assert!(differ.is_empty(), "{differ:#?}");
+}
@@ -0,0 +1,195 @@
+//! The nodes of the exported AST, by example.
+
+use std::process::Command;
+
+use ruscal::{
+  ast::Span,
+  ast_export::{to_json, to_sexpr},
+  parser::statements_finish,
+};
+use serde_json::{json, Value};
+
+fn export(src: &str) -> Value {
+  let stmts = statements_finish(Span::new(src))
+    .expect("the example should parse");
+  to_json(&stmts)
+}
+
+/// A span from the 1-based line and column, the byte offset and the
+/// byte length.
+fn span(
+  line: u32,
+  column: usize,
+  offset: usize,
+  len: usize,
+) -> Value {
+  json!({ "line": line, "column": column, "offset": offset, "len": len })
+}
+
+#[test]
+fn var_def_with_a_type_annotation() {
+  assert_eq!(
+    export("var x: i64 = 1;"),
+    json!([{
+      "kind": "VarDef",
+      "span": span(1, 1, 0, 15),
+      "name": "x",
+      "type": "i64",
+      "value": {
+        "kind": "NumLiteral",
+        "span": span(1, 14, 13, 1),
+        "value": 1.0,
+      },
+    }])
+  );
+}
+
+#[test]
+fn inferred_types_are_null() {
+  let ast = export("var s = \"a\";");
+  assert_eq!(ast[0]["type"], Value::Null);
+  assert_eq!(
+    ast[0]["value"],
+    json!({
+      "kind": "StrLiteral",
+      "span": span(1, 9, 8, 3),
+      "value": "a",
+    })
+  );
+}
+
+#[test]
+fn fn_def_with_arguments() {
+  let src = "fn add(a: i64, b: f64?) -> i64 {\n  a + b\n}";
+  assert_eq!(
+    export(src),
+    json!([{
+      "kind": "FnDef",
+      "span": span(1, 1, 0, 42),
+      "name": "add",
+      "args": [
+        {
+          "kind": "Arg",
+          "span": span(1, 8, 7, 1),
+          "name": "a",
+          "type": "i64",
+        },
+        {
+          "kind": "Arg",
+          "span": span(1, 16, 15, 1),
+          "name": "b",
+          "type": "f64?",
+        },
+      ],
+      "ret_type": "i64",
+      "cofn": false,
+      "test": false,
+      "body": [{
+        "kind": "Expression",
+        "span": span(2, 3, 35, 5),
+        "expr": {
+          "kind": "Add",
+          "span": span(2, 3, 35, 5),
+          "lhs": {
+            "kind": "Ident",
+            "span": span(2, 3, 35, 1),
+            "name": "a",
+          },
+          "rhs": {
+            "kind": "Ident",
+            "span": span(2, 7, 39, 1),
+            "name": "b",
+          },
+        },
+      }],
+    }])
+  );
+}
+
+#[test]
+fn coroutine_types() {
+  let ast = export("cofn gen() -> cofn<str> { yield \"a\"; }");
+  assert_eq!(ast[0]["kind"], "FnDef");
+  assert_eq!(ast[0]["cofn"], true);
+  assert_eq!(ast[0]["ret_type"], "cofn<str>");
+  assert_eq!(ast[0]["body"][0]["kind"], "Yield");
+  assert_eq!(ast[0]["body"][0]["value"]["kind"], "StrLiteral");
+}
+
+#[test]
+fn if_without_else_and_break() {
+  let ast = export("for i in 0 to 3 { if i < 1 { break; } }");
+  let body = &ast[0]["body"];
+  assert_eq!(ast[0]["kind"], "For");
+  assert_eq!(ast[0]["var"], "i");
+  assert_eq!(body[0]["expr"]["kind"], "If");
+  assert_eq!(body[0]["expr"]["cond"]["kind"], "Lt");
+  assert_eq!(
+    body[0]["expr"]["then"],
+    json!([{ "kind": "Break", "span": span(1, 30, 29, 5) }])
+  );
+  assert_eq!(body[0]["expr"]["else"], Value::Null);
+}
+
+#[test]
+fn interpolated_strings() {
+  let ast = export("\"x = {x:.3}\";");
+  assert_eq!(
+    ast[0]["expr"]["parts"],
+    json!([
+      { "kind": "Text", "span": null, "value": "x = " },
+      {
+        "kind": "Expr",
+        "span": span(1, 7, 6, 1),
+        "expr": {
+          "kind": "Ident",
+          "span": span(1, 7, 6, 1),
+          "name": "x",
+        },
+        "spec": ".3",
+      },
+    ])
+  );
+}
+
+#[test]
+fn try_catch_and_calls() {
+  let ast = export("try { f(1); } catch e { print(e); }");
+  let ex = &ast[0]["expr"];
+  assert_eq!(ex["kind"], "Try");
+  assert_eq!(ex["name"], "e");
+  assert_eq!(ex["body"][0]["expr"]["kind"], "FnInvoke");
+  assert_eq!(ex["body"][0]["expr"]["name"], "f");
+  assert_eq!(ex["body"][0]["expr"]["args"][0]["value"], 1.0);
+  assert_eq!(ex["handler"][0]["expr"]["name"], "print");
+}
+
+#[test]
+fn sexpr_has_the_same_fields() {
+  let src = "var x: i64 = f(1);\nbreak;";
+  let stmts = statements_finish(Span::new(src)).unwrap();
+  assert_eq!(
+    to_sexpr(&stmts),
+    r#"((VarDef :span (1 1 0 18) :name "x" :type "i64"
+  :value (FnInvoke :span (1 14 13 4) :name "f"
+          :args ((NumLiteral :span (1 16 15 1) :value 1.0))))
+ (Break :span (2 1 19 5)))"#
+  );
+}
+
+#[test]
+fn command_line_prints_only_the_ast() {
+  let path =
+    std::env::temp_dir().join("ruscal_ast_export.rscl");
+  std::fs::write(&path, "var x = 1;\n").unwrap();
+  let output = Command::new(env!("CARGO_BIN_EXE_ruscal"))
+    .arg("--ast-format=json")
+    .arg(&path)
+    .output()
+    .expect("failed to run ruscal");
+  std::fs::remove_file(&path).unwrap();
+  assert!(output.status.success());
+  let ast: Value = serde_json::from_slice(&output.stdout)
+    .expect("stdout should be only the JSON");
+  assert_eq!(ast, export("var x = 1;\n"));
+}
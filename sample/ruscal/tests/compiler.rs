@@ -0,0 +1,109 @@
+//! Programs whose jumps, literals or stack offsets do not fit in the
+//! byte-sized argument of an instruction.
+
+use std::{cell::RefCell, error::Error, rc::Rc};
+
+use ruscal::{
+  compiler::Compiler,
+  diagnostics::Diagnostic,
+  file_io::parse_program,
+  tracer::Tracer,
+  value::Value,
+  vm::{Vm, YieldResult},
+};
+
+/// Counts the instructions run.
+#[derive(Default)]
+struct Count(usize);
+
+impl Tracer for Count {
+  fn on_instruction(
+    &mut self,
+    _vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    self.0 += 1;
+    Ok(())
+  }
+}
+
+fn compile(src: &str) -> Result<Compiler, Box<dyn Error>> {
+  let stmts = parse_program("test.rscl", src).unwrap();
+  let mut compiler = Compiler::new();
+  compiler.compile(&stmts)?;
+  Ok(compiler)
+}
+
+/// The message and the line of the compile error.
+fn compile_error(src: &str) -> (String, u32) {
+  let Err(e) = compile(src) else {
+    panic!("{src} compiled");
+  };
+  let diag = e.downcast::<Diagnostic>().unwrap();
+  (diag.msg, diag.primary.unwrap().line)
+}
+
+/// Sixty assignments, which take more than 256 instructions.
+fn long_body() -> String {
+  let mut src = "var x: f64 = 0;\n".to_string();
+  for i in 0..60 {
+    src += &format!("x = x + {i};\n");
+  }
+  src
+}
+
+#[test]
+fn long_bodies_run_without_jumps() {
+  let src = long_body() + "x + 1;";
+  let mut vm = Vm::new(
+    Rc::new(compile(&src).unwrap().into_bytecode()),
+    Box::new(()),
+  );
+  let count = Rc::new(RefCell::new(Count::default()));
+  vm.add_tracer(count.clone());
+  vm.init_fn("main", &[]).unwrap();
+  let res = vm.interpret().unwrap();
+  assert!(matches!(
+    res,
+    YieldResult::Finished(Value::F64(x)) if x == 1771.
+  ));
+  assert!(count.borrow().0 > 256, "{}", count.borrow().0);
+}
+
+#[test]
+fn jumps_past_the_last_instruction_are_an_error() {
+  let src = long_body()
+    + "if x > 1 { print(\"big\"); } else { print(\"small\"); };";
+  let (msg, line) = compile_error(&src);
+  assert!(msg.starts_with("Jump target "), "{msg}");
+  assert_eq!(line, 62);
+
+  let src = long_body() + "for i in 0 to 3 { x = x + i; }";
+  let (msg, line) = compile_error(&src);
+  assert!(msg.starts_with("Jump target "), "{msg}");
+  assert_eq!(line, 62);
+}
+
+#[test]
+fn too_many_literals_are_an_error() {
+  let src: String =
+    (0..300).map(|i| format!("print({i});\n")).collect();
+  let (msg, line) = compile_error(&src);
+  assert_eq!(
+    msg,
+    "Literal number 256 does not fit in an instruction, which \
+    takes at most 255"
+  );
+  assert_eq!(line, 256);
+}
+
+#[test]
+fn too_many_arguments_are_an_error() {
+  let args = vec!["1"; 256].join(", ");
+  let (msg, line) = compile_error(&format!("print({args});"));
+  assert_eq!(
+    msg,
+    "Number of arguments 256 does not fit in an instruction, \
+    which takes at most 255"
+  );
+  assert_eq!(line, 1);
+}
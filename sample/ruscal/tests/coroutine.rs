@@ -0,0 +1,44 @@
+//! Awaiting coroutines through their whole life.
+
+use ruscal::engine::Engine;
+
+#[test]
+fn awaiting_a_finished_coroutine_is_thrown() {
+  let src = r#"
+    cofn one() -> i64 { yield i64(1); }
+    var co = one();
+    var a: i64 = await co;
+    try { await co; } catch e { };
+    var b: i64 = try { await co } catch e { i64(10) };
+    a + b;
+  "#;
+  let res: i64 = Engine::new().eval(src).unwrap();
+  assert_eq!(res, 11);
+}
+
+#[test]
+fn await_gives_only_yielded_values() {
+  let src = r#"
+    cofn two() -> i64 { yield i64(1); yield i64(2); }
+    var co = two();
+    var a: i64 = await co;
+    var b: i64 = await co;
+    var c: i64 = try { await co } catch e { i64(100) };
+    var d: i64 = try { await co } catch e { i64(100) };
+    a + b + c + d;
+  "#;
+  let res: i64 = Engine::new().eval(src).unwrap();
+  assert_eq!(res, 203);
+
+  let src = r#"
+    cofn none_left() -> i64 { i64(0) }
+    var co = none_left();
+    var v: i64 = await co;
+    v + i64(1);
+  "#;
+  let err = Engine::new().eval::<i64>(src).unwrap_err();
+  assert!(
+    err.to_string().starts_with("The coroutine has finished"),
+    "{err}"
+  );
+}
@@ -0,0 +1,151 @@
+//! Embedding through `Engine`, with natives registered as closures.
+
+use ruscal::{
+  diagnostics::Aborted,
+  engine::{Engine, RuntimeError},
+  value::Value,
+};
+
+#[test]
+fn eval_calls_registered_closures() {
+  let mut engine = Engine::new();
+  engine.register_fn("hypot", |x: f64, y: f64| x.hypot(y));
+  engine.register_fn("repeat", |s: String, n: i64| {
+    s.repeat(n as usize)
+  });
+  engine.register_fn("answer", || 42i64);
+  let res: f64 = engine.eval("hypot(3, 4);").unwrap();
+  assert_eq!(res, 5.);
+  let res: String = engine.eval("repeat(\"ab\", 3);").unwrap();
+  assert_eq!(res, "ababab");
+  let res: i64 = engine.eval("answer() + 1;").unwrap();
+  assert_eq!(res, 43);
+}
+
+#[test]
+fn signatures_are_type_checked() {
+  let mut engine = Engine::new();
+  engine.register_fn("hypot", |x: f64, y: f64| x.hypot(y));
+  for src in ["hypot(\"a\", 1);", "hypot(1);"] {
+    let err = engine.eval::<Value>(src).unwrap_err();
+    assert!(err.is::<Aborted>(), "{src}: {err}");
+  }
+}
+
+#[test]
+fn optional_values_are_none_or_the_value() {
+  let mut engine = Engine::new();
+  engine
+    .register_fn("or_zero", |x: Option<i64>| x.unwrap_or(0));
+  engine.register_fn("find", |s: String| {
+    s.find('x').map(|i| i as i64)
+  });
+  let res: i64 =
+    engine.eval("or_zero(none) + or_zero(some(2));").unwrap();
+  assert_eq!(res, 2);
+  let res: Option<i64> = engine.eval("find(\"abc\");").unwrap();
+  assert_eq!(res, None);
+  let res: i64 = engine.eval("find(\"abx\") ?? 0;").unwrap();
+  assert_eq!(res, 2);
+}
+
+#[test]
+fn call_runs_a_function_of_the_program() {
+  let engine = Engine::new();
+  let program = engine
+    .compile("fn add(x: f64, y: f64) -> f64 { x + y }")
+    .unwrap();
+  let res: f64 =
+    engine.call(&program, "add", (1., 2.)).unwrap();
+  assert_eq!(res, 3.);
+  let res: f64 =
+    engine.call(&program, "add", (3., 4.)).unwrap();
+  assert_eq!(res, 7.);
+  assert!(engine.call::<f64>(&program, "add", (1.,)).is_err());
+  assert!(engine.call::<f64>(&program, "sub", ()).is_err());
+}
+
+#[test]
+fn uncaught_errors_are_returned() {
+  let engine = Engine::new();
+  let err =
+    engine.eval::<Value>("throw \"oops\";").unwrap_err();
+  assert!(err.to_string().contains("oops"), "{err}");
+}
+
+#[test]
+fn natives_mutate_the_host_context() {
+  let mut engine = Engine::with_host(Vec::<String>::new());
+  engine
+    .register_fn("emit", |out: &mut Vec<String>, s: String| {
+      out.push(s)
+    });
+  engine.register_fn("count", |out: &mut Vec<String>| {
+    out.len() as i64
+  });
+  let src = r#"
+    cofn gen() -> i64 { emit("in coroutine"); yield count(); }
+    for i in 0 to 2 { emit("line {i}"); }
+    var g = gen();
+    await g;
+  "#;
+  let res: i64 = engine.eval(src).unwrap();
+  assert_eq!(res, 3);
+  assert_eq!(
+    *engine.host(),
+    ["line 0", "line 1", "in coroutine"]
+  );
+  engine.host_mut().clear();
+  let res: i64 =
+    engine.eval("emit(\"again\"); count();").unwrap();
+  assert_eq!(res, 1);
+}
+
+#[test]
+fn native_errors_are_thrown_with_a_backtrace() {
+  let mut engine = Engine::new();
+  engine.register_fn("parse", |s: String| s.parse::<i64>());
+  let src = r#"
+    fn parse_or(s: str, default: i64) -> i64 {
+      try { parse(s) } catch e { default }
+    }
+    parse_or("12", 0) + parse_or("x", 1);
+  "#;
+  let res: i64 = engine.eval(src).unwrap();
+  assert_eq!(res, 13);
+
+  let src = r#"
+    fn check(s: str) -> i64 {
+      parse(s)
+    }
+    check("x");
+  "#;
+  let err = engine.eval::<i64>(src).unwrap_err();
+  let err = err.downcast::<RuntimeError>().unwrap();
+  assert_eq!(err.message, "invalid digit found in string");
+  let frames: Vec<_> = err
+    .backtrace
+    .iter()
+    .map(|frame| (frame.fn_name.as_str(), frame.line))
+    .collect();
+  assert_eq!(frames, [("check", Some(3)), ("main", Some(5))]);
+}
+
+#[test]
+fn eval_returns_the_last_statement_after_locals() {
+  let src = r#"
+    fn sum_to(n: i64) -> i64 {
+      var sum = i64(0);
+      for i in 0 to n {
+        var v = i64(7);
+        sum = sum + v;
+      }
+      sum
+    }
+    var total = sum_to(i64(5));
+    var unused = 1;
+    total;
+  "#;
+  let res: i64 = Engine::new().eval(src).unwrap();
+  assert_eq!(res, 35);
+}
@@ -0,0 +1,102 @@
+//! The `format` native and interpolated string literals.
+
+use std::rc::Rc;
+
+use ruscal::{
+  compiler::Compiler,
+  file_io::parse_program,
+  value::Value,
+  vm::{Vm, YieldResult},
+};
+
+/// Run the source and return the string it evaluates to, or the
+/// error it throws.
+fn eval(src: &str) -> Result<String, String> {
+  let stmts = parse_program("test.rscl", src).unwrap();
+  let mut compiler = Compiler::new();
+  compiler.compile(&stmts).unwrap();
+  let mut vm =
+    Vm::new(Rc::new(compiler.into_bytecode()), Box::new(()));
+  vm.init_fn("main", &[]).unwrap();
+  match vm.interpret().map_err(|e| e.to_string())? {
+    YieldResult::Finished(Value::Str(s)) => Ok(s),
+    _ => panic!("{src} did not finish with a string"),
+  }
+}
+
+#[test]
+fn format_replaces_placeholders() {
+  for (src, expected) in [
+    (r#"format("{} and {}", 1, "a");"#, "1 and a"),
+    (
+      r#"format("{:>6.2}|{:<4}|", 3.14159, "ab");"#,
+      "  3.14|ab  |",
+    ),
+    (r#"format("{:08.3}", 3.14159);"#, "0003.142"),
+    (
+      r#"format("{:x} {:04X}", i64(255), i64(255));"#,
+      "ff 00FF",
+    ),
+    (r#"format("{:^7}", "mid");"#, "  mid  "),
+  ] {
+    assert_eq!(eval(src).as_deref(), Ok(expected), "{src}");
+  }
+}
+
+#[test]
+fn format_unescapes_braces_once() {
+  for (src, expected) in [
+    (r#"format("{{}} {}", 1);"#, "{} 1"),
+    (r#"format("{{x}}");"#, "{x}"),
+    (r#"format("}}{{");"#, "}{"),
+  ] {
+    assert_eq!(eval(src).as_deref(), Ok(expected), "{src}");
+  }
+}
+
+#[test]
+fn format_errors_are_thrown() {
+  for (src, msg) in [
+    (r#"format("{} {}", 1);"#, "Not enough arguments"),
+    (r#"format(format("{{x}}"), 1);"#, "Invalid placeholder"),
+    (r#"format("{:.}", 1);"#, "Missing precision"),
+  ] {
+    let err = eval(src).unwrap_err();
+    assert!(err.contains(msg), "{src}: {err}");
+  }
+}
+
+#[test]
+fn interpolation_formats_expressions() {
+  let src = r#"
+    var x = 3.14159;
+    var n = i64(255);
+    var name = "ruscal";
+    "{x:.3} {n} {n:x} {n + i64(1)} [{name:>8}]";
+  "#;
+  assert_eq!(
+    eval(src).as_deref(),
+    Ok("3.142 255 ff 256 [  ruscal]")
+  );
+}
+
+#[test]
+fn interpolation_keeps_literal_braces_and_placeholders() {
+  let src = r#"
+    var n = 1;
+    "{n} {{}} {{n}} {} {:.2} { }";
+  "#;
+  assert_eq!(
+    eval(src).as_deref(),
+    Ok("1 {{}} {{n}} {} {:.2} { }")
+  );
+}
+
+#[test]
+fn braces_print_the_same_with_or_without_interpolation() {
+  let plain = eval(r#""x {{ y }} } z {";"#).unwrap();
+  assert_eq!(plain, "x {{ y }} } z {");
+  let interpolated =
+    eval(r#"var n = 1; "{n}: x {{ y }} } z {";"#).unwrap();
+  assert_eq!(interpolated, format!("1: {plain}"));
+}
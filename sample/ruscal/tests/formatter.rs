@@ -0,0 +1,150 @@
+//! The formatter over the scripts in `scripts/` and over comments
+//! in places that are easy to lose.
+
+use std::path::{Path, PathBuf};
+
+use ruscal::formatter::{format_source, FormatConfig};
+
+fn format(src: &str) -> String {
+  format_source(src, &FormatConfig::new())
+    .unwrap_or_else(|e| panic!("{src}: {e:?}"))
+}
+
+/// The scripts that parse, with their sources.
+fn scripts() -> Vec<(PathBuf, String)> {
+  let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let mut scripts: Vec<_> =
+    std::fs::read_dir(root.join("scripts"))
+      .unwrap()
+      .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+      .filter(|path| {
+        path.extension().is_some_and(|ext| ext == "rscl")
+      })
+      .filter_map(|path| {
+        let src = std::fs::read_to_string(&path).unwrap();
+        format_source(&src, &FormatConfig::new())
+          .is_ok()
+          .then_some((path, src))
+      })
+      .collect();
+  scripts.sort();
+  assert!(!scripts.is_empty());
+  scripts
+}
+
+/// The `//` comments in the order they appear.
+fn comments(src: &str) -> Vec<&str> {
+  src
+    .lines()
+    .filter_map(|line| {
+      line.find("//").map(|pos| line[pos..].trim_end())
+    })
+    .collect()
+}
+
+#[test]
+fn formatting_is_idempotent() {
+  for (path, src) in scripts() {
+    let formatted = format(&src);
+    assert_eq!(
+      format(&formatted),
+      formatted,
+      "{}",
+      path.display()
+    );
+  }
+}
+
+#[test]
+fn formatting_keeps_comments_in_order() {
+  for (path, src) in scripts() {
+    assert_eq!(
+      comments(&format(&src)),
+      comments(&src),
+      "{}",
+      path.display()
+    );
+  }
+}
+
+#[test]
+fn comments_in_arguments_follow_their_argument() {
+  let src = "var x = add(1, // one
+  2); // after
+print(
+x, // the value
+// on its own line
+add(x, 1), // nested
+);
+var y = 1 + // plus
+  2;
+";
+  let expected = "var x = add(
+    1, // one
+    2,
+); // after
+print(
+    x, // the value
+    // on its own line
+    add(x, 1), // nested
+);
+// plus
+var y = 1 + 2;
+";
+  assert_eq!(format(src), expected);
+  assert_eq!(format(expected), expected);
+}
+
+#[test]
+fn comments_in_nested_arguments() {
+  let src = "print(f(a, // a
+b), c);
+";
+  let expected = "print(
+    f(
+        a, // a
+        b,
+    ),
+    c,
+);
+";
+  assert_eq!(format(src), expected);
+  assert_eq!(format(expected), expected);
+}
+
+#[test]
+fn comments_in_parameters_follow_their_parameter() {
+  let src = "fn add(a: f64, // first
+  b: f64) -> f64 {
+  a + b
+}
+fn none(
+// nothing
+) -> f64 { 1 }
+cofn gen(n: i64
+// on its own line
+, last: i64 // last
+) -> i64 { yield n; }
+";
+  let expected = "fn add(
+    a: f64, // first
+    b: f64
+) -> f64 {
+    a + b
+}
+fn none(
+    // nothing
+) -> f64 {
+    1
+}
+cofn gen(
+    n: i64,
+    // on its own line
+    last: i64 // last
+) -> i64 {
+    yield n;
+}
+";
+  assert_eq!(format(src), expected);
+  assert_eq!(format(expected), expected);
+}
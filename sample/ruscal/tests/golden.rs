@@ -0,0 +1,314 @@
+//! Golden-output tests of the scripts in `scripts/`.
+//!
+//! Each `scripts/name.rscl` is compiled and run by `ruscal -R` with
+//! no input, and its stdout and stderr are compared with
+//! `scripts/name.out` and `scripts/name.err`, where a missing file
+//! expects no output. Mismatches are shown as unified diffs.
+//!
+//!     cargo test --test golden [-- [--bless] [filter...]]
+//!
+//! `--bless` writes the expectations from the current output instead,
+//! and filters run only the scripts whose names contain one of them.
+
+use std::{
+  io::Read,
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+  thread,
+  time::{Duration, Instant},
+};
+
+/// Time a script can run before it is considered hung.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Lines of context around the changes in diffs.
+const CONTEXT: usize = 3;
+
+struct Output {
+  stdout: String,
+  stderr: String,
+}
+
+fn main() {
+  let mut bless = false;
+  let mut filters = vec![];
+  for arg in std::env::args().skip(1) {
+    match arg.as_str() {
+      "--bless" => bless = true,
+      // Options meant for the default test harness
+      _ if arg.starts_with('-') => (),
+      _ => filters.push(arg),
+    }
+  }
+
+  let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let mut scripts: Vec<_> =
+    std::fs::read_dir(root.join("scripts"))
+      .expect("scripts directory should be readable")
+      .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+      .filter(|path| {
+        path.extension().is_some_and(|ext| ext == "rscl")
+      })
+      .filter(|path| {
+        let name = path.file_stem().unwrap().to_string_lossy();
+        filters.is_empty()
+          || filters.iter().any(|f| name.contains(f.as_str()))
+      })
+      .collect();
+  scripts.sort();
+
+  let plural = if scripts.len() == 1 { "" } else { "s" };
+  println!("running {} script{plural}", scripts.len());
+  let mut failures = vec![];
+  for script in &scripts {
+    let name = script.file_stem().unwrap().to_string_lossy();
+    let result = run(root, script).and_then(|output| {
+      if bless {
+        bless_output(script, &output)
+      } else {
+        check_output(script, &output)
+      }
+    });
+    match result {
+      Ok(()) if bless => println!("golden {name} ... blessed"),
+      Ok(()) => println!("golden {name} ... ok"),
+      Err(msg) => {
+        println!("golden {name} ... FAILED");
+        failures.push((name, msg));
+      }
+    }
+  }
+
+  if !failures.is_empty() {
+    println!();
+    println!("failures:");
+    for (name, msg) in &failures {
+      println!();
+      println!("---- {name} ----");
+      print!("{msg}");
+    }
+    println!();
+    println!(
+      "To accept the new output, run \
+       `cargo test --test golden -- --bless`"
+    );
+  }
+  println!();
+  println!(
+    "golden result: {}. {} passed; {} failed",
+    if failures.is_empty() { "ok" } else { "FAILED" },
+    scripts.len() - failures.len(),
+    failures.len()
+  );
+  if !failures.is_empty() {
+    std::process::exit(1);
+  }
+}
+
+/// Run the script from the root of the crate, so that the paths in
+/// the diagnostics are relative to it.
+fn run(root: &Path, script: &Path) -> Result<Output, String> {
+  let relative = script.strip_prefix(root).unwrap_or(script);
+  let mut child = Command::new(env!("CARGO_BIN_EXE_ruscal"))
+    .arg("-R")
+    .arg(relative)
+    .current_dir(root)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|e| format!("failed to start ruscal: {e}\n"))?;
+
+  // Read the pipes while waiting, so that a full pipe does not
+  // block the script.
+  let read_all = |mut pipe: Box<dyn Read + Send>| {
+    thread::spawn(move || {
+      let mut buf = vec![];
+      pipe.read_to_end(&mut buf).map(|_| buf)
+    })
+  };
+  let stdout = read_all(Box::new(child.stdout.take().unwrap()));
+  let stderr = read_all(Box::new(child.stderr.take().unwrap()));
+
+  let start = Instant::now();
+  loop {
+    match child.try_wait() {
+      Ok(Some(_)) => break,
+      Ok(None) if start.elapsed() > TIMEOUT => {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(format!(
+          "timed out after {}s\n",
+          TIMEOUT.as_secs()
+        ));
+      }
+      Ok(None) => thread::sleep(Duration::from_millis(10)),
+      Err(e) => return Err(format!("failed to wait: {e}\n")),
+    }
+  }
+
+  let text = |handle: thread::JoinHandle<_>| {
+    let buf: std::io::Result<Vec<u8>> = handle.join().unwrap();
+    buf
+      .map(|buf| String::from_utf8_lossy(&buf).into_owned())
+      .map_err(|e| format!("failed to read the output: {e}\n"))
+  };
+  Ok(Output {
+    stdout: text(stdout)?,
+    stderr: text(stderr)?,
+  })
+}
+
+fn expectations(script: &Path) -> [(PathBuf, &'static str); 2] {
+  [
+    (script.with_extension("out"), "stdout"),
+    (script.with_extension("err"), "stderr"),
+  ]
+}
+
+fn check_output(
+  script: &Path,
+  output: &Output,
+) -> Result<(), String> {
+  let mut msg = String::new();
+  let actual = [&output.stdout, &output.stderr];
+  for ((path, stream), actual) in
+    expectations(script).into_iter().zip(actual)
+  {
+    let expected =
+      std::fs::read_to_string(&path).unwrap_or_default();
+    if expected != *actual {
+      let name = path.file_name().unwrap().to_string_lossy();
+      msg += &unified_diff(
+        &name,
+        &format!("actual {stream}"),
+        &expected,
+        actual,
+      );
+    }
+  }
+  if msg.is_empty() {
+    Ok(())
+  } else {
+    Err(msg)
+  }
+}
+
+/// Write the output to the expectation files, or remove them if
+/// there is no output.
+fn bless_output(
+  script: &Path,
+  output: &Output,
+) -> Result<(), String> {
+  let actual = [&output.stdout, &output.stderr];
+  for ((path, _), actual) in
+    expectations(script).into_iter().zip(actual)
+  {
+    let result = if actual.is_empty() {
+      match std::fs::remove_file(&path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+          Ok(())
+        }
+        result => result,
+      }
+    } else {
+      std::fs::write(&path, actual)
+    };
+    result.map_err(|e| format!("{}: {e}\n", path.display()))?;
+  }
+  Ok(())
+}
+
+/// The edits turning the old lines into the new ones, as the lines
+/// prefixed by ' ', '-' or '+', from the longest common subsequence.
+fn diff_lines<'a>(
+  old: &[&'a str],
+  new: &[&'a str],
+) -> Vec<(char, &'a str)> {
+  let (n, m) = (old.len(), new.len());
+  let mut lcs = vec![vec![0; m + 1]; n + 1];
+  for i in (0..n).rev() {
+    for j in (0..m).rev() {
+      lcs[i][j] = if old[i] == new[j] {
+        lcs[i + 1][j + 1] + 1
+      } else {
+        lcs[i + 1][j].max(lcs[i][j + 1])
+      };
+    }
+  }
+  let (mut i, mut j) = (0, 0);
+  let mut ops = vec![];
+  while i < n || j < m {
+    if i < n && j < m && old[i] == new[j] {
+      ops.push((' ', old[i]));
+      i += 1;
+      j += 1;
+    } else if i < n
+      && (j == m || lcs[i + 1][j] >= lcs[i][j + 1])
+    {
+      ops.push(('-', old[i]));
+      i += 1;
+    } else {
+      ops.push(('+', new[j]));
+      j += 1;
+    }
+  }
+  ops
+}
+
+fn unified_diff(
+  old_name: &str,
+  new_name: &str,
+  old: &str,
+  new: &str,
+) -> String {
+  let old_lines: Vec<_> = old.lines().collect();
+  let new_lines: Vec<_> = new.lines().collect();
+  let ops = diff_lines(&old_lines, &new_lines);
+  let mut out = format!("--- {old_name}\n+++ {new_name}\n");
+
+  // Line numbers in the old and the new text before each edit
+  let mut lines = vec![];
+  let (mut old_no, mut new_no) = (0, 0);
+  for (op, _) in &ops {
+    lines.push((old_no, new_no));
+    old_no += (*op != '+') as usize;
+    new_no += (*op != '-') as usize;
+  }
+  lines.push((old_no, new_no));
+
+  let changes: Vec<_> =
+    (0..ops.len()).filter(|&i| ops[i].0 != ' ').collect();
+  if changes.is_empty() {
+    out += "(the texts differ only in their line endings)\n";
+    return out;
+  }
+  let mut k = 0;
+  while k < changes.len() {
+    let start = changes[k].saturating_sub(CONTEXT);
+    let mut end = changes[k] + 1;
+    k += 1;
+    while k < changes.len() && changes[k] < end + 2 * CONTEXT {
+      end = changes[k] + 1;
+      k += 1;
+    }
+    let end = (end + CONTEXT).min(ops.len());
+    let (old_start, new_start) = lines[start];
+    let (old_end, new_end) = lines[end];
+    let range = |start: usize, len: usize| {
+      format!(
+        "{},{len}",
+        if len == 0 { start } else { start + 1 }
+      )
+    };
+    out += &format!(
+      "@@ -{} +{} @@\n",
+      range(old_start, old_end - old_start),
+      range(new_start, new_end - new_start)
+    );
+    for (op, line) in &ops[start..end] {
+      out += &format!("{op}{line}\n");
+    }
+  }
+  out
+}
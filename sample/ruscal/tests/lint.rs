@@ -0,0 +1,118 @@
+//! Each lint with code that triggers it and code that does not.
+
+use ruscal::{
+  file_io::parse_program,
+  lint::{lint, LintConfig},
+};
+
+/// Lint the source and return the names of the lints found with
+/// the source text they point at.
+fn lints(src: &str) -> Vec<(&'static str, &str)> {
+  let stmts = parse_program("test.rscl", src).unwrap();
+  lint(&stmts, &LintConfig::new())
+    .into_iter()
+    .map(|w| (w.lint.name(), *w.span.fragment()))
+    .collect()
+}
+
+#[test]
+fn unused_variable() {
+  let src = "fn f(x: i64, y: i64) -> i64 { var z = 1; x }
+f(1, 2);
+for i in 0 to 3 { print(1); }";
+  assert_eq!(
+    lints(src),
+    [
+      ("unused_variable", "y"),
+      ("unused_variable", "z"),
+      ("unused_variable", "i"),
+    ]
+  );
+
+  let src = "fn f(x: i64, _y: i64) -> i64 { x }
+var z = f(1, 2);
+for i in 0 to z { print(i); }
+for k in 0 to z { break; }
+print(k);
+if let v = some(1) { print(v); };";
+  assert_eq!(lints(src), []);
+}
+
+#[test]
+fn unused_function() {
+  let src = "fn f() -> i64 { g() }
+fn g() -> i64 { 1 }
+fn h() -> i64 { 2 }
+h();";
+  assert_eq!(
+    lints(src),
+    [("unused_function", "f"), ("unused_function", "g")]
+  );
+
+  let src = "fn f() -> i64 { g() }
+fn g() -> i64 { 1 }
+fn _h() -> i64 { 2 }
+test fn t() { assert_eq(f(), 1); }";
+  assert_eq!(lints(src), []);
+}
+
+#[test]
+fn shadowing() {
+  let src = "var x = 1;
+print(x);
+var x = 2;
+print(x);
+for x in 0 to 3 { print(x); }";
+  assert_eq!(
+    lints(src),
+    [("shadowing", "x"), ("shadowing", "x")]
+  );
+
+  let src = "for i in 0 to 3 { print(i); }
+for i in 0 to 3 { print(i); }
+var o = some(1);
+if let o = o { print(o); };
+try { throw \"a\"; } catch e { print(e); };
+try { throw \"b\"; } catch e { print(e); };
+var i = 1;
+print(i);";
+  assert_eq!(lints(src), []);
+}
+
+#[test]
+fn unused_assignment() {
+  let src = "var x = 1;
+print(x);
+x = 2;
+x = 3;
+print(x);
+x = 4;";
+  assert_eq!(
+    lints(src),
+    [
+      ("unused_assignment", "x = 2;"),
+      ("unused_assignment", "x = 4;"),
+    ]
+  );
+
+  let src = "var x = 1;
+for i in 0 to 3 { print(x); x = i; }
+x = 2;
+print(x);";
+  assert_eq!(lints(src), []);
+}
+
+#[test]
+fn unreachable_code() {
+  let src = "fn f() -> i64 { return 1; print(2); 3 }
+f();";
+  assert_eq!(lints(src), [("unreachable_code", "print(2)")]);
+
+  let src = "fn f(x: i64) -> i64 {
+  if x < 0 { return 0; };
+  fn g() -> i64 { 1 }
+  x + g()
+}
+f(1);";
+  assert_eq!(lints(src), []);
+}
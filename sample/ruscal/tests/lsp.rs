@@ -0,0 +1,369 @@
+//! Drive the language server binary with a scripted client.
+
+use std::{
+  io::{BufReader, Write},
+  process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use ruscal::lsp::{read_message, write_message};
+use serde_json::{json, Value};
+
+const URI: &str = "file:///test.rscl";
+
+struct Client {
+  child: Child,
+  stdin: ChildStdin,
+  stdout: BufReader<ChildStdout>,
+  next_id: u64,
+  /// Notifications received while waiting for a response
+  notifications: Vec<Value>,
+}
+
+impl Client {
+  fn start() -> Self {
+    let mut child =
+      Command::new(env!("CARGO_BIN_EXE_ruscal-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start the server");
+    let stdin = child.stdin.take().unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut client = Self {
+      child,
+      stdin,
+      stdout,
+      next_id: 0,
+      notifications: vec![],
+    };
+    let res = client.request("initialize", json!({}));
+    assert_eq!(res["capabilities"]["hoverProvider"], true);
+    client.notify("initialized", json!({}));
+    client
+  }
+
+  fn request(&mut self, method: &str, params: Value) -> Value {
+    self.next_id += 1;
+    let id = self.next_id;
+    write_message(
+      &mut self.stdin,
+      &json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "params": params,
+      }),
+    )
+    .unwrap();
+    loop {
+      let msg = self.read();
+      if msg["id"] == id {
+        assert!(msg.get("error").is_none(), "{msg}");
+        return msg["result"].clone();
+      }
+      self.notifications.push(msg);
+    }
+  }
+
+  fn notify(&mut self, method: &str, params: Value) {
+    write_message(
+      &mut self.stdin,
+      &json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+      }),
+    )
+    .unwrap();
+  }
+
+  fn read(&mut self) -> Value {
+    read_message(&mut self.stdout)
+      .unwrap()
+      .expect("the server closed the stream")
+  }
+
+  /// Open the document and return the published diagnostics.
+  fn open(&mut self, text: &str) -> Vec<Value> {
+    self.notify(
+      "textDocument/didOpen",
+      json!({
+        "textDocument": {
+          "uri": URI,
+          "languageId": "ruscal",
+          "version": 1,
+          "text": text,
+        }
+      }),
+    );
+    self.diagnostics()
+  }
+
+  fn change(&mut self, text: &str) -> Vec<Value> {
+    self.notify(
+      "textDocument/didChange",
+      json!({
+        "textDocument": { "uri": URI, "version": 2 },
+        "contentChanges": [{ "text": text }],
+      }),
+    );
+    self.diagnostics()
+  }
+
+  fn diagnostics(&mut self) -> Vec<Value> {
+    let msg = if self.notifications.is_empty() {
+      self.read()
+    } else {
+      self.notifications.remove(0)
+    };
+    assert_eq!(
+      msg["method"],
+      "textDocument/publishDiagnostics"
+    );
+    assert_eq!(msg["params"]["uri"], URI);
+    msg["params"]["diagnostics"].as_array().unwrap().clone()
+  }
+
+  fn at(
+    &mut self,
+    method: &str,
+    line: u32,
+    character: u32,
+  ) -> Value {
+    self.request(
+      method,
+      json!({
+        "textDocument": { "uri": URI },
+        "position": { "line": line, "character": character },
+      }),
+    )
+  }
+
+  fn shutdown(mut self) {
+    assert_eq!(
+      self.request("shutdown", Value::Null),
+      Value::Null
+    );
+    self.notify("exit", Value::Null);
+    assert!(self.child.wait().unwrap().success());
+  }
+}
+
+fn range(line: u32, start: u32, end: u32) -> Value {
+  json!({
+    "start": { "line": line, "character": start },
+    "end": { "line": line, "character": end },
+  })
+}
+
+const PROGRAM: &str = r#"fn add(a: i64, b: i64) -> i64 {
+  a + b
+}
+var x = add(i64(1), i64(2));
+print(x);
+"#;
+
+#[test]
+fn publishes_diagnostics() {
+  let mut client = Client::start();
+  assert_eq!(client.open(PROGRAM), Vec::<Value>::new());
+
+  let diags = client.change("var x = 1 + \"a\";\nprint(x);\n");
+  assert_eq!(diags.len(), 1);
+  assert_eq!(diags[0]["severity"], 1);
+  assert_eq!(diags[0]["range"], range(0, 8, 15));
+  assert!(diags[0]["message"]
+    .as_str()
+    .unwrap()
+    .contains("incompatible type"));
+
+  let diags = client.change("var x = ;\nvar y = 1 +;\n");
+  assert_eq!(diags.len(), 2);
+  assert_eq!(diags[1]["range"]["start"]["line"], 1);
+
+  let diags = client.change("var unused = 1;\n");
+  assert_eq!(diags.len(), 1);
+  assert_eq!(diags[0]["severity"], 2);
+  assert_eq!(diags[0]["code"], "unused_variable");
+
+  client.shutdown();
+}
+
+#[test]
+fn hover_shows_types() {
+  let mut client = Client::start();
+  client.open(PROGRAM);
+
+  let hover = client.at("textDocument/hover", 4, 6);
+  assert_eq!(
+    hover["contents"]["value"],
+    "```ruscal\nx: I64\n```"
+  );
+  assert_eq!(hover["range"], range(4, 6, 7));
+
+  let hover = client.at("textDocument/hover", 3, 9);
+  assert_eq!(
+    hover["contents"]["value"],
+    "```ruscal\nfn add(a: I64, b: I64) -> I64\n```"
+  );
+
+  let hover = client.at("textDocument/hover", 3, 13);
+  assert!(hover["contents"]["value"]
+    .as_str()
+    .unwrap()
+    .contains("fn i64(arg: Any) -> I64"));
+
+  assert_eq!(
+    client.at("textDocument/hover", 2, 0),
+    Value::Null
+  );
+  client.shutdown();
+}
+
+#[test]
+fn goes_to_definition() {
+  let mut client = Client::start();
+  client.open(PROGRAM);
+
+  let def = client.at("textDocument/definition", 3, 8);
+  assert_eq!(
+    def,
+    json!({ "uri": URI, "range": range(0, 3, 6) })
+  );
+
+  let def = client.at("textDocument/definition", 1, 6);
+  assert_eq!(def["range"], range(0, 15, 16));
+
+  let def = client.at("textDocument/definition", 4, 6);
+  assert_eq!(def["range"], range(3, 4, 5));
+
+  // Native functions have no definition in the source
+  let def = client.at("textDocument/definition", 4, 0);
+  assert_eq!(def, Value::Null);
+  client.shutdown();
+}
+
+#[test]
+fn completes_names_in_scope() {
+  let mut client = Client::start();
+  client.open(
+    "var top = 1;\nfn f(arg: i64) -> i64 {\n  var local = arg;\n  \
+    local\n}\nvar after = 2;\n",
+  );
+  let labels = |items: &Value| -> Vec<String> {
+    items
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|item| item["label"].as_str().unwrap().to_string())
+      .collect()
+  };
+
+  // Inside the function, before `local` is used
+  let items = client.at("textDocument/completion", 3, 2);
+  let names = labels(&items);
+  assert!(names.contains(&"arg".to_string()));
+  assert!(names.contains(&"local".to_string()));
+  assert!(names.contains(&"f".to_string()));
+  assert!(names.contains(&"sqrt".to_string()));
+  assert!(!names.contains(&"top".to_string()));
+  assert!(!names.contains(&"after".to_string()));
+
+  // At the end of the top level
+  let items = client.at("textDocument/completion", 6, 0);
+  let names = labels(&items);
+  assert!(names.contains(&"top".to_string()));
+  assert!(names.contains(&"after".to_string()));
+  assert!(!names.contains(&"arg".to_string()));
+  let top = items
+    .as_array()
+    .unwrap()
+    .iter()
+    .find(|item| item["label"] == "top")
+    .unwrap();
+  assert_eq!(top["detail"], "F64");
+  client.shutdown();
+}
+
+#[test]
+fn rejects_unknown_requests() {
+  let mut client = Client::start();
+  client.next_id += 1;
+  let id = client.next_id;
+  write_message(
+    &mut client.stdin,
+    &json!({
+      "jsonrpc": "2.0",
+      "id": id,
+      "method": "textDocument/rename",
+      "params": {},
+    }),
+  )
+  .unwrap();
+  let msg = client.read();
+  assert_eq!(msg["id"], id);
+  assert_eq!(msg["error"]["code"], -32601);
+  client.shutdown();
+}
+
+#[test]
+fn keeps_serving_after_malformed_messages() {
+  let mut client = Client::start();
+  for frame in [
+    "Content-Length: 6\r\n\r\n{bad}\n",
+    "Content-Type: application/json\r\n\r\n",
+  ] {
+    client.stdin.write_all(frame.as_bytes()).unwrap();
+    let msg = client.read();
+    assert_eq!(msg["id"], Value::Null);
+    assert_eq!(msg["error"]["code"], -32700);
+  }
+  assert_eq!(client.open(PROGRAM), Vec::<Value>::new());
+  let hover = client.at("textDocument/hover", 4, 6);
+  assert_eq!(
+    hover["contents"]["value"],
+    "```ruscal\nx: I64\n```"
+  );
+  client.shutdown();
+}
+
+#[test]
+fn skips_bodies_without_a_length_or_too_large() {
+  let mut client = Client::start();
+  // A body without the header, followed right away by a request.
+  let body =
+    json!({ "jsonrpc": "2.0", "id": 100, "method": "x" });
+  let request = json!({
+    "jsonrpc": "2.0",
+    "id": 101,
+    "method": "unknown/method",
+  })
+  .to_string();
+  let frame = format!(
+    "Content-Type: application/json\r\n\r\n{body}\
+    Content-Length: {}\r\n\r\n{request}",
+    request.len()
+  );
+  client.stdin.write_all(frame.as_bytes()).unwrap();
+  let msg = client.read();
+  assert_eq!(msg["id"], Value::Null);
+  assert_eq!(msg["error"]["code"], -32700);
+  let msg = client.read();
+  assert_eq!(msg["id"], 101);
+  assert_eq!(msg["error"]["code"], -32601);
+
+  let len = (16 << 20) + 1;
+  let frame = format!("Content-Length: {len}\r\n\r\n");
+  client.stdin.write_all(frame.as_bytes()).unwrap();
+  client.stdin.write_all(&vec![b' '; len]).unwrap();
+  let msg = client.read();
+  assert_eq!(msg["id"], Value::Null);
+  assert_eq!(msg["error"]["code"], -32700);
+  assert!(msg["error"]["message"]
+    .as_str()
+    .unwrap()
+    .contains("is larger than the limit"));
+
+  assert_eq!(client.open(PROGRAM), Vec::<Value>::new());
+  client.shutdown();
+}
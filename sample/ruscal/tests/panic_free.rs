@@ -0,0 +1,150 @@
+//! Inputs that used to panic or overflow the stack, which have to be
+//! rejected with errors instead. The fuzz targets in `fuzz/` search
+//! for more of them.
+
+use std::{cell::RefCell, error::Error, rc::Rc};
+
+use ruscal::{
+  ast::Span,
+  file_io::{parse_program, read_program, write_program},
+  parser::statements_finish,
+  tracer::Tracer,
+  type_checker::{type_check, TypeCheckContext},
+  vm::{Vm, YieldResult},
+  Args,
+};
+
+/// Stops the program after a number of instructions.
+struct Fuel(usize);
+
+impl Tracer for Fuel {
+  fn on_instruction(
+    &mut self,
+    _vm: &Vm,
+  ) -> Result<(), Box<dyn Error>> {
+    self.0 = self.0.checked_sub(1).ok_or("Out of fuel")?;
+    Ok(())
+  }
+}
+
+/// Load and run the bytecode until it finishes, fails or runs out of
+/// fuel, and return the error, if any.
+fn run(data: &[u8]) -> Result<(), Box<dyn Error>> {
+  let bytecode = read_program(&mut &data[..])?;
+  let mut vm = Vm::new(Rc::new(bytecode), Box::new(()));
+  vm.add_tracer(Rc::new(RefCell::new(Fuel(1_000))));
+  vm.init_fn("main", &[])?;
+  while let YieldResult::Suspend(_) = vm.interpret()? {}
+  Ok(())
+}
+
+fn compile(src: &str) -> Vec<u8> {
+  let mut buf = vec![];
+  write_program(
+    "test.rscl",
+    src,
+    &mut buf,
+    "",
+    &Args::default(),
+  )
+  .expect("the example should compile");
+  buf
+}
+
+#[test]
+fn mismatched_if_branches_are_type_errors() {
+  for src in [
+    "var x = if 1 { 1 } else { \"a\" };",
+    "var x = if 1 { } else { \"a\" };",
+    "fn f() -> i64 { if 1 { 1; 2 } else { } }",
+  ] {
+    let stmts = parse_program("test.rscl", src).unwrap();
+    let _ = type_check(&stmts, &mut TypeCheckContext::new());
+  }
+  let src = "var x = if 1 { 1 } else { \"a\" };";
+  let stmts = parse_program("test.rscl", src).unwrap();
+  let errors = type_check(&stmts, &mut TypeCheckContext::new())
+    .unwrap_err();
+  assert_eq!(*errors[0].span.fragment(), "1 } else {");
+}
+
+#[test]
+fn deep_nesting_is_a_syntax_error() {
+  let examples = [
+    format!("{}1{};", "(".repeat(10_000), ")".repeat(10_000)),
+    format!("{}1;", "1 + ".repeat(10_000)),
+    format!("{}1;", "none ?? ".repeat(10_000)),
+    format!(
+      "{}{}",
+      "if 1 { ".repeat(10_000),
+      "}".repeat(10_000)
+    ),
+    format!(
+      "fn f(x: {}i64{}) {{}}",
+      "cofn<".repeat(10_000),
+      ">".repeat(10_000)
+    ),
+  ];
+  for src in &examples {
+    let errors = statements_finish(Span::new(src)).unwrap_err();
+    assert!(
+      errors.iter().any(|e| e.msg.contains("nested deeper")),
+      "{:?}",
+      errors.iter().map(|e| &e.msg).collect::<Vec<_>>()
+    );
+  }
+}
+
+/// These used to be parsed again at every level of nesting, taking
+/// exponential time.
+#[test]
+fn nesting_is_parsed_in_linear_time() {
+  for src in [
+    format!("print({};", "f(".repeat(40)),
+    format!("print({};", "some(".repeat(40)),
+    format!("print({};", "(1 < ".repeat(40)),
+    format!("{}1{}", "if 1 { ".repeat(25), "}".repeat(25)),
+  ] {
+    let start = std::time::Instant::now();
+    let _ = statements_finish(Span::new(&src));
+    assert!(start.elapsed().as_secs() < 5, "{src}");
+  }
+}
+
+#[test]
+fn nesting_within_the_limit_compiles() {
+  let src = format!("{}1{};", "(".repeat(60), ")".repeat(60));
+  run(&compile(&src)).unwrap();
+}
+
+#[test]
+fn corrupt_bytecode_is_rejected() {
+  let src = std::fs::read_to_string(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/scripts/33-coroutine.rscl"
+  ))
+  .unwrap();
+  let data = compile(&src);
+  run(&data).unwrap();
+  for len in 0..data.len() {
+    assert!(run(&data[..len]).is_err(), "truncated to {len}");
+  }
+  for pos in 0..data.len() {
+    for flip in [0x01, 0x80, 0xff] {
+      let mut data = data.clone();
+      data[pos] ^= flip;
+      let _ = run(&data);
+    }
+  }
+}
+
+#[test]
+fn integer_division_by_zero_is_thrown() {
+  let src = "try { i64(1) / i64(0); } catch e { print(e); }";
+  run(&compile(src)).unwrap();
+  let err = run(&compile("i64(1) / i64(0);")).unwrap_err();
+  assert!(
+    err.to_string().contains("division by zero"),
+    "{err}"
+  );
+}
@@ -0,0 +1,68 @@
+//! Counts of the calls between functions in the profile.
+
+use std::{cell::RefCell, rc::Rc};
+
+use ruscal::{
+  compiler::Compiler,
+  file_io::parse_program,
+  profiler::Profiler,
+  vm::{Vm, YieldResult},
+};
+
+/// Run the source with a profiler and return the lines of the
+/// caller -> callee section of the report.
+fn call_pairs(src: &str) -> Vec<String> {
+  let stmts = parse_program("test.rscl", src).unwrap();
+  let mut compiler = Compiler::new();
+  compiler.compile(&stmts).unwrap();
+  let mut vm =
+    Vm::new(Rc::new(compiler.into_bytecode()), Box::new(()));
+  let profiler = Rc::new(RefCell::new(Profiler::new()));
+  vm.add_tracer(profiler.clone());
+  vm.init_fn("main", &[]).unwrap();
+  assert!(matches!(
+    vm.interpret().unwrap(),
+    YieldResult::Finished(_)
+  ));
+
+  let mut report = vec![];
+  profiler.borrow_mut().report(&mut report).unwrap();
+  let report = String::from_utf8(report).unwrap();
+  report
+    .lines()
+    .skip_while(|line| !line.ends_with("caller -> callee"))
+    .skip(1)
+    .map(|line| {
+      line.split_whitespace().collect::<Vec<_>>().join(" ")
+    })
+    .collect()
+}
+
+#[test]
+fn counts_calls_by_caller_and_callee() {
+  let src = "fn g(x: f64) -> f64 { x * 2 }
+fn f(x: f64) -> f64 { g(x) + g(x + 1) }
+var sum = 0;
+for i in 0 to 3 {
+  sum = sum + f(i);
+}
+print(sum);";
+  assert_eq!(
+    call_pairs(src),
+    ["6 f -> g", "3 main -> f", "1 main -> print"]
+  );
+}
+
+#[test]
+fn counts_recursive_calls() {
+  let src = "fn fact(n: f64) -> f64 {
+  if n < 2 { 1 } else { n * fact(n - 1) }
+}
+// 4 recursive calls, then 2
+fact(5);
+fact(3);";
+  assert_eq!(
+    call_pairs(src),
+    ["6 fact -> fact", "2 main -> fact"]
+  );
+}
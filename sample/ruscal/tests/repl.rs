@@ -0,0 +1,68 @@
+//! Sessions of the REPL over many inputs.
+
+use ruscal::{ast::TypeDecl, repl::Repl, value::Value, Args};
+
+fn eval(repl: &mut Repl, src: &str) -> Option<Value> {
+  repl
+    .eval(src)
+    .unwrap_or_else(|e| panic!("{src}: {e}"))
+    .map(|(value, _)| value)
+}
+
+#[test]
+fn variables_survive_many_inputs() {
+  let args = Args::new();
+  let mut repl = Repl::new(&args);
+  eval(&mut repl, "var x = 1;");
+  for i in 0..300 {
+    let src = format!("x + {i};");
+    assert_eq!(
+      eval(&mut repl, &src),
+      Some(Value::F64(1. + i as f64))
+    );
+    eval(
+      &mut repl,
+      "if x < 2 { var t = 3; t * 2 } else { 0 };",
+    );
+  }
+  eval(&mut repl, "var y = x * 10;");
+  for i in 0..300 {
+    eval(&mut repl, &format!("x = x + {i} - {i};"));
+  }
+  assert_eq!(eval(&mut repl, "x;"), Some(Value::F64(1.)));
+  assert_eq!(eval(&mut repl, "y;"), Some(Value::F64(10.)));
+}
+
+#[test]
+fn too_many_variables_are_an_error() {
+  let args = Args::new();
+  let mut repl = Repl::new(&args);
+  for i in 0..300 {
+    eval(&mut repl, &format!("var v{i} = {i};"));
+  }
+  assert_eq!(eval(&mut repl, "v299;"), Some(Value::F64(299.)));
+  // The error is printed with the input, like a type error.
+  let err = repl.eval("v0;").unwrap_err().to_string();
+  assert_eq!(err, "aborting due to 1 previous error(s)");
+  // The session is still usable.
+  assert_eq!(eval(&mut repl, "v298;"), Some(Value::F64(298.)));
+}
+
+#[test]
+fn rejected_inputs_define_nothing() {
+  let args = Args::new();
+  let mut repl = Repl::new(&args);
+  eval(&mut repl, "var x = 1;");
+  // A type error, a compile error and a parse error.
+  assert!(repl.eval("var y = 2; y + \"a\";").is_err());
+  assert!(repl.eval("var z = 3; break;").is_err());
+  assert!(repl.eval("var w = 4; fn f( {}").is_err());
+  for name in ["y", "z", "w"] {
+    assert!(repl.eval(&format!("{name};")).is_err(), "{name}");
+  }
+  assert_eq!(
+    eval(&mut repl, "var y = x + 1; y;"),
+    Some(Value::F64(2.))
+  );
+  assert_eq!(repl.type_of("y;").unwrap(), TypeDecl::F64);
+}
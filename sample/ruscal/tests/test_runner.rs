@@ -0,0 +1,61 @@
+//! Exit codes of `ruscal -T`.
+
+use std::{
+  path::{Path, PathBuf},
+  process::{Command, Output},
+};
+
+fn run_tests(path: &Path) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_ruscal"))
+    .arg("-T")
+    .arg(path)
+    .output()
+    .expect("failed to run ruscal")
+}
+
+/// Write the source to a file of its own in the temporary directory.
+fn script(name: &str, src: &str) -> PathBuf {
+  let path = std::env::temp_dir()
+    .join(format!("ruscal-{}-{name}.rscl", std::process::id()));
+  std::fs::write(&path, src).unwrap();
+  path
+}
+
+#[test]
+fn passing_tests_exit_with_success() {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("scripts/unit_tests.rscl");
+  let output = run_tests(&path);
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  assert!(output.status.success(), "{stdout}");
+  assert!(
+    stdout.contains("test result: ok. 2 passed"),
+    "{stdout}"
+  );
+}
+
+#[test]
+fn failing_tests_exit_with_failure() {
+  let path = script(
+    "failing",
+    "test fn passes() { assert_eq(i64(1), 1); }
+test fn fails() { assert_eq(i64(1), 2); }",
+  );
+  let output = run_tests(&path);
+  std::fs::remove_file(&path).unwrap();
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  assert_eq!(output.status.code(), Some(1), "{stdout}");
+  assert!(stdout.contains("test fails ... FAILED"), "{stdout}");
+  assert!(stdout.contains("test passes ... ok"), "{stdout}");
+}
+
+#[test]
+fn compile_errors_exit_with_failure() {
+  let path = script(
+    "compile_error",
+    "test fn t() { assert_eq(1, 1); }\nbreak;",
+  );
+  let output = run_tests(&path);
+  std::fs::remove_file(&path).unwrap();
+  assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,64 @@
+//! The JSON-lines trace of a small script, compared line by line.
+
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use ruscal::{
+  compiler::Compiler, file_io::parse_program,
+  tracer::JsonTracer, vm::Vm,
+};
+
+/// A writer whose output can be read after the tracer took it.
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.0.borrow_mut().write(buf)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+fn trace(src: &str) -> String {
+  let stmts = parse_program("test.rscl", src).unwrap();
+  let mut compiler = Compiler::new();
+  compiler.compile(&stmts).unwrap();
+  let mut vm =
+    Vm::new(Rc::new(compiler.into_bytecode()), Box::new(()));
+  let buf = SharedBuf::default();
+  vm.add_tracer(Rc::new(RefCell::new(JsonTracer::new(
+    buf.clone(),
+  ))));
+  vm.init_fn("main", &[]).unwrap();
+  vm.interpret().unwrap();
+  let out = buf.0.borrow();
+  String::from_utf8(out.clone()).unwrap()
+}
+
+#[test]
+fn traces_calls_and_returns() {
+  let expected = r#"{"arg0":1,"depth":1,"event":"instruction","fn":"main","ip":0,"op":"LoadLiteral","stack":0}
+{"arg0":0,"depth":1,"event":"instruction","fn":"main","ip":1,"op":"LoadLiteral","stack":1}
+{"arg0":1,"depth":1,"event":"instruction","fn":"main","ip":2,"op":"Copy","stack":2}
+{"arg0":1,"depth":1,"event":"instruction","fn":"main","ip":3,"op":"Call","stack":3}
+{"args":["1"],"callee":"inc","depth":1,"event":"call","fn":"main","ip":3,"stack":3}
+{"arg0":0,"depth":2,"event":"instruction","fn":"inc","ip":0,"op":"LoadLiteral","stack":1}
+{"arg0":1,"depth":2,"event":"instruction","fn":"inc","ip":1,"op":"Copy","stack":2}
+{"arg0":1,"depth":2,"event":"instruction","fn":"inc","ip":2,"op":"Copy","stack":3}
+{"arg0":0,"depth":2,"event":"instruction","fn":"inc","ip":3,"op":"Add","stack":4}
+{"depth":2,"event":"return","fn":"inc","ip":4,"stack":3,"value":"2"}
+{"arg0":1,"depth":1,"event":"instruction","fn":"main","ip":4,"op":"Store","stack":2}
+{"arg0":0,"depth":1,"event":"instruction","fn":"main","ip":5,"op":"Pop","stack":1}
+{"depth":1,"event":"return","fn":"main","ip":6,"stack":1,"value":"2"}
+"#;
+  let actual =
+    trace("fn inc(x: f64) -> f64 { x + 1 }\ninc(1);");
+  for (i, (actual, expected)) in
+    actual.lines().zip(expected.lines()).enumerate()
+  {
+    assert_eq!(actual, expected, "line {}", i + 1);
+  }
+  assert_eq!(actual.lines().count(), expected.lines().count());
+}
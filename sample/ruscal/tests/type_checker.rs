@@ -0,0 +1,269 @@
+//! Programs that the type checker has to accept or reject.
+
+use ruscal::{
+  file_io::parse_program,
+  type_checker::{
+    type_check, TypeCheckContext, TypeCheckError,
+  },
+};
+
+/// Type check the source and return the errors, if any.
+fn check(src: &str) -> Vec<TypeCheckError<'_>> {
+  check_with(src, false)
+}
+
+/// Type check the source in strict mode.
+fn check_strict(src: &str) -> Vec<TypeCheckError<'_>> {
+  check_with(src, true)
+}
+
+fn check_with(
+  src: &str,
+  strict: bool,
+) -> Vec<TypeCheckError<'_>> {
+  let stmts = parse_program("test.rscl", src).unwrap();
+  let mut ctx = TypeCheckContext::new();
+  ctx.set_strict(strict);
+  type_check(&stmts, &mut ctx).err().unwrap_or_default()
+}
+
+#[test]
+fn bad_assignment_points_at_the_definition() {
+  let src = "var s = \"a\";\ns = 1;";
+  let errors = check(src);
+  assert_eq!(errors.len(), 1);
+  let [(note, span)] = &errors[0].notes[..] else {
+    panic!("expected a note: {:?}", errors[0].notes);
+  };
+  assert_eq!(
+    note,
+    "Variable \"s\" is defined here with type Str"
+  );
+  assert_eq!(*span.fragment(), "s");
+  assert_eq!(span.location_line(), 1);
+}
+
+#[test]
+fn catch_binding_is_scoped_to_the_handler() {
+  let src = "try { 1 } catch e { 0 };\nprint(e);";
+  let errors = check(src);
+  assert_eq!(errors.len(), 1);
+  assert_eq!(
+    errors[0].msg,
+    "Variable \"e\" not found in scope"
+  );
+
+  let src = "var e = 1;\ntry { 1 } catch e { 0 };\ne = 2;";
+  assert!(check(src).is_empty());
+}
+
+#[test]
+fn if_let_binding_is_scoped_to_the_branch() {
+  let src = "var o: i64? = some(1);
+var s = \"a\";
+if let s = o { s + 1; };
+s = \"b\";";
+  let errors = check(src);
+  assert!(errors.is_empty(), "{:?}", errors[0].msg);
+
+  let src = "var o: i64? = some(1);
+if let v = o { v; };
+v;";
+  let errors = check(src);
+  assert_eq!(errors.len(), 1);
+  assert_eq!(
+    errors[0].msg,
+    "Variable \"v\" not found in scope"
+  );
+}
+
+/// The lines of the errors in the order they are reported.
+fn error_lines(errors: &[TypeCheckError]) -> Vec<u32> {
+  errors.iter().map(|e| e.span.location_line()).collect()
+}
+
+#[test]
+fn errors_are_capped_by_max_errors() {
+  let src: String = (0..5)
+    .map(|i| format!("var x{i}: i64 = \"a\";\n"))
+    .collect();
+  assert_eq!(error_lines(&check(&src)), [1, 2, 3, 4, 5]);
+
+  let stmts = parse_program("test.rscl", &src).unwrap();
+  let mut ctx = TypeCheckContext::new();
+  ctx.set_max_errors(2);
+  let errors = type_check(&stmts, &mut ctx).unwrap_err();
+  assert_eq!(error_lines(&errors), [1, 2]);
+}
+
+#[test]
+fn errors_are_sorted_by_position() {
+  let src = "fn f(x: f64) -> str { \"a\" }
+var s: f64 = f(\"b\");
+var t: str = 1;";
+  let errors = check(src);
+  let offsets: Vec<_> =
+    errors.iter().map(|e| e.span.location_offset()).collect();
+  let mut sorted = offsets.clone();
+  sorted.sort();
+  assert_eq!(offsets, sorted);
+  assert_eq!(error_lines(&errors), [2, 2, 3]);
+}
+
+#[test]
+fn errors_in_function_bodies_after_an_earlier_error() {
+  let src = "var a: i64 = \"a\";
+fn f(x: f64) -> f64 {
+  var s: str = x;
+  x
+}
+fn g() -> f64 {
+  var t: str = 1;
+  2
+}
+var b: str = f(1);";
+  assert_eq!(error_lines(&check(src)), [1, 3, 7, 10]);
+}
+
+#[test]
+fn return_of_the_wrong_type() {
+  let src = "fn f(x: f64) -> str {
+  if x < 0 { return x; };
+  \"a\"
+}";
+  let errors = check(src);
+  assert_eq!(error_lines(&errors), [2]);
+  assert_eq!(
+    errors[0].msg,
+    "Returned value of type F64 doesn't match the declared \
+    return type Str"
+  );
+  assert_eq!(*errors[0].span.fragment(), "x");
+
+  let src = "fn f(x: f64) -> f64 {\n  return x;\n}";
+  assert!(check(src).is_empty());
+}
+
+#[test]
+fn yield_of_the_wrong_type() {
+  let src = "cofn f() -> f64 {
+  yield 1;
+  yield \"a\";
+}";
+  let errors = check(src);
+  assert_eq!(error_lines(&errors), [3]);
+  assert_eq!(
+    errors[0].msg,
+    "Yielded value of type Str doesn't match the declared \
+    yield type F64"
+  );
+}
+
+#[test]
+fn argument_counts_are_checked() {
+  let src = "fn add(a: f64, b: f64) -> f64 { a + b }
+add(1);
+add(1, 2, 3);
+add(1, 2);";
+  let errors = check(src);
+  let msgs: Vec<_> =
+    errors.iter().map(|e| &e.msg[..]).collect();
+  assert_eq!(
+    msgs,
+    [
+      "Function \"add\" expects 2 argument(s), but 1 were given: \
+      fn add(a: F64, b: F64) -> F64",
+      "Function \"add\" expects 2 argument(s), but 3 were given: \
+      fn add(a: F64, b: F64) -> F64",
+    ]
+  );
+  assert_eq!(error_lines(&errors), [2, 3]);
+}
+
+#[test]
+fn variadic_functions_take_extra_arguments() {
+  assert!(check("print();\nprint(1);\nprint(1, \"a\", 2);")
+    .is_empty());
+
+  let errors = check("format(\"{}\", 1, 2);\nformat();");
+  assert_eq!(error_lines(&errors), [2]);
+  assert!(
+    errors[0]
+      .msg
+      .starts_with("Function \"format\" expects at least 1 "),
+    "{}",
+    errors[0].msg
+  );
+}
+
+#[test]
+fn await_gives_the_yield_type() {
+  let src = "cofn f() -> i64 { yield i64(1); }
+var co: cofn<i64> = f();
+var n: i64 = await co;
+var s: str = await co;";
+  let errors = check(src);
+  assert_eq!(error_lines(&errors), [4]);
+
+  let src = "cofn f() -> i64 { yield i64(1); }
+var co: cofn<str> = f();";
+  assert_eq!(error_lines(&check(src)), [2]);
+}
+
+#[test]
+fn coroutine_yield_of_the_wrong_type() {
+  let src = "cofn f() -> i64 {
+  yield i64(1);
+  yield \"a\";
+}";
+  let errors = check(src);
+  assert_eq!(error_lines(&errors), [3]);
+  assert_eq!(
+    errors[0].msg,
+    "Yielded value of type Str doesn't match the declared \
+    yield type I64"
+  );
+}
+
+#[test]
+fn bare_cofn_yields_any() {
+  let src = "cofn f() -> i64 { yield i64(1); }
+fn first(co: cofn) -> i64 { await co }
+fn name(co: cofn) -> str { await co }
+var co: cofn = f();
+first(co);
+name(f());";
+  let errors = check(src);
+  assert!(errors.is_empty(), "{:?}", errors[0].msg);
+}
+
+#[test]
+fn strict_mode_accepts_integer_literals_as_i64() {
+  for src in [
+    include_str!("../scripts/33-generator.rscl"),
+    "var n: i64 = 3;\nfor i in 0 to n { print(i * 2 + 1); }",
+    "var x: i64 = 2 * 3 - 1;\nvar y: f64 = 2;\nvar z = y * 2;",
+    "fn f(n: i64) -> i64 { if n < 1 { return 0; }; n - 1 }
+f(3);",
+    "var o: i64? = some(1);\nvar p: i64? = 2;\no ?? 0;",
+  ] {
+    let errors = check_strict(src);
+    assert!(errors.is_empty(), "{src}: {:?}", errors[0].msg);
+  }
+}
+
+#[test]
+fn strict_mode_rejects_mixed_arithmetic() {
+  for (src, fragment) in [
+    ("var x = i64(1) + 1.5;", "i64(1) + 1.5"),
+    ("var x = 1; var y: i64 = x;", "x"),
+    ("var n = i64(2); var x: f64 = 0.5 * n;", "0.5 * n"),
+    ("fn f(x: f64) -> f64 { x } f(i64(1));", "i64(1)"),
+    ("for i in 0 to 1.5 { }", "1.5"),
+  ] {
+    let errors = check_strict(src);
+    assert_eq!(errors.len(), 1, "{src}");
+    assert_eq!(*errors[0].span.fragment(), fragment, "{src}");
+    assert!(check(src).is_empty(), "{src}");
+  }
+}
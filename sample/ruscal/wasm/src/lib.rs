@@ -3,6 +3,7 @@ use std::{any::Any, rc::Rc};
 use ruscal::{
   ast::TypeDecl,
   bytecode::NativeFn,
+  diagnostics::Aborted,
   file_io::{parse_program, read_program, write_program},
   type_checker::{self, TypeCheckContext},
   value::Value,
@@ -23,7 +24,10 @@ extern "C" {
   pub(crate) fn wasm_set_fill_style(s: &str);
 }
 
-fn print_fn(_: &dyn Any, args: &[Value]) -> Value {
+fn print_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
   let output = args.iter().map(|v| v.to_string()).fold(
     String::new(),
     |acc, cur| {
@@ -37,58 +41,68 @@ fn print_fn(_: &dyn Any, args: &[Value]) -> Value {
   wasm_print(&output);
 
   wasm_print("\n");
-  Value::I64(0)
+  Ok(Value::I64(0))
 }
 
-fn dbg_fn(_: &dyn Any, values: &[Value]) -> Value {
-  wasm_print(&format!("dbg: {:?}\n", values[0]));
-  Value::I64(0)
+fn dbg_fn(
+  _: &mut dyn Any,
+  values: &[Value],
+) -> Result<Value, String> {
+  wasm_print(&format!(
+    "dbg: {:?}\n",
+    values.first().ok_or("function missing argument")?
+  ));
+  Ok(Value::I64(0))
 }
 
-fn puts_fn(_: &dyn Any, args: &[Value]) -> Value {
+fn puts_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
   for arg in args {
     wasm_print(&format!("{}", arg));
   }
-  Value::F64(0.)
+  Ok(Value::F64(0.))
 }
 
-fn rectangle_fn(_: &dyn Any, args: &[Value]) -> Value {
+fn rectangle_fn(
+  _: &mut dyn Any,
+  args: &[Value],
+) -> Result<Value, String> {
   let mut f64vals = args.iter().take(4).map(|val| {
-    if let Ok(v) = val.coerce_f64() {
-      Ok(v)
-    } else {
-      Err("wrong type!".to_string())
-    }
+    val.coerce_f64().map_err(|_| "wrong type!".to_string())
   });
   let short = "Input needs to be more than 4 values";
-  let x0 = f64vals.next().expect(short).unwrap();
-  let y0 = f64vals.next().expect(short).unwrap();
-  let x1 = f64vals.next().expect(short).unwrap();
-  let y1 = f64vals.next().expect(short).unwrap();
+  let x0 = f64vals.next().ok_or(short)??;
+  let y0 = f64vals.next().ok_or(short)??;
+  let x1 = f64vals.next().ok_or(short)??;
+  let y1 = f64vals.next().ok_or(short)??;
   wasm_rectangle(x0, y0, x1, y1);
-  Value::I64(0)
+  Ok(Value::I64(0))
 }
 
-fn set_fill_style_fn(_: &dyn Any, vals: &[Value]) -> Value {
+fn set_fill_style_fn(
+  _: &mut dyn Any,
+  vals: &[Value],
+) -> Result<Value, String> {
   if let [Value::Str(s), ..] = vals {
     wasm_set_fill_style(s);
   }
-  Value::I64(0)
+  Ok(Value::I64(0))
 }
 
-fn hex_string_fn(_: &dyn Any, vals: &[Value]) -> Value {
+fn hex_string_fn(
+  _: &mut dyn Any,
+  vals: &[Value],
+) -> Result<Value, String> {
   if let [val, ..] = vals {
-    match val.coerce_i64() {
-      Ok(i) => Value::Str(format!("{:02x}", i)),
-      _ => {
-        wasm_print(
-          "hex_string() could not convert argument to i64",
-        );
-        Value::Str("".to_string())
-      }
-    }
+    let i = val.coerce_i64().map_err(|_| {
+      "hex_string() could not convert argument to i64"
+        .to_string()
+    })?;
+    Ok(Value::Str(format!("{:02x}", i)))
   } else {
-    Value::Str("".to_string())
+    Err("function missing argument".to_string())
   }
 }
 
@@ -101,11 +115,8 @@ fn wasm_functions<'src>(
   set_fn(
     "print",
     Box::new(|| {
-      NativeFn::new(
-        vec![("arg", TypeDecl::Any)],
-        TypeDecl::Any,
-        Box::new(print_fn),
-      )
+      NativeFn::new(vec![], TypeDecl::Any, Box::new(print_fn))
+        .variadic()
     }),
   );
   set_fn(
@@ -121,11 +132,8 @@ fn wasm_functions<'src>(
   set_fn(
     "puts",
     Box::new(|| {
-      NativeFn::new(
-        vec![("arg", TypeDecl::Any)],
-        TypeDecl::Any,
-        Box::new(puts_fn),
-      )
+      NativeFn::new(vec![], TypeDecl::Any, Box::new(puts_fn))
+        .variadic()
     }),
   );
   set_fn(
@@ -165,10 +173,27 @@ fn wasm_functions<'src>(
   );
 }
 
+/// Messages of the diagnostics if the error has them, since they
+/// are not printed to the console in the browser.
+fn error_text(e: &(dyn std::error::Error + 'static)) -> String {
+  match e.downcast_ref::<Aborted>() {
+    Some(aborted) => aborted
+      .0
+      .iter()
+      .map(|diag| diag.to_string())
+      .collect::<Vec<_>>()
+      .join("\n"),
+    None => e.to_string(),
+  }
+}
+
 #[wasm_bindgen]
 pub fn type_check(src: &str) -> Result<JsValue, JsValue> {
   let stmts = parse_program("<input>", src).map_err(|e| {
-    JsValue::from_str(&format!("Parse Error: {e}"))
+    JsValue::from_str(&format!(
+      "Parse Error: {}",
+      error_text(&*e)
+    ))
   })?;
 
   let mut tc_ctx = TypeCheckContext::new();
@@ -178,7 +203,14 @@ pub fn type_check(src: &str) -> Result<JsValue, JsValue> {
   });
 
   type_checker::type_check(&stmts, &mut tc_ctx).map_err(
-    |e| JsValue::from_str(&format!("Type Check Error: {e}")),
+    |errors| {
+      let msgs: Vec<_> =
+        errors.iter().map(|e| e.to_string()).collect();
+      JsValue::from_str(&format!(
+        "Type Check Error: {}",
+        msgs.join("\n")
+      ))
+    },
   )?;
 
   Ok(JsValue::from_str("Ok"))
@@ -204,7 +236,10 @@ pub fn compile(src: &str) -> Result<Vec<u8>, JsValue> {
     &args,
   )
   .map_err(|e| {
-    JsValue::from_str(&format!("Compile Error: {e}"))
+    JsValue::from_str(&format!(
+      "Compile Error: {}",
+      error_text(&*e)
+    ))
   })?;
 
   Ok(buf)
@@ -230,7 +265,10 @@ pub fn disasm(src: &str) -> Result<String, JsValue> {
     &args,
   )
   .map_err(|e| {
-    JsValue::from_str(&format!("Compile Error: {e}"))
+    JsValue::from_str(&format!(
+      "Compile Error: {}",
+      error_text(&*e)
+    ))
   })?;
 
   let mut bytecode =
@@ -270,7 +308,10 @@ pub fn compile_and_run(src: &str) -> Result<(), JsValue> {
     &args,
   )
   .map_err(|e| {
-    JsValue::from_str(&format!("Compile Error: {e}"))
+    JsValue::from_str(&format!(
+      "Compile Error: {}",
+      error_text(&*e)
+    ))
   })?;
 
   let mut bytecode =
@@ -281,7 +322,7 @@ pub fn compile_and_run(src: &str) -> Result<(), JsValue> {
     bytecode.add_fn(name.to_string(), f())
   });
 
-  let mut vm = Vm::new(Rc::new(bytecode), Box::new(()), false);
+  let mut vm = Vm::new(Rc::new(bytecode), Box::new(()));
 
   if let Err(e) = vm.init_fn("main", &[]) {
     eprintln!("init_fn error: {e:?}");